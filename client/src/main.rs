@@ -1,14 +1,17 @@
 use reqwest::Client;
 use std::fs;
-use merkle_tree::{calculate_merkle_root, validate_proof, generate_proof, hash, SiblingNode};
+use merkle_tree::{validate_proof_for_leaf, DomainSeparated, MerkleHasher, Sha256Hasher, SiblingNode};
 use std::collections::HashMap;
 use serde::{Deserialize, Serialize};
 use std::env;
 
+type Hasher = DomainSeparated<Sha256Hasher>;
+type HashValue = <Hasher as MerkleHasher>::Digest;
+
 #[derive(Deserialize, Serialize)]
 struct ProofResponse {
-    root: u64,
-    proof: Vec<SiblingNode>,
+    root: HashValue,
+    proof: Vec<SiblingNode<HashValue>>,
 }
 
 #[tokio::main]
@@ -56,9 +59,9 @@ async fn upload_files_and_save_merkle_root(client: &Client, files: &[String], se
     let root_prefix = "Root: ";
     if let Some(pos) = res.find(root_prefix) {
         let root_str = &res[pos + root_prefix.len()..];
-        if let Ok(root_hash) = root_str.parse::<u64>() {
-            println!("Merkle root: {}", root_hash);
-            fs::write("merkle_root.txt", root_hash.to_le_bytes())?;
+        if let Ok(root_hash) = hex::decode(root_str) {
+            println!("Merkle root: {}", root_str);
+            fs::write("merkle_root.txt", root_hash)?;
         } else {
             eprintln!("Failed to parse Merkle root");
         }
@@ -91,14 +94,15 @@ async fn download_and_verify_files(client: &Client, files: &[String], server_url
         let proof_response = get_proof(client, filename, server_url).await?;
 
         let stored_root = fs::read("merkle_root.txt")?;
-        let stored_root = u64::from_le_bytes(stored_root[..8].try_into().unwrap());
+        let stored_root: HashValue = stored_root.as_slice().try_into()?;
+        let file_hash = Hasher::hash_leaf(res.as_bytes());
 
-        println!("Stored root: {}", stored_root);
-        println!("Generated root: {}", proof_response.root);
+        println!("Stored root: {}", hex::encode(stored_root));
+        println!("Generated root: {}", hex::encode(proof_response.root));
         println!("Res: {}", res);
-        println!("Res: {}", &hash(&res).to_string());
+        println!("Res: {}", hex::encode(file_hash));
         println!("Proof: {:?}", proof_response.proof);
-        if validate_proof(&stored_root, &hash(&res).to_string(), proof_response.proof) {
+        if validate_proof_for_leaf::<Hasher>(&stored_root, res.as_bytes(), proof_response.proof) {
             println!("File {} is verified!", filename);
         } else {
             println!("File {} verification failed!", filename);
@@ -150,7 +154,7 @@ async fn test_upload_files() -> Result<(), Box<dyn std::error::Error>> {
         upload_data.insert(file.clone(), data.to_string());
     }
 
-    let res = client.post(&format!("{}/upload", &mockito::server_url()))
+    let res = client.post(format!("{}/upload", &mockito::server_url()))
         .json(&upload_data)
         .send()
         .await?;
@@ -162,10 +166,9 @@ async fn test_upload_files() -> Result<(), Box<dyn std::error::Error>> {
 #[tokio::test]
 async fn test_download_and_verify_files() -> Result<(), Box<dyn std::error::Error>> {
     let file_data = "test data";
-    let file_hash = calculate_merkle_root(file_data);
 
     // Generate a proof for the file_data
-    let (root, proof) = generate_proof(file_data, 0);  // Assuming we want the proof for the first "block"
+    let (root, proof) = merkle_tree::generate_proof::<Hasher>(file_data, 0);  // Assuming we want the proof for the first "block"
 
 
     let proof_response = ProofResponse {
@@ -185,7 +188,7 @@ async fn test_download_and_verify_files() -> Result<(), Box<dyn std::error::Erro
 
     let client = Client::new();
 
-    let res = client.get(&format!("{}/download/file1.txt", &mockito::server_url()))
+    let res = client.get(format!("{}/download/file1.txt", &mockito::server_url()))
         .send()
         .await?
         .text()
@@ -193,13 +196,13 @@ async fn test_download_and_verify_files() -> Result<(), Box<dyn std::error::Erro
 
     assert_eq!(res, file_data);
 
-    let proof_response: ProofResponse = client.get(&format!("{}/proof/file1.txt", &mockito::server_url()))
+    let proof_response: ProofResponse = client.get(format!("{}/proof/file1.txt", &mockito::server_url()))
         .send()
         .await?
         .json()
         .await?;
 
-    let is_valid = validate_proof(&proof_response.root, "test", proof_response.proof);
+    let is_valid = merkle_tree::validate_proof::<Hasher>(&proof_response.root, "test", proof_response.proof);
     assert!(is_valid);
 
     Ok(())
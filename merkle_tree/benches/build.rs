@@ -0,0 +1,28 @@
+//! Time to build the root of a million-leaf tree from its data blocks.
+//!
+//! Run with `cargo bench -p merkle_tree --bench build`, and with `--features parallel` to compare.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use merkle_tree::{calculate_merkle_root_from_leaves, Sha256Hasher};
+
+const LEAVES: usize = 1 << 20;
+
+fn build_root(c: &mut Criterion) {
+    let data: Vec<[u8; 64]> = (0..LEAVES as u32)
+        .map(|i| {
+            let mut block = [0; 64];
+            block[..4].copy_from_slice(&i.to_le_bytes());
+            block
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("calculate_merkle_root_from_leaves");
+    group.sample_size(10);
+    group.bench_function("sha256", |b| {
+        b.iter(|| calculate_merkle_root_from_leaves::<Sha256Hasher, _>(&data))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, build_root);
+criterion_main!(benches);
@@ -0,0 +1,40 @@
+//! Verification time of compact multiproofs over a million-leaf tree.
+//!
+//! Run with `cargo bench -p merkle_tree --bench multiproof`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use merkle_tree::{validate_compact_multiproof_for_digests, MerkleHasher, MerkleTree, Sha256Hasher};
+use rand::{seq::index::sample, SeedableRng};
+
+const LEAVES: usize = 1 << 20;
+
+fn validate_multiproof(c: &mut Criterion) {
+    let leaves: Vec<[u8; 32]> = (0..LEAVES as u32)
+        .map(|i| Sha256Hasher::hash_leaf(&i.to_le_bytes()))
+        .collect();
+    let tree = MerkleTree::<Sha256Hasher>::from_leaf_digests(leaves.clone());
+    let root = tree.root();
+    let mut rng = rand::rngs::SmallRng::seed_from_u64(42);
+
+    let mut group = c.benchmark_group("validate_compact_multiproof");
+    group.sample_size(10);
+    for k in [100, 10_000, 100_000] {
+        let indices = sample(&mut rng, LEAVES, k).into_vec();
+        let proof = tree.multiproof(&indices);
+        let proven: Vec<[u8; 32]> = indices.iter().map(|&i| leaves[i]).collect();
+
+        group.bench_with_input(BenchmarkId::from_parameter(k), &k, |b, _| {
+            b.iter_batched(
+                || (proven.clone(), proof.clone()),
+                |(proven, proof)| {
+                    assert!(validate_compact_multiproof_for_digests::<Sha256Hasher>(&root, proven, proof))
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, validate_multiproof);
+criterion_main!(benches);
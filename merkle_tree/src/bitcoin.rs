@@ -0,0 +1,454 @@
+//! Transaction Merkle roots and partial Merkle trees as Bitcoin computes them.
+//!
+//! Bitcoin hashes with double SHA-256 and has no leaf or node prefixes. A level with an odd number
+//! of nodes duplicates its last node instead of padding the leaves with empty ones like
+//! [`crate::TreeShape::Padded`], so a list of transactions and the same list with its last ones
+//! repeated have the same root (CVE-2012-2459). [`bitcoin_merkle_root`] reports such duplicated
+//! pairs and [`PartialMerkleTree::extract_matches`] rejects them.
+//!
+//! Txids and roots are hashes in the byte order they are hashed in. Block explorers and RPCs show
+//! them reversed, which [`txid_from_hex`] and [`txid_to_hex`] convert from and to.
+
+use crate::{MerkleError, MerkleHasher, Sha256Hasher};
+
+/// SHA-256 applied twice, the hash of Bitcoin txids, block headers and transaction Merkle trees.
+/// A leaf hashed from a serialized transaction is its txid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DoubleSha256Hasher;
+
+impl MerkleHasher for DoubleSha256Hasher {
+    type Digest = [u8; 32];
+    const ALGORITHM_ID: u8 = 7;
+
+    fn hash_parts(parts: &[&[u8]]) -> [u8; 32] {
+        Sha256Hasher::hash_parts(&[&Sha256Hasher::hash_parts(parts)])
+    }
+}
+
+/// The most transactions a block can hold: the block weight limit over the weight of the smallest
+/// transaction.
+const MAX_TRANSACTIONS: u32 = 4_000_000 / 240;
+
+/// Reads a txid or block hash shown in the usual reversed hex.
+pub fn txid_from_hex(hex: &str) -> Result<[u8; 32], MerkleError> {
+    let mut txid: [u8; 32] = hex::decode(hex)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(MerkleError::InvalidEncoding)?;
+    txid.reverse();
+    Ok(txid)
+}
+
+/// Shows a txid or block hash in the usual reversed hex.
+pub fn txid_to_hex(txid: &[u8; 32]) -> String {
+    let mut reversed = *txid;
+    reversed.reverse();
+    hex::encode(reversed)
+}
+
+/// Returns the Merkle root field of a serialized 80-byte block header.
+pub fn header_merkle_root(header: &[u8; 80]) -> [u8; 32] {
+    header[36..68].try_into().unwrap()
+}
+
+/// Computes the transaction Merkle root of a block from its txids, like Bitcoin Core's
+/// `ComputeMerkleRoot`, and whether two equal nodes were paired on the way. A block whose root
+/// only matches its header because of such a pair is mutated and must not be accepted, but it may
+/// not be marked invalid either, since the same root belongs to a valid block. The root of no
+/// transactions is all zeros.
+pub fn bitcoin_merkle_root(txids: &[[u8; 32]]) -> ([u8; 32], bool) {
+    let mut level = txids.to_vec();
+    let mut mutated = false;
+    while level.len() > 1 {
+        mutated |= level.chunks_exact(2).any(|pair| pair[0] == pair[1]);
+        if level.len() % 2 == 1 {
+            level.push(level[level.len() - 1]);
+        }
+        level = level.chunks_exact(2).map(|pair| DoubleSha256Hasher::hash_nodes(&pair[0], &pair[1])).collect();
+    }
+    (level.first().copied().unwrap_or_default(), mutated)
+}
+
+/// The number of nodes at `height` of a tree of `total` transactions.
+fn width(total: u32, height: u32) -> u32 {
+    ((total as u64 + (1 << height) - 1) >> height) as u32
+}
+
+/// The height of the root of a tree of `total` transactions.
+fn root_height(total: u32) -> u32 {
+    let mut height = 0;
+    while width(total, height) > 1 {
+        height += 1;
+    }
+    height
+}
+
+/// The transactions a [`PartialMerkleTree`] proves, as their index in the block and their txid.
+pub type MatchedTransactions = Vec<(usize, [u8; 32])>;
+
+/// A partial Merkle tree, the part of a BIP 37 `merkleblock` message that proves which of a
+/// block's transactions match a filter.
+///
+/// The tree is walked depth-first from the root. For every node visited, `flags` holds whether it
+/// is an ancestor of (or is) a matched transaction; the children of such nodes are visited, and
+/// every other node that is visited, as well as every matched transaction, has its hash in
+/// `hashes`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialMerkleTree {
+    pub total_transactions: u32,
+    pub hashes: Vec<[u8; 32]>,
+    pub flags: Vec<bool>,
+}
+
+impl PartialMerkleTree {
+    /// Builds the partial tree of a block's txids proving the ones whose entry in `matches` is set.
+    pub fn new(txids: &[[u8; 32]], matches: &[bool]) -> Result<Self, MerkleError> {
+        if txids.is_empty() {
+            return Err(MerkleError::EmptyTree);
+        }
+        if matches.len() != txids.len() {
+            return Err(MerkleError::ProofLengthMismatch { expected: txids.len(), actual: matches.len() });
+        }
+        if txids.len() > MAX_TRANSACTIONS as usize {
+            return Err(MerkleError::SizeOutOfRange { size: txids.len(), len: MAX_TRANSACTIONS as usize });
+        }
+        let total = txids.len() as u32;
+        let mut tree = PartialMerkleTree { total_transactions: total, hashes: Vec::new(), flags: Vec::new() };
+        tree.build(root_height(total), 0, txids, matches);
+        Ok(tree)
+    }
+
+    /// The hash of the node at `position` of `height`, duplicating the last node of odd levels.
+    fn node_hash(&self, height: u32, position: u32, txids: &[[u8; 32]]) -> [u8; 32] {
+        if height == 0 {
+            return txids[position as usize];
+        }
+        let left = self.node_hash(height - 1, position * 2, txids);
+        let right = if position * 2 + 1 < width(self.total_transactions, height - 1) {
+            self.node_hash(height - 1, position * 2 + 1, txids)
+        } else {
+            left
+        };
+        DoubleSha256Hasher::hash_nodes(&left, &right)
+    }
+
+    fn build(&mut self, height: u32, position: u32, txids: &[[u8; 32]], matches: &[bool]) {
+        let start = (position as usize) << height;
+        let end = txids.len().min(((position as usize) + 1) << height);
+        let parent_of_match = matches[start..end].iter().any(|&matched| matched);
+        self.flags.push(parent_of_match);
+        if height == 0 || !parent_of_match {
+            let hash = self.node_hash(height, position, txids);
+            self.hashes.push(hash);
+        } else {
+            self.build(height - 1, position * 2, txids, matches);
+            if position * 2 + 1 < width(self.total_transactions, height - 1) {
+                self.build(height - 1, position * 2 + 1, txids, matches);
+            }
+        }
+    }
+
+    /// Recomputes the root and returns it with the indices and txids of the matched transactions,
+    /// like Bitcoin Core's `ExtractMatches`. Compare the root with the block header's.
+    ///
+    /// Trees whose flags or hashes are not all used, up to the padding of the last flag byte, are
+    /// malformed, and trees with two identical sibling subtrees are mutated.
+    pub fn extract_matches(&self) -> Result<([u8; 32], MatchedTransactions), MerkleError> {
+        let total = self.total_transactions;
+        if total == 0 {
+            return Err(MerkleError::EmptyTree);
+        }
+        if total > MAX_TRANSACTIONS {
+            return Err(MerkleError::SizeOutOfRange { size: total as usize, len: MAX_TRANSACTIONS as usize });
+        }
+        if self.hashes.len() > total as usize || self.flags.len() < self.hashes.len() {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let mut walk = Extraction { tree: self, flags_used: 0, hashes_used: 0, matches: Vec::new() };
+        let root = walk.extract(root_height(total), 0)?;
+        if walk.flags_used.div_ceil(8) != self.flags.len().div_ceil(8) || walk.hashes_used != self.hashes.len() {
+            return Err(MerkleError::MalformedProof);
+        }
+        Ok((root, walk.matches))
+    }
+
+    /// Serializes the tree as in a `merkleblock` message: the transaction count as a little-endian
+    /// `u32`, the hashes and the flags packed into bytes from the least significant bit, each list
+    /// preceded by its length as a Bitcoin `CompactSize`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut flag_bytes = vec![0u8; self.flags.len().div_ceil(8)];
+        for (i, &flag) in self.flags.iter().enumerate() {
+            flag_bytes[i / 8] |= (flag as u8) << (i % 8);
+        }
+
+        let mut bytes = self.total_transactions.to_le_bytes().to_vec();
+        write_compact_size(&mut bytes, self.hashes.len() as u64);
+        for hash in &self.hashes {
+            bytes.extend_from_slice(hash);
+        }
+        write_compact_size(&mut bytes, flag_bytes.len() as u64);
+        bytes.extend(flag_bytes);
+        bytes
+    }
+
+    /// Reads a tree written by [`PartialMerkleTree::to_bytes`]. The flags include the padding of
+    /// the last byte, which [`PartialMerkleTree::extract_matches`] allows to be left unused.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MerkleError> {
+        let mut rest = bytes;
+        let total_transactions = u32::from_le_bytes(take(&mut rest, 4)?.try_into().unwrap());
+        let hash_count = read_count(&mut rest, 32)?;
+        let hashes = (0..hash_count).map(|_| Ok(take(&mut rest, 32)?.try_into().unwrap())).collect::<Result<_, _>>()?;
+        let flag_count = read_count(&mut rest, 1)?;
+        let flags = take(&mut rest, flag_count)?.iter().flat_map(|byte| (0..8).map(move |bit| (byte >> bit) & 1 == 1)).collect();
+        if !rest.is_empty() {
+            return Err(MerkleError::TrailingData(rest.len()));
+        }
+        Ok(PartialMerkleTree { total_transactions, hashes, flags })
+    }
+}
+
+/// The state of walking a [`PartialMerkleTree`] back up to its root.
+struct Extraction<'a> {
+    tree: &'a PartialMerkleTree,
+    flags_used: usize,
+    hashes_used: usize,
+    matches: MatchedTransactions,
+}
+
+impl Extraction<'_> {
+    fn extract(&mut self, height: u32, position: u32) -> Result<[u8; 32], MerkleError> {
+        let parent_of_match = *self.tree.flags.get(self.flags_used).ok_or(MerkleError::MalformedProof)?;
+        self.flags_used += 1;
+        if height == 0 || !parent_of_match {
+            let hash = *self.tree.hashes.get(self.hashes_used).ok_or(MerkleError::MalformedProof)?;
+            self.hashes_used += 1;
+            if height == 0 && parent_of_match {
+                self.matches.push((position as usize, hash));
+            }
+            return Ok(hash);
+        }
+
+        let left = self.extract(height - 1, position * 2)?;
+        let right = if position * 2 + 1 < width(self.tree.total_transactions, height - 1) {
+            let right = self.extract(height - 1, position * 2 + 1)?;
+            // Only the last node of an odd level may be paired with itself
+            if right == left {
+                return Err(MerkleError::MutatedTree);
+            }
+            right
+        } else {
+            left
+        };
+        Ok(DoubleSha256Hasher::hash_nodes(&left, &right))
+    }
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8], MerkleError> {
+    if bytes.len() < len {
+        return Err(MerkleError::TruncatedData);
+    }
+    let (field, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(field)
+}
+
+fn write_compact_size(bytes: &mut Vec<u8>, value: u64) {
+    match value {
+        0..=0xfc => bytes.push(value as u8),
+        0xfd..=0xffff => {
+            bytes.push(0xfd);
+            bytes.extend((value as u16).to_le_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            bytes.push(0xfe);
+            bytes.extend((value as u32).to_le_bytes());
+        }
+        _ => {
+            bytes.push(0xff);
+            bytes.extend(value.to_le_bytes());
+        }
+    }
+}
+
+/// Reads a `CompactSize`, rejecting encodings that are longer than necessary as Bitcoin does.
+fn read_compact_size(bytes: &mut &[u8]) -> Result<u64, MerkleError> {
+    let (value, min) = match take(bytes, 1)?[0] {
+        0xfd => (u16::from_le_bytes(take(bytes, 2)?.try_into().unwrap()) as u64, 0xfd),
+        0xfe => (u32::from_le_bytes(take(bytes, 4)?.try_into().unwrap()) as u64, 0x1_0000),
+        0xff => (u64::from_le_bytes(take(bytes, 8)?.try_into().unwrap()), 0x1_0000_0000),
+        small => (small as u64, 0),
+    };
+    if value < min {
+        return Err(MerkleError::InvalidEncoding);
+    }
+    Ok(value)
+}
+
+/// Reads the `CompactSize` count of a list of entries of `entry_len` bytes, rejecting counts the
+/// rest of the data cannot hold before anything is allocated for them.
+fn read_count(bytes: &mut &[u8], entry_len: usize) -> Result<usize, MerkleError> {
+    let count = read_compact_size(bytes)?;
+    match usize::try_from(count) {
+        Ok(count) if count.saturating_mul(entry_len) <= bytes.len() => Ok(count),
+        _ => Err(MerkleError::TruncatedData),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Block 100000, whose four transactions are proven in the BIP 37 examples.
+    const BLOCK_100000_HEADER: &str = "0100000050120119172a610421a6c3011dd330d9df07b63616c2cc1f1cd00200000000006657a9252aacd5c0b2940996ecff952228c3067cc38d4885efb5a4ac4247e9f337221b4d4c86041b0f2b5710";
+    const BLOCK_100000_HASH: &str = "000000000003ba27aa200b1cecaad478d2b00432346c3f1f3986da1afd33e506";
+    const BLOCK_100000_TXIDS: [&str; 4] = [
+        "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+        "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+        "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+        "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+    ];
+
+    /// The genesis block, whose only transaction is its coinbase.
+    const GENESIS_HEADER: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+    const GENESIS_HASH: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+    const GENESIS_COINBASE: &str = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+
+    fn header(hex: &str) -> [u8; 80] {
+        hex::decode(hex).unwrap().try_into().unwrap()
+    }
+
+    fn txids(hexes: &[&str]) -> Vec<[u8; 32]> {
+        hexes.iter().map(|hex| txid_from_hex(hex).unwrap()).collect()
+    }
+
+    fn txid(i: u8) -> [u8; 32] {
+        DoubleSha256Hasher::hash_leaf(&[i])
+    }
+
+    #[test]
+    fn roots_match_block_headers() {
+        let block_100000 = header(BLOCK_100000_HEADER);
+        assert_eq!(BLOCK_100000_HASH, txid_to_hex(&DoubleSha256Hasher::hash_leaf(&block_100000)));
+        let (root, mutated) = bitcoin_merkle_root(&txids(&BLOCK_100000_TXIDS));
+        assert_eq!(header_merkle_root(&block_100000), root);
+        assert_eq!("f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766", txid_to_hex(&root));
+        assert!(!mutated);
+
+        let genesis = header(GENESIS_HEADER);
+        assert_eq!(GENESIS_HASH, txid_to_hex(&DoubleSha256Hasher::hash_leaf(&genesis)));
+        assert_eq!((header_merkle_root(&genesis), false), bitcoin_merkle_root(&txids(&[GENESIS_COINBASE])));
+
+        // Block 170, with the first transaction that spends coins
+        let block_170 = txids(&[
+            "b1fea52486ce0c62bb442b530a3f0132b826c74e473d1f2c220bfa78111c5082",
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+        ]);
+        assert_eq!(
+            "7dac2c5666815c17a3b36427de37bb9d2e2c5ccec3f8633eb91a4205cb4c10ff",
+            txid_to_hex(&bitcoin_merkle_root(&block_170).0)
+        );
+        assert_eq!(([0; 32], false), bitcoin_merkle_root(&[]));
+    }
+
+    #[test]
+    fn odd_levels_duplicate_their_last_node() {
+        let [a, b, c] = [txid(0), txid(1), txid(2)];
+        let h = DoubleSha256Hasher::hash_nodes;
+        assert_eq!((h(&h(&a, &b), &h(&c, &c)), false), bitcoin_merkle_root(&[a, b, c]));
+    }
+
+    #[test]
+    fn duplicated_transactions_are_detected() {
+        let txids: Vec<[u8; 32]> = (0..6).map(txid).collect();
+        let (root, mutated) = bitcoin_merkle_root(&txids);
+        assert!(!mutated);
+        // Repeating the last two transactions duplicates the node they hash to, at the same root
+        let mut duplicated = txids.clone();
+        duplicated.extend_from_slice(&txids[4..]);
+        assert_eq!((root, true), bitcoin_merkle_root(&duplicated));
+        let mut repeated_last = txids[..5].to_vec();
+        repeated_last.push(txids[4]);
+        assert_eq!((bitcoin_merkle_root(&txids[..5]).0, true), bitcoin_merkle_root(&repeated_last));
+
+        let mut matches = vec![false; 8];
+        matches[6] = true;
+        let partial = PartialMerkleTree::new(&duplicated, &matches).unwrap();
+        assert_eq!(Err(MerkleError::MutatedTree), partial.extract_matches());
+    }
+
+    #[test]
+    fn partial_tree_layout() {
+        // Proving the second of four transactions: the root and its left child are ancestors, the
+        // first two transactions and the right child are hashes
+        let txids = txids(&BLOCK_100000_TXIDS);
+        let partial = PartialMerkleTree::new(&txids, &[false, true, false, false]).unwrap();
+        let h = DoubleSha256Hasher::hash_nodes;
+        assert_eq!(vec![txids[0], txids[1], h(&txids[2], &txids[3])], partial.hashes);
+        assert_eq!(vec![true, true, false, true, false], partial.flags);
+
+        let bytes = partial.to_bytes();
+        assert_eq!(&[4, 0, 0, 0, 3], &bytes[..5]);
+        assert_eq!(&[1, 0b01011], &bytes[5 + 3 * 32..]);
+        let decoded = PartialMerkleTree::from_bytes(&bytes).unwrap();
+        assert_eq!(8, decoded.flags.len());
+        let (root, matches) = decoded.extract_matches().unwrap();
+        assert_eq!(header_merkle_root(&header(BLOCK_100000_HEADER)), root);
+        assert_eq!(vec![(1, txids[1])], matches);
+    }
+
+    #[test]
+    fn every_selection_round_trips() {
+        for n in 1..=13u8 {
+            let txids: Vec<[u8; 32]> = (0..n).map(txid).collect();
+            let (root, _) = bitcoin_merkle_root(&txids);
+            for selection in 0..1u32 << n {
+                let matches: Vec<bool> = (0..n).map(|i| selection & (1 << i) != 0).collect();
+                let partial = PartialMerkleTree::from_bytes(&PartialMerkleTree::new(&txids, &matches).unwrap().to_bytes())
+                    .unwrap();
+                let expected: MatchedTransactions =
+                    (0..n as usize).filter(|&i| matches[i]).map(|i| (i, txids[i])).collect();
+                assert_eq!(Ok((root, expected)), partial.extract_matches());
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_partial_trees() {
+        let txids: Vec<[u8; 32]> = (0..10).map(txid).collect();
+        let mut matches = vec![false; 10];
+        matches[3] = true;
+        let partial = PartialMerkleTree::new(&txids, &matches).unwrap();
+
+        let mut extra_hash = partial.clone();
+        extra_hash.hashes.push([0; 32]);
+        assert_eq!(Err(MerkleError::MalformedProof), extra_hash.extract_matches());
+        let mut missing_flag = partial.clone();
+        missing_flag.flags.truncate(3);
+        assert_eq!(Err(MerkleError::MalformedProof), missing_flag.extract_matches());
+        let mut extra_flags = partial.clone();
+        extra_flags.flags.extend([false; 8]);
+        assert_eq!(Err(MerkleError::MalformedProof), extra_flags.extract_matches());
+        let mut tampered = partial.clone();
+        tampered.hashes[0][0] ^= 1;
+        assert_ne!(bitcoin_merkle_root(&txids).0, tampered.extract_matches().unwrap().0);
+        let mut empty = partial.clone();
+        empty.total_transactions = 0;
+        assert_eq!(Err(MerkleError::EmptyTree), empty.extract_matches());
+
+        let bytes = partial.to_bytes();
+        assert_eq!(Err(MerkleError::TruncatedData), PartialMerkleTree::from_bytes(&bytes[..bytes.len() - 1]));
+        assert_eq!(Err(MerkleError::TrailingData(1)), PartialMerkleTree::from_bytes(&[&bytes[..], &[0]].concat()));
+        // The count of hashes written with three bytes instead of one
+        let mut long_count = bytes[..4].to_vec();
+        long_count.extend([0xfd, bytes[4], 0]);
+        long_count.extend_from_slice(&bytes[5..]);
+        assert_eq!(Err(MerkleError::InvalidEncoding), PartialMerkleTree::from_bytes(&long_count));
+
+        assert_eq!(
+            Err(MerkleError::ProofLengthMismatch { expected: 10, actual: 9 }),
+            PartialMerkleTree::new(&txids, &matches[..9])
+        );
+        assert_eq!(Err(MerkleError::InvalidEncoding), txid_from_hex("abcd"));
+    }
+}
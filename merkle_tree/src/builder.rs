@@ -0,0 +1,181 @@
+//! Building the root of a tree one leaf at a time.
+//!
+//! A [`MerkleBuilder`] only keeps the frontier of the tree: the roots of the complete subtrees of
+//! 2^h leaves that the leaves pushed so far fill, one for every bit set in their number, largest
+//! first. Pushing a leaf merges the subtrees of equal size like a binary counter carries, so the
+//! builder holds O(log n) digests however many leaves it has seen, and the root of the leaves so
+//! far can be computed from the frontier at any point.
+
+use crate::{concatenate_hash_values, MerkleHasher, TreeShape};
+
+/// Computes the root of a stream of leaves of unknown length in O(log n) memory.
+///
+/// The root is the same as [`crate::calculate_merkle_root_with_shape`] would compute for all of
+/// the leaves at once.
+#[derive(Debug, Clone)]
+pub struct MerkleBuilder<H: MerkleHasher> {
+    // The root of the complete subtree of 2^h leaves at index h, if bit h of `len` is set
+    frontier: Vec<Option<H::Digest>>,
+    len: usize,
+    shape: TreeShape,
+}
+
+impl<H: MerkleHasher> Default for MerkleBuilder<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H: MerkleHasher> MerkleBuilder<H> {
+    /// Creates a builder for a tree padded to a power of two, like [`crate::calculate_merkle_root`].
+    pub fn new() -> Self {
+        Self::with_shape(TreeShape::Padded)
+    }
+
+    /// Creates a builder for a tree of the given shape.
+    pub fn with_shape(shape: TreeShape) -> Self {
+        MerkleBuilder { frontier: Vec::new(), len: 0, shape }
+    }
+
+    /// The number of leaves pushed so far.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn shape(&self) -> TreeShape {
+        self.shape
+    }
+
+    /// Hashes a data block with [`MerkleHasher::hash_leaf`] and appends it.
+    pub fn push<T: AsRef<[u8]>>(&mut self, leaf: T) {
+        self.push_digest(H::hash_leaf(leaf.as_ref()));
+    }
+
+    /// Appends a leaf that was already hashed.
+    pub fn push_digest(&mut self, leaf: H::Digest) {
+        let mut node = leaf;
+        for height in 0.. {
+            if height == self.frontier.len() {
+                self.frontier.push(None);
+            }
+            match self.frontier[height].take() {
+                // Two complete subtrees of the same size make one twice as large
+                Some(left) => node = concatenate_hash_values::<H>(left, node),
+                None => {
+                    self.frontier[height] = Some(node);
+                    break;
+                }
+            }
+        }
+        self.len += 1;
+    }
+
+    /// Returns the roots of the complete subtrees the leaves so far fill, from the largest (and
+    /// leftmost) to the smallest.
+    pub fn frontier(&self) -> Vec<H::Digest> {
+        self.frontier.iter().rev().flatten().copied().collect()
+    }
+
+    /// Returns the root of the tree of the leaves pushed so far. The builder can keep taking
+    /// leaves afterwards.
+    pub fn root_so_far(&self) -> H::Digest {
+        match self.shape {
+            TreeShape::Padded => self.padded_root(),
+            TreeShape::Unbalanced => self.unbalanced_root(),
+        }
+    }
+
+    /// Returns the root of the tree of all the leaves that were pushed.
+    pub fn finalize(self) -> H::Digest {
+        self.root_so_far()
+    }
+
+    /// Each subtree of the frontier is the left sibling of the (promoted) root of the smaller ones.
+    fn unbalanced_root(&self) -> H::Digest {
+        let mut subtrees = self.frontier.iter().flatten();
+        let Some(&smallest) = subtrees.next() else {
+            return H::hash_parts(&[]);
+        };
+        subtrees.fold(smallest, |right, &left| concatenate_hash_values::<H>(left, right))
+    }
+
+    /// Walks up the right edge of the padded tree, giving the partial subtree at each height the
+    /// root of an empty subtree as its right sibling where the leaves ran out.
+    fn padded_root(&self) -> H::Digest {
+        let mut empty = H::hash_leaf(&[]);
+        if self.len == 0 {
+            return empty;
+        }
+        if self.len.is_power_of_two() {
+            return self.frontier[self.frontier.len() - 1].unwrap();
+        }
+
+        // The root of the rightmost subtree at the current height that has any leaves in it
+        let mut partial: Option<H::Digest> = None;
+        let height = self.len.next_power_of_two().trailing_zeros() as usize;
+        for subtree in &self.frontier[..height] {
+            partial = match (subtree, partial) {
+                (Some(left), right) => Some(concatenate_hash_values::<H>(*left, right.unwrap_or(empty))),
+                (None, Some(left)) => Some(concatenate_hash_values::<H>(left, empty)),
+                (None, None) => None,
+            };
+            empty = concatenate_hash_values::<H>(empty, empty);
+        }
+        partial.unwrap()
+    }
+}
+
+impl<H: MerkleHasher, T: AsRef<[u8]>> Extend<T> for MerkleBuilder<H> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, leaves: I) {
+        for leaf in leaves {
+            self.push(leaf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calculate_merkle_root, calculate_merkle_root_with_shape, SipHasher64};
+
+    #[test]
+    fn roots_match_the_whole_tree() {
+        for shape in [TreeShape::Padded, TreeShape::Unbalanced] {
+            let mut builder = MerkleBuilder::<SipHasher64>::with_shape(shape);
+            let mut leaves = Vec::new();
+            for n in 0..=300u32 {
+                assert_eq!(calculate_merkle_root_with_shape::<SipHasher64>(leaves.clone(), shape), builder.root_so_far());
+                assert_eq!(n.count_ones() as usize, builder.frontier().len());
+
+                let leaf = SipHasher64::hash_leaf(&n.to_le_bytes());
+                builder.push_digest(leaf);
+                leaves.push(leaf);
+            }
+            assert_eq!(301, builder.len());
+            assert_eq!(calculate_merkle_root_with_shape::<SipHasher64>(leaves, shape), builder.finalize());
+        }
+    }
+
+    #[test]
+    fn builds_a_sentence() {
+        let sentence = "Here's an eight word sentence, special for you and me.";
+        let mut builder = MerkleBuilder::<SipHasher64>::new();
+        builder.extend(sentence.split_whitespace());
+        assert_eq!(10, builder.len());
+        assert_eq!(calculate_merkle_root::<SipHasher64>(sentence), builder.finalize());
+    }
+
+    #[test]
+    fn frontier_is_the_complete_subtrees() {
+        let mut builder = MerkleBuilder::<SipHasher64>::with_shape(TreeShape::Unbalanced);
+        builder.extend(["a", "b", "c", "d", "e", "f"]);
+        let [a, b, c, d, e, f] = ["a", "b", "c", "d", "e", "f"].map(|leaf| SipHasher64::hash_leaf(leaf.as_bytes()));
+        let h = concatenate_hash_values::<SipHasher64>;
+        assert_eq!(vec![h(h(a, b), h(c, d)), h(e, f)], builder.frontier());
+        assert_eq!(h(h(h(a, b), h(c, d)), h(e, f)), builder.root_so_far());
+    }
+}
@@ -0,0 +1,233 @@
+//! Content-defined chunking with FastCDC.
+//!
+//! Fixed-size blocks shift when bytes are inserted or removed, so every block after an edit
+//! changes. [`FastCdc`] instead cuts where a Gear rolling hash of the last bytes matches a mask, so
+//! a boundary only depends on the data around it and the boundaries after an edit fall on the same
+//! content as before. Two versions of a file then share all but the chunks around the edit, and so
+//! most of their Merkle leaves.
+//!
+//! This follows FastCDC (Xia et al., 2016): the first `min_size` bytes of a chunk are never hashed,
+//! a stricter mask is used before `avg_size` and a looser one after it (normalized chunking), so
+//! chunk sizes cluster around `avg_size`, and chunks are cut at `max_size` at the latest.
+
+use std::io::{self, Read};
+
+/// Random values for each byte, generated with SplitMix64 from a fixed seed. Changing them moves
+/// every chunk boundary.
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x6a09_e667_f3bc_c908;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// A mask of the `bits` highest bits, which the Gear hash mixes the most bytes into.
+fn high_bits(bits: u32) -> u64 {
+    u64::MAX.checked_shl(64 - bits).unwrap_or(0)
+}
+
+/// The chunk size parameters of content-defined chunking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FastCdc {
+    min_size: usize,
+    avg_size: usize,
+    max_size: usize,
+}
+
+impl Default for FastCdc {
+    /// Chunks of 16 KiB to 256 KiB, 64 KiB on average like [`crate::DEFAULT_CHUNK_SIZE`].
+    fn default() -> Self {
+        Self::new(16 * 1024, 64 * 1024, 256 * 1024)
+    }
+}
+
+impl FastCdc {
+    /// Panics unless `0 < min_size <= avg_size <= max_size` and `avg_size` is at least 2.
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        assert!(
+            0 < min_size && min_size <= avg_size && avg_size <= max_size && avg_size >= 2,
+            "Chunk sizes must satisfy 0 < min <= avg <= max and avg >= 2"
+        );
+        FastCdc { min_size, avg_size, max_size }
+    }
+
+    pub fn min_size(&self) -> usize {
+        self.min_size
+    }
+
+    pub fn avg_size(&self) -> usize {
+        self.avg_size
+    }
+
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Returns the length of the chunk at the start of `data`, which is all of it if `data` ends
+    /// before a boundary is found.
+    pub fn cut(&self, data: &[u8]) -> usize {
+        if data.len() <= self.min_size {
+            return data.len();
+        }
+        let end = data.len().min(self.max_size);
+        let normal = end.min(self.avg_size);
+        let bits = self.avg_size.ilog2();
+        let (strict, loose) = (high_bits(bits + 1), high_bits(bits - 1));
+
+        let mut hash = 0u64;
+        for (i, &byte) in data[..end].iter().enumerate().skip(self.min_size) {
+            hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+            let mask = if i < normal { strict } else { loose };
+            if hash & mask == 0 {
+                return i + 1;
+            }
+        }
+        end
+    }
+
+    /// Splits `data` into content-defined chunks.
+    pub fn chunks<'a>(&self, data: &'a [u8]) -> impl Iterator<Item = &'a [u8]> {
+        let cdc = *self;
+        let mut rest = data;
+        std::iter::from_fn(move || {
+            if rest.is_empty() {
+                return None;
+            }
+            let (chunk, tail) = rest.split_at(cdc.cut(rest));
+            rest = tail;
+            Some(chunk)
+        })
+    }
+
+    /// Splits a stream into content-defined chunks, holding at most `max_size` bytes at a time.
+    pub fn chunks_from_reader<R: Read>(&self, reader: R) -> ContentChunks<R> {
+        ContentChunks { reader, cdc: *self, buffer: Vec::with_capacity(self.max_size), eof: false }
+    }
+}
+
+/// An iterator over the content-defined chunks of a stream, see [`FastCdc::chunks_from_reader`].
+#[derive(Debug)]
+pub struct ContentChunks<R> {
+    reader: R,
+    cdc: FastCdc,
+    buffer: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> ContentChunks<R> {
+    /// Reads until `max_size` bytes are buffered or the stream ends, so a cut sees as much as it
+    /// would of the whole data.
+    fn fill(&mut self) -> io::Result<()> {
+        while !self.eof && self.buffer.len() < self.cdc.max_size {
+            let len = self.buffer.len();
+            self.buffer.resize(self.cdc.max_size, 0);
+            match self.reader.read(&mut self.buffer[len..]) {
+                Ok(read) => {
+                    self.buffer.truncate(len + read);
+                    self.eof = read == 0;
+                }
+                Err(err) => {
+                    self.buffer.truncate(len);
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for ContentChunks<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(err) = self.fill() {
+            return Some(Err(err));
+        }
+        if self.buffer.is_empty() {
+            return None;
+        }
+        let rest = self.buffer.split_off(self.cdc.cut(&self.buffer));
+        Some(Ok(std::mem::replace(&mut self.buffer, rest)))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Pseudorandom bytes, so the rolling hash sees varied content.
+    pub(crate) fn random_data(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn chunk_sizes_are_within_bounds() {
+        let cdc = FastCdc::new(512, 2048, 8192);
+        let data = random_data(1 << 20, 1);
+        let chunks: Vec<&[u8]> = cdc.chunks(&data).collect();
+        assert_eq!(data, chunks.concat());
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(rest.iter().all(|chunk| (512..=8192).contains(&chunk.len())));
+        assert!(!last.is_empty() && last.len() <= 8192);
+
+        // Normalized chunking keeps the average close to the target
+        let average = data.len() / chunks.len();
+        assert!((1500..3000).contains(&average), "average chunk size {}", average);
+    }
+
+    #[test]
+    fn repeated_bytes_give_equal_chunks() {
+        let cdc = FastCdc::new(64, 256, 1024);
+        let data = vec![0u8; 5000];
+        let sizes: Vec<usize> = cdc.chunks(&data).map(<[u8]>::len).collect();
+        // The hash is the same from every chunk start, so every chunk is cut at the same length
+        assert!(sizes[..sizes.len() - 1].windows(2).all(|pair| pair[0] == pair[1]));
+    }
+
+    #[test]
+    fn reader_matches_slices() {
+        let cdc = FastCdc::new(256, 1024, 4096);
+        let data = random_data(100_000, 2);
+        let expected: Vec<&[u8]> = cdc.chunks(&data).collect();
+        let chunks: Vec<Vec<u8>> = cdc.chunks_from_reader(data.as_slice()).collect::<io::Result<_>>().unwrap();
+        assert_eq!(expected, chunks);
+        assert_eq!(0, cdc.chunks_from_reader(io::empty()).count());
+    }
+
+    #[test]
+    fn boundaries_survive_an_insertion() {
+        let cdc = FastCdc::new(256, 1024, 4096);
+        let data = random_data(200_000, 3);
+        let mut edited = data.clone();
+        edited.splice(100_000..100_000, b"inserted in the middle".iter().copied());
+
+        let before: Vec<&[u8]> = cdc.chunks(&data).collect();
+        let after: Vec<&[u8]> = cdc.chunks(&edited).collect();
+        let changed = after.iter().filter(|chunk| !before.contains(chunk)).count();
+        assert!(changed <= 2, "{} of {} chunks changed", changed, after.len());
+    }
+
+    #[test]
+    #[should_panic(expected = "Chunk sizes must satisfy")]
+    fn invalid_sizes() {
+        FastCdc::new(4096, 1024, 8192);
+    }
+}
@@ -0,0 +1,390 @@
+//! Merkle trees over the blocks of a file.
+//!
+//! A [`ChunkedFile`] reads a stream in blocks of `chunk_size` bytes (the last one may be shorter),
+//! or in content-defined chunks (see [`FastCdc`]), hashes each block into a leaf and keeps only the
+//! tree of their digests. Any block, or the blocks covering a byte range, can then be proven on its
+//! own, so a client that knows the root, the file length and the chunk size can check part of a
+//! file without downloading all of it.
+
+use crate::error::check_index;
+use crate::{
+    try_validate_compact_multiproof_for_digests, verify_digest_at, CompactMerkleMultiProof, CompactMerkleProof,
+    FastCdc, MerkleError, MerkleHasher, MerkleTree,
+};
+use std::collections::HashSet;
+use std::io::{self, Read};
+use std::ops::Range;
+
+/// The block size used unless another one is configured.
+pub const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The number of blocks of `chunk_size` bytes in a file of `file_len` bytes.
+fn chunk_count(file_len: usize, chunk_size: usize) -> usize {
+    file_len.div_ceil(chunk_size)
+}
+
+/// The byte range of the block at `index` in a file of `file_len` bytes.
+fn chunk_bytes(file_len: usize, chunk_size: usize, index: usize) -> Range<usize> {
+    index * chunk_size..file_len.min((index + 1) * chunk_size)
+}
+
+/// The indices of the blocks that cover the bytes `start..end`, after checking that the range is
+/// non-empty and within the file.
+fn covering_chunks(file_len: usize, chunk_size: usize, start: usize, end: usize) -> Result<Range<usize>, MerkleError> {
+    if start >= end || end > file_len {
+        return Err(MerkleError::InvalidRange { start, end, len: file_len });
+    }
+    Ok(start / chunk_size..end.div_ceil(chunk_size))
+}
+
+/// A Merkle tree over the blocks of a file, one leaf per block.
+///
+/// The blocks are either of a fixed size or content-defined by a [`FastCdc`] chunker, in which case
+/// two versions of a file share most of their leaves.
+#[derive(Debug, Clone)]
+pub struct ChunkedFile<H: MerkleHasher> {
+    tree: MerkleTree<H>,
+    // The offset just past each block
+    ends: Vec<usize>,
+    chunk_size: Option<usize>,
+}
+
+impl<H: MerkleHasher> ChunkedFile<H> {
+    /// Reads `reader` to the end in blocks of `chunk_size` bytes and builds the tree of their
+    /// hashes. Only one block is held in memory at a time.
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn from_reader<R: Read>(mut reader: R, chunk_size: usize) -> io::Result<Self> {
+        assert!(chunk_size > 0, "The chunk size must not be zero");
+        let chunks = std::iter::from_fn(|| {
+            let mut chunk = Vec::with_capacity(chunk_size);
+            // `take` keeps reading until the block is full or the stream ends
+            match reader.by_ref().take(chunk_size as u64).read_to_end(&mut chunk) {
+                Ok(0) => None,
+                Ok(_) => Some(Ok(chunk)),
+                Err(err) => Some(Err(err)),
+            }
+        });
+        Self::from_chunks(chunks, Some(chunk_size))
+    }
+
+    /// Builds the tree of the blocks of data that is already in memory.
+    ///
+    /// Panics if `chunk_size` is zero.
+    pub fn from_bytes(data: &[u8], chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "The chunk size must not be zero");
+        Self::from_chunks(data.chunks(chunk_size).map(Ok), Some(chunk_size)).expect("Data in memory cannot fail to read")
+    }
+
+    /// Reads `reader` to the end in content-defined chunks and builds the tree of their hashes.
+    /// At most [`FastCdc::max_size`] bytes are held in memory at a time.
+    pub fn from_reader_content_defined<R: Read>(reader: R, cdc: &FastCdc) -> io::Result<Self> {
+        Self::from_chunks(cdc.chunks_from_reader(reader), None)
+    }
+
+    /// Builds the tree of the content-defined chunks of data that is already in memory.
+    pub fn from_bytes_content_defined(data: &[u8], cdc: &FastCdc) -> Self {
+        Self::from_chunks(cdc.chunks(data).map(Ok), None).expect("Data in memory cannot fail to read")
+    }
+
+    fn from_chunks<C: AsRef<[u8]>>(
+        chunks: impl Iterator<Item = io::Result<C>>,
+        chunk_size: Option<usize>,
+    ) -> io::Result<Self> {
+        let mut leaves = Vec::new();
+        let mut ends = Vec::new();
+        let mut len = 0;
+        for chunk in chunks {
+            let chunk = chunk?;
+            leaves.push(H::hash_leaf(chunk.as_ref()));
+            len += chunk.as_ref().len();
+            ends.push(len);
+        }
+        Ok(ChunkedFile { tree: MerkleTree::from_leaf_digests(leaves), ends, chunk_size })
+    }
+
+    pub fn root(&self) -> H::Digest {
+        self.tree.root()
+    }
+
+    pub fn tree(&self) -> &MerkleTree<H> {
+        &self.tree
+    }
+
+    /// The size of the blocks, or `None` if they are content-defined.
+    pub fn chunk_size(&self) -> Option<usize> {
+        self.chunk_size
+    }
+
+    /// The length of the file in bytes.
+    pub fn len(&self) -> usize {
+        self.ends.last().copied().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    /// The number of blocks, and so of leaves.
+    pub fn chunk_count(&self) -> usize {
+        self.tree.len()
+    }
+
+    /// The byte range of the block at `index` within the file.
+    ///
+    /// Panics if the index is beyond the number of blocks.
+    pub fn chunk_bytes(&self, index: usize) -> Range<usize> {
+        let start = index.checked_sub(1).map_or(0, |previous| self.ends[previous]);
+        start..self.ends[index]
+    }
+
+    /// The indices of the blocks that cover the bytes `start..end`, which a
+    /// [`ChunkedFile::byte_range_proof`] proves.
+    pub fn covering_chunks(&self, start: usize, end: usize) -> Result<Range<usize>, MerkleError> {
+        if start >= end || end > self.len() {
+            return Err(MerkleError::InvalidRange { start, end, len: self.len() });
+        }
+        let first = self.ends.partition_point(|&chunk_end| chunk_end <= start);
+        let last = self.ends.partition_point(|&chunk_end| chunk_end < end);
+        Ok(first..last + 1)
+    }
+
+    /// Returns the indices of the blocks whose hash is not a leaf of `other`, i.e. the blocks to
+    /// send to someone who already has `other`, such as an earlier version of the file.
+    pub fn chunks_missing_from(&self, other: &ChunkedFile<H>) -> Vec<usize> {
+        let known: HashSet<&H::Digest> = other.tree.leaves().iter().collect();
+        let leaves = self.tree.leaves();
+        (0..self.chunk_count()).filter(|&index| !known.contains(&leaves[index])).collect()
+    }
+
+    /// Returns the proof for the block at `index`. For fixed-size blocks, check it with
+    /// [`verify_chunk`].
+    pub fn chunk_proof(&self, index: usize) -> Result<CompactMerkleProof<H::Digest>, MerkleError> {
+        self.tree.try_compact_proof(index)
+    }
+
+    /// Returns the proof for the blocks that cover the bytes `start..end`. For fixed-size blocks,
+    /// check it with [`verify_byte_range`] against the data of those blocks; a verifier cannot
+    /// derive the offsets of content-defined blocks from the file length.
+    pub fn byte_range_proof(&self, start: usize, end: usize) -> Result<CompactMerkleMultiProof<H::Digest>, MerkleError> {
+        let chunks: Vec<usize> = self.covering_chunks(start, end)?.collect();
+        self.tree.try_multiproof(&chunks)
+    }
+}
+
+/// Checks that `chunk` is the block at `index` of a file of `file_len` bytes, split into blocks of
+/// `chunk_size` bytes, whose tree has the given root.
+///
+/// Panics if `chunk_size` is zero.
+pub fn verify_chunk<H: MerkleHasher>(
+    root: &H::Digest,
+    file_len: usize,
+    chunk_size: usize,
+    index: usize,
+    chunk: &[u8],
+    proof: &CompactMerkleProof<H::Digest>,
+) -> Result<(), MerkleError> {
+    let tree_size = chunk_count(file_len, chunk_size);
+    check_index(index, tree_size)?;
+    if (proof.leaf_index, proof.tree_size) != (index, tree_size) {
+        return Err(MerkleError::MalformedProof);
+    }
+    let expected = chunk_bytes(file_len, chunk_size, index).len();
+    if chunk.len() != expected {
+        return Err(MerkleError::DataLengthMismatch { expected, actual: chunk.len() });
+    }
+    verify_digest_at::<H>(root, index, tree_size, H::hash_leaf(chunk), &proof.to_proof()?)
+}
+
+/// Checks the data of the blocks covering the bytes `start..end` of a file of `file_len` bytes,
+/// split into blocks of `chunk_size` bytes, against the root of its tree. Returns the bytes
+/// `start..end` out of `chunks`.
+///
+/// Panics if `chunk_size` is zero.
+pub fn verify_byte_range<'a, H: MerkleHasher>(
+    root: &H::Digest,
+    file_len: usize,
+    chunk_size: usize,
+    start: usize,
+    end: usize,
+    chunks: &'a [u8],
+    proof: &CompactMerkleMultiProof<H::Digest>,
+) -> Result<&'a [u8], MerkleError> {
+    let covering = covering_chunks(file_len, chunk_size, start, end)?;
+    if proof.tree_size != chunk_count(file_len, chunk_size) || !proof.leaf_indices.iter().copied().eq(covering.clone()) {
+        return Err(MerkleError::MalformedProof);
+    }
+    let offset = covering.start * chunk_size;
+    let expected = chunk_bytes(file_len, chunk_size, covering.end - 1).end - offset;
+    if chunks.len() != expected {
+        return Err(MerkleError::DataLengthMismatch { expected, actual: chunks.len() });
+    }
+
+    let leaves = chunks.chunks(chunk_size).map(H::hash_leaf).collect();
+    if !try_validate_compact_multiproof_for_digests::<H>(root, leaves, proof.clone())? {
+        return Err(MerkleError::RootMismatch);
+    }
+    Ok(&chunks[start - offset..end - offset])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cdc::tests::random_data;
+    use crate::{validate_compact_proof_for_digest, SipHasher64};
+
+    const CHUNK_SIZE: usize = 16;
+
+    fn file(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    /// Returns at most three bytes per read, to check that blocks are filled across reads.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(3);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn reading_matches_splitting() {
+        for len in [0, 1, 15, 16, 17, 160, 165] {
+            let data = file(len);
+            let chunked = ChunkedFile::<SipHasher64>::from_reader(Trickle(&data), CHUNK_SIZE).unwrap();
+            let expected = ChunkedFile::<SipHasher64>::from_bytes(&data, CHUNK_SIZE);
+            assert_eq!(expected.root(), chunked.root());
+            assert_eq!(expected.tree().leaves(), chunked.tree().leaves());
+            assert_eq!(len, chunked.len());
+            assert_eq!(Some(CHUNK_SIZE), chunked.chunk_size());
+            assert_eq!(len.div_ceil(CHUNK_SIZE), chunked.chunk_count());
+        }
+    }
+
+    #[test]
+    fn chunk_proofs() {
+        let data = file(165);
+        let chunked = ChunkedFile::<SipHasher64>::from_reader(data.as_slice(), CHUNK_SIZE).unwrap();
+        let root = chunked.root();
+        assert_eq!(11, chunked.chunk_count());
+        assert_eq!(160..165, chunked.chunk_bytes(10));
+
+        for index in 0..chunked.chunk_count() {
+            let chunk = &data[chunked.chunk_bytes(index)];
+            let proof = chunked.chunk_proof(index).unwrap();
+            assert_eq!(Ok(()), verify_chunk::<SipHasher64>(&root, data.len(), CHUNK_SIZE, index, chunk, &proof));
+        }
+
+        let proof = chunked.chunk_proof(3).unwrap();
+        let mut chunk = data[48..64].to_vec();
+        assert_eq!(
+            Err(MerkleError::MalformedProof),
+            verify_chunk::<SipHasher64>(&root, data.len(), CHUNK_SIZE, 4, &data[64..80], &proof)
+        );
+        assert_eq!(
+            Err(MerkleError::DataLengthMismatch { expected: 16, actual: 15 }),
+            verify_chunk::<SipHasher64>(&root, data.len(), CHUNK_SIZE, 3, &chunk[1..], &proof)
+        );
+        chunk[0] ^= 1;
+        assert_eq!(
+            Err(MerkleError::RootMismatch),
+            verify_chunk::<SipHasher64>(&root, data.len(), CHUNK_SIZE, 3, &chunk, &proof)
+        );
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 11, len: 11 }), chunked.chunk_proof(11));
+    }
+
+    #[test]
+    fn byte_range_proofs() {
+        let data = file(165);
+        let chunked = ChunkedFile::<SipHasher64>::from_bytes(&data, CHUNK_SIZE);
+        let root = chunked.root();
+
+        for (start, end) in [(0, 1), (5, 12), (15, 17), (16, 32), (20, 100), (150, 165), (0, 165)] {
+            let covering = chunked.covering_chunks(start, end).unwrap();
+            let chunks = &data[covering.start * CHUNK_SIZE..data.len().min(covering.end * CHUNK_SIZE)];
+            let proof = chunked.byte_range_proof(start, end).unwrap();
+            assert_eq!(
+                Ok(&data[start..end]),
+                verify_byte_range::<SipHasher64>(&root, data.len(), CHUNK_SIZE, start, end, chunks, &proof)
+            );
+        }
+
+        let proof = chunked.byte_range_proof(20, 40).unwrap();
+        let mut chunks = data[16..48].to_vec();
+        assert_eq!(
+            Err(MerkleError::MalformedProof),
+            verify_byte_range::<SipHasher64>(&root, data.len(), CHUNK_SIZE, 20, 50, &data[16..64], &proof)
+        );
+        assert_eq!(
+            Err(MerkleError::DataLengthMismatch { expected: 32, actual: 31 }),
+            verify_byte_range::<SipHasher64>(&root, data.len(), CHUNK_SIZE, 20, 40, &chunks[..31], &proof)
+        );
+        chunks[30] ^= 1;
+        assert_eq!(
+            Err(MerkleError::RootMismatch),
+            verify_byte_range::<SipHasher64>(&root, data.len(), CHUNK_SIZE, 20, 40, &chunks, &proof)
+        );
+
+        for (start, end) in [(5, 5), (6, 5), (160, 166)] {
+            assert_eq!(Err(MerkleError::InvalidRange { start, end, len: 165 }), chunked.byte_range_proof(start, end));
+        }
+    }
+
+    #[test]
+    fn content_defined_chunks() {
+        let cdc = FastCdc::new(256, 1024, 4096);
+        let data = random_data(100_000, 4);
+        let chunked = ChunkedFile::<SipHasher64>::from_reader_content_defined(Trickle(&data), &cdc).unwrap();
+        assert_eq!(ChunkedFile::<SipHasher64>::from_bytes_content_defined(&data, &cdc).root(), chunked.root());
+        assert_eq!(None, chunked.chunk_size());
+        assert_eq!(data.len(), chunked.len());
+
+        let chunks: Vec<&[u8]> = cdc.chunks(&data).collect();
+        assert_eq!(chunks.len(), chunked.chunk_count());
+        for (index, chunk) in chunks.iter().enumerate() {
+            assert_eq!(*chunk, &data[chunked.chunk_bytes(index)]);
+            let proof = chunked.chunk_proof(index).unwrap();
+            let leaf_hash = SipHasher64::hash_leaf(chunk);
+            assert!(validate_compact_proof_for_digest::<SipHasher64>(&chunked.root(), leaf_hash, proof));
+        }
+
+        let covering = chunked.covering_chunks(5000, 5001).unwrap();
+        assert_eq!(1, covering.len());
+        assert!(chunked.chunk_bytes(covering.start).contains(&5000));
+        assert_eq!(Ok(0..chunks.len()), chunked.covering_chunks(0, data.len()));
+    }
+
+    /// Only the chunks around an edit have to be sent again, unlike with fixed-size blocks where
+    /// every block after an insertion shifts.
+    #[test]
+    fn edited_files_share_most_chunks() {
+        let cdc = FastCdc::new(256, 1024, 4096);
+        let data = random_data(200_000, 5);
+        let mut edited = data.clone();
+        edited.splice(50_000..50_000, b"a few new bytes".iter().copied());
+        edited.truncate(190_000);
+
+        let before = ChunkedFile::<SipHasher64>::from_bytes_content_defined(&data, &cdc);
+        let after = ChunkedFile::<SipHasher64>::from_bytes_content_defined(&edited, &cdc);
+        let missing = after.chunks_missing_from(&before);
+        assert!(missing.len() <= 3, "{} of {} chunks changed", missing.len(), after.chunk_count());
+        assert!(before.chunks_missing_from(&before).is_empty());
+
+        let before = ChunkedFile::<SipHasher64>::from_bytes(&data, 1024);
+        let after = ChunkedFile::<SipHasher64>::from_bytes(&edited, 1024);
+        assert!(after.chunks_missing_from(&before).len() > after.chunk_count() / 2);
+    }
+
+    #[test]
+    fn empty_files_have_no_chunks() {
+        let chunked = ChunkedFile::<SipHasher64>::from_reader(io::empty(), DEFAULT_CHUNK_SIZE).unwrap();
+        assert!(chunked.is_empty());
+        assert_eq!(0, chunked.chunk_count());
+        assert_eq!(Err(MerkleError::EmptyTree), chunked.chunk_proof(0));
+        assert_eq!(Err(MerkleError::InvalidRange { start: 0, end: 1, len: 0 }), chunked.byte_range_proof(0, 1));
+    }
+}
@@ -0,0 +1,202 @@
+//! Single proofs without sibling directions.
+//!
+//! Whether each sibling on the path of a leaf is on the left or on the right follows from the
+//! leaf's index and the layout of the tree, so a [`CompactMerkleProof`] only carries the index,
+//! the tree size and the sibling hashes, and the verifier works the directions out itself. Besides
+//! saving the direction of every sibling, this binds the proof to its index: the directions of a
+//! [`MerkleProof`] are whatever the prover sent.
+
+use crate::error::check_index;
+use crate::{
+    try_generate_proof, validate_proof_for_digest, MerkleDigest, MerkleError, MerkleHasher, MerkleProof,
+    SiblingNode, TreeLayout, TreeShape,
+};
+use serde::{Deserialize, Serialize};
+
+/// A proof for the leaf at `leaf_index` of a tree of `tree_size` leaves, made of the sibling
+/// hashes on its path from the leaf up.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct CompactMerkleProof<D> {
+    pub leaf_index: usize,
+    pub tree_size: usize,
+    pub siblings: Vec<D>,
+}
+
+impl<D: MerkleDigest> CompactMerkleProof<D> {
+    /// Drops the directions of `proof`, a proof for the leaf at `leaf_index` of a padded tree of
+    /// `tree_size` leaves.
+    ///
+    /// Fails if the index is beyond the tree or the directions are not the ones it requires.
+    pub fn from_proof(proof: &MerkleProof<D>, leaf_index: usize, tree_size: usize) -> Result<Self, MerkleError> {
+        Self::from_proof_with_shape(proof, leaf_index, tree_size, TreeShape::Padded)
+    }
+
+    /// Same as [`CompactMerkleProof::from_proof`], for a tree of the given shape.
+    pub fn from_proof_with_shape(
+        proof: &MerkleProof<D>,
+        leaf_index: usize,
+        tree_size: usize,
+        shape: TreeShape,
+    ) -> Result<Self, MerkleError> {
+        check_index(leaf_index, tree_size)?;
+        TreeLayout::new(tree_size, shape).check_proof(leaf_index, proof)?;
+        let siblings = proof
+            .iter()
+            .map(|node| match node {
+                SiblingNode::Left(hash) | SiblingNode::Right(hash) => *hash,
+            })
+            .collect();
+        Ok(CompactMerkleProof { leaf_index, tree_size, siblings })
+    }
+
+    /// Restores the directions of the proof, assuming a padded tree.
+    ///
+    /// Fails if the index is beyond the tree or the number of siblings differs from its depth.
+    pub fn to_proof(&self) -> Result<MerkleProof<D>, MerkleError> {
+        self.to_proof_with_shape(TreeShape::Padded)
+    }
+
+    /// Same as [`CompactMerkleProof::to_proof`], for a tree of the given shape.
+    pub fn to_proof_with_shape(&self, shape: TreeShape) -> Result<MerkleProof<D>, MerkleError> {
+        check_index(self.leaf_index, self.tree_size)?;
+        let sides = TreeLayout::new(self.tree_size, shape).sibling_sides(self.leaf_index);
+        if sides.len() != self.siblings.len() {
+            return Err(MerkleError::ProofDepthMismatch { expected: sides.len(), actual: self.siblings.len() });
+        }
+        let proof = sides
+            .into_iter()
+            .zip(&self.siblings)
+            .map(|(left, &hash)| if left { SiblingNode::Left(hash) } else { SiblingNode::Right(hash) })
+            .collect();
+        Ok(proof)
+    }
+}
+
+/// Generates a compact proof that the word at `index` is contained in the sentence. See
+/// [`crate::generate_proof`].
+///
+/// Panics if the index is beyond the length of the sentence.
+pub fn generate_compact_proof<H: MerkleHasher>(
+    sentence: &str,
+    index: usize,
+) -> (H::Digest, CompactMerkleProof<H::Digest>) {
+    try_generate_compact_proof::<H>(sentence, index).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Same as [`generate_compact_proof`], but returns an error instead of panicking.
+pub fn try_generate_compact_proof<H: MerkleHasher>(
+    sentence: &str,
+    index: usize,
+) -> Result<(H::Digest, CompactMerkleProof<H::Digest>), MerkleError> {
+    let (root, proof) = try_generate_proof::<H>(sentence, index)?;
+    let tree_size = sentence.split_whitespace().count();
+    Ok((root, CompactMerkleProof::from_proof(&proof, index, tree_size)?))
+}
+
+/// Checks whether the given word is at the proof's index of a sentence with the given root.
+pub fn validate_compact_proof<H: MerkleHasher>(
+    root: &H::Digest,
+    word: &str,
+    proof: CompactMerkleProof<H::Digest>,
+) -> bool {
+    validate_compact_proof_for_digest::<H>(root, H::hash_leaf(word.as_bytes()), proof)
+}
+
+/// Checks whether the given leaf hash is at the proof's index of a padded tree with the given root.
+pub fn validate_compact_proof_for_digest<H: MerkleHasher>(
+    root: &H::Digest,
+    leaf_hash: H::Digest,
+    proof: CompactMerkleProof<H::Digest>,
+) -> bool {
+    validate_compact_proof_with_shape::<H>(root, leaf_hash, proof, TreeShape::Padded)
+}
+
+/// Same as [`validate_compact_proof_for_digest`], for a tree of the given shape.
+pub fn validate_compact_proof_with_shape<H: MerkleHasher>(
+    root: &H::Digest,
+    leaf_hash: H::Digest,
+    proof: CompactMerkleProof<H::Digest>,
+    shape: TreeShape,
+) -> bool {
+    proof
+        .to_proof_with_shape(shape)
+        .is_ok_and(|proof| validate_proof_for_digest::<H>(root, leaf_hash, proof))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{generate_proof, MerkleTree, SipHasher64};
+
+    const SENTENCE: &str = "Here's an eight word sentence, special for you.";
+
+    #[test]
+    fn conversions_round_trip() {
+        let words: Vec<&str> = SENTENCE.split_whitespace().collect();
+        for index in 0..words.len() {
+            let (root, proof) = generate_proof::<SipHasher64>(SENTENCE, index);
+            let compact = CompactMerkleProof::from_proof(&proof, index, words.len()).unwrap();
+            assert_eq!(proof.len(), compact.siblings.len());
+            assert_eq!(Ok(proof), compact.to_proof());
+            assert_eq!((root, compact.clone()), generate_compact_proof::<SipHasher64>(SENTENCE, index));
+            assert!(validate_compact_proof::<SipHasher64>(&root, words[index], compact));
+        }
+    }
+
+    #[test]
+    fn unbalanced_trees_skip_promoted_nodes() {
+        let leaves = ["a", "b", "c", "d", "e"];
+        let tree = MerkleTree::<SipHasher64>::from_leaves_with_shape(&leaves, TreeShape::Unbalanced);
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = tree.proof(index);
+            let compact = CompactMerkleProof::from_proof_with_shape(&proof, index, 5, TreeShape::Unbalanced).unwrap();
+            assert_eq!(Ok(proof), compact.to_proof_with_shape(TreeShape::Unbalanced));
+            let leaf_hash = SipHasher64::hash_leaf(leaf.as_bytes());
+            assert!(validate_compact_proof_with_shape::<SipHasher64>(
+                &tree.root(),
+                leaf_hash,
+                compact,
+                TreeShape::Unbalanced
+            ));
+        }
+
+        // The last leaf is promoted twice, so in a padded tree it would need two more siblings
+        let compact = tree.compact_proof(4);
+        assert_eq!(1, compact.siblings.len());
+        assert_eq!(Err(MerkleError::ProofDepthMismatch { expected: 3, actual: 1 }), compact.to_proof());
+        let leaf_hash = SipHasher64::hash_leaf(b"e");
+        assert!(!validate_compact_proof_for_digest::<SipHasher64>(&tree.root(), leaf_hash, compact));
+    }
+
+    #[test]
+    fn proofs_are_bound_to_their_index() {
+        let (root, mut proof) = generate_compact_proof::<SipHasher64>(SENTENCE, 1);
+        assert!(validate_compact_proof::<SipHasher64>(&root, "an", proof.clone()));
+        proof.leaf_index = 0;
+        assert!(!validate_compact_proof::<SipHasher64>(&root, "an", proof.clone()));
+        proof.leaf_index = 8;
+        assert!(!validate_compact_proof::<SipHasher64>(&root, "an", proof));
+    }
+
+    #[test]
+    fn mismatched_proofs_are_rejected() {
+        let (_, proof) = generate_proof::<SipHasher64>(SENTENCE, 1);
+        assert_eq!(Err(MerkleError::DirectionMismatch { position: 0 }), CompactMerkleProof::from_proof(&proof, 2, 8));
+        assert_eq!(
+            Err(MerkleError::ProofDepthMismatch { expected: 4, actual: 3 }),
+            CompactMerkleProof::from_proof(&proof, 1, 16)
+        );
+        assert_eq!(
+            Err(MerkleError::IndexOutOfRange { index: 8, len: 8 }),
+            CompactMerkleProof::from_proof(&proof, 8, 8)
+        );
+
+        let compact = CompactMerkleProof { leaf_index: 1, tree_size: 8, siblings: vec![0u64; 2] };
+        assert_eq!(Err(MerkleError::ProofDepthMismatch { expected: 3, actual: 2 }), compact.to_proof());
+        assert_eq!(Err(MerkleError::EmptyTree), CompactMerkleProof::<u64>::from_proof(&vec![], 0, 0));
+        assert_eq!(
+            Err(MerkleError::IndexOutOfRange { index: 8, len: 8 }),
+            try_generate_compact_proof::<SipHasher64>(SENTENCE, 8)
+        );
+    }
+}
@@ -0,0 +1,295 @@
+//! Consistency proofs between two sizes of an append-only tree, following RFC 6962 / RFC 9162.
+//!
+//! A consistency proof shows that the tree of the first `old_size` leaves is a prefix of the tree
+//! of the first `new_size` leaves, i.e. that leaves were only appended and none was rewritten.
+//! They are only defined for [`TreeShape::Unbalanced`] trees, since padding changes as the tree
+//! grows.
+
+use crate::{concatenate_hash_values, MerkleError, MerkleHasher, MerkleTree, TreeShape};
+
+/// A consistency proof is just an alias for the list of subtree hashes it needs.
+pub type ConsistencyProof<D> = Vec<D>;
+
+/// Returns the largest power of two strictly smaller than `n`, for `n > 1`.
+fn split_point(n: usize) -> usize {
+    1 << (usize::BITS - 1 - (n - 1).leading_zeros())
+}
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    /// The root of the tree built from the first `size` leaves only.
+    ///
+    /// Panics if the tree is not [`TreeShape::Unbalanced`] or `size` is beyond the number of
+    /// leaves.
+    pub fn root_at(&self, size: usize) -> H::Digest {
+        self.try_root_at(size).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`MerkleTree::root_at`], but returns an error instead of panicking.
+    pub fn try_root_at(&self, size: usize) -> Result<H::Digest, MerkleError> {
+        self.check_sizes(size, size)?;
+        if size == 0 {
+            return Ok(H::hash_parts(&[]));
+        }
+        Ok(self.subtree_root(0, size))
+    }
+
+    fn check_sizes(&self, old_size: usize, new_size: usize) -> Result<(), MerkleError> {
+        if self.shape() != TreeShape::Unbalanced {
+            return Err(MerkleError::UnbalancedShapeRequired);
+        }
+        if new_size > self.len() {
+            return Err(MerkleError::SizeOutOfRange { size: new_size, len: self.len() });
+        }
+        if old_size > new_size {
+            return Err(MerkleError::SizeOutOfRange { size: old_size, len: new_size });
+        }
+        Ok(())
+    }
+
+    /// The root of the (unbalanced) subtree over the leaves in `start..end`, where `start` is a
+    /// multiple of the largest power of two below `end - start`, as it is for every subtree RFC 6962
+    /// recurses into. Complete subtrees are read from the cached levels, so only O(log n) nodes
+    /// are combined.
+    fn subtree_root(&self, start: usize, end: usize) -> H::Digest {
+        let size = end - start;
+        if size.is_power_of_two() && start.is_multiple_of(size) {
+            let height = size.trailing_zeros() as usize;
+            return self.levels()[height][start >> height];
+        }
+
+        let k = split_point(size);
+        concatenate_hash_values::<H>(self.subtree_root(start, start + k), self.subtree_root(start + k, end))
+    }
+
+    /// Returns the proof that the tree of the first `old_size` leaves is a prefix of the tree of
+    /// the first `new_size` leaves, as defined by `SUBPROOF` in RFC 9162. The proof is empty if
+    /// either size is zero or the sizes are equal.
+    ///
+    /// Panics if the tree is not [`TreeShape::Unbalanced`], `old_size > new_size` or `new_size`
+    /// is beyond the number of leaves.
+    pub fn consistency_proof(&self, old_size: usize, new_size: usize) -> ConsistencyProof<H::Digest> {
+        self.try_consistency_proof(old_size, new_size).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`MerkleTree::consistency_proof`], but returns an error instead of panicking.
+    pub fn try_consistency_proof(
+        &self,
+        old_size: usize,
+        new_size: usize,
+    ) -> Result<ConsistencyProof<H::Digest>, MerkleError> {
+        self.check_sizes(old_size, new_size)?;
+
+        let mut proof = Vec::new();
+        if old_size == 0 || old_size == new_size {
+            return Ok(proof);
+        }
+
+        // Walk down from the root of the new tree, collecting hashes bottom-up afterwards
+        let (mut start, mut end, mut m) = (0, new_size, old_size);
+        let mut complete = true;
+        while m != end - start {
+            let k = split_point(end - start);
+            if m <= k {
+                proof.push(self.subtree_root(start + k, end));
+                end = start + k;
+            } else {
+                proof.push(self.subtree_root(start, start + k));
+                start += k;
+                m -= k;
+                complete = false;
+            }
+        }
+        if !complete {
+            proof.push(self.subtree_root(start, end));
+        }
+
+        proof.reverse();
+        Ok(proof)
+    }
+}
+
+/// Checks that `old_root`, the root of a tree of `old_size` leaves, and `new_root`, the root of a
+/// tree of `new_size` leaves, belong to the same append-only tree, using the verification algorithm
+/// of RFC 9162 section 2.1.4.2.
+pub fn verify_consistency<H: MerkleHasher>(
+    old_root: &H::Digest,
+    new_root: &H::Digest,
+    old_size: usize,
+    new_size: usize,
+    proof: &[H::Digest],
+) -> bool {
+    if old_size > new_size {
+        return false;
+    }
+    if old_size == 0 {
+        return proof.is_empty() && *old_root == H::hash_parts(&[]);
+    }
+    if old_size == new_size {
+        return proof.is_empty() && old_root == new_root;
+    }
+
+    // If the old tree is a complete subtree, its root is the first node of the path
+    let mut path = proof.iter().copied();
+    let first = if old_size.is_power_of_two() {
+        *old_root
+    } else {
+        match path.next() {
+            Some(first) => first,
+            None => return false,
+        }
+    };
+
+    let mut fn_ = old_size - 1;
+    let mut sn = new_size - 1;
+    while fn_ & 1 == 1 {
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+
+    let mut fr = first;
+    let mut sr = first;
+    for c in path {
+        if sn == 0 {
+            return false;
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            fr = concatenate_hash_values::<H>(c, fr);
+            sr = concatenate_hash_values::<H>(c, sr);
+            if fn_ & 1 == 0 {
+                while fn_ & 1 == 0 && fn_ != 0 {
+                    fn_ >>= 1;
+                    sn >>= 1;
+                }
+            }
+        } else {
+            sr = concatenate_hash_values::<H>(sr, c);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+
+    fr == *old_root && sr == *new_root && sn == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        calculate_merkle_root_with_shape, tree::tests::rfc6962_leaves, tree::tests::RFC6962_ROOTS,
+        DomainSeparated, Sha256Hasher, SipHasher64,
+    };
+
+    type Rfc6962Hasher = DomainSeparated<Sha256Hasher>;
+
+    fn decode(hashes: &[&str]) -> Vec<[u8; 32]> {
+        hashes.iter().map(|h| hex::decode(h).unwrap().try_into().unwrap()).collect()
+    }
+
+    #[test]
+    fn rfc6962_consistency_vectors() {
+        let leaves = rfc6962_leaves();
+        let tree = MerkleTree::<Rfc6962Hasher>::from_leaves_with_shape(&leaves, TreeShape::Unbalanced);
+        let vectors: [(usize, usize, Vec<&str>); 4] = [
+            (1, 1, vec![]),
+            (1, 8, vec![
+                "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+            ]),
+            (6, 8, vec![
+                "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+            ]),
+            (2, 5, vec![
+                "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+            ]),
+        ];
+
+        for (old_size, new_size, expected) in vectors {
+            let proof = tree.consistency_proof(old_size, new_size);
+            assert_eq!(decode(&expected), proof);
+
+            let old_root = decode(&[RFC6962_ROOTS[old_size - 1]])[0];
+            let new_root = decode(&[RFC6962_ROOTS[new_size - 1]])[0];
+            assert_eq!(old_root, tree.root_at(old_size));
+            assert!(verify_consistency::<Rfc6962Hasher>(&old_root, &new_root, old_size, new_size, &proof));
+        }
+    }
+
+    #[test]
+    fn all_sizes_are_consistent() {
+        let words: Vec<String> = (0..40).map(|i| format!("word{}", i)).collect();
+        let tree = MerkleTree::<SipHasher64>::from_leaves_with_shape(&words, TreeShape::Unbalanced);
+
+        for new_size in 0..=words.len() {
+            let new_root = tree.root_at(new_size);
+            let leaves: Vec<u64> = tree.leaves()[..new_size].to_vec();
+            assert_eq!(calculate_merkle_root_with_shape::<SipHasher64>(leaves, TreeShape::Unbalanced), new_root);
+
+            for old_size in 0..=new_size {
+                let old_root = tree.root_at(old_size);
+                let proof = tree.consistency_proof(old_size, new_size);
+                assert!(verify_consistency::<SipHasher64>(&old_root, &new_root, old_size, new_size, &proof));
+            }
+        }
+    }
+
+    #[test]
+    fn rewritten_history_is_rejected() {
+        let words: Vec<String> = (0..13).map(|i| format!("word{}", i)).collect();
+        let tree = MerkleTree::<Rfc6962Hasher>::from_leaves_with_shape(&words, TreeShape::Unbalanced);
+        let mut rewritten = tree.clone();
+        rewritten.update_leaf(4, "rewritten");
+
+        for old_size in 1..13 {
+            let old_root = tree.root_at(old_size);
+            let proof = rewritten.consistency_proof(old_size, 13);
+            let consistent = verify_consistency::<Rfc6962Hasher>(&old_root, &rewritten.root(), old_size, 13, &proof);
+            // Only trees that never contained the rewritten leaf are still a prefix
+            assert_eq!(old_size <= 4, consistent);
+        }
+    }
+
+    #[test]
+    fn malformed_proofs_are_rejected() {
+        let words: Vec<String> = (0..11).map(|i| format!("word{}", i)).collect();
+        let tree = MerkleTree::<Rfc6962Hasher>::from_leaves_with_shape(&words, TreeShape::Unbalanced);
+        let (old_root, new_root) = (tree.root_at(6), tree.root());
+        let proof = tree.consistency_proof(6, 11);
+        let verify = |old_size, new_size, proof: &[[u8; 32]]| {
+            verify_consistency::<Rfc6962Hasher>(&old_root, &new_root, old_size, new_size, proof)
+        };
+
+        assert!(verify(6, 11, &proof));
+        assert!(!verify(6, 11, &proof[..proof.len() - 1]));
+        assert!(!verify(6, 11, &[proof.clone(), vec![[0; 32]]].concat()));
+        assert!(!verify(5, 11, &proof));
+        assert!(!verify(6, 8, &proof));
+        assert!(!verify(11, 6, &proof));
+        assert!(!verify(6, 11, &[]));
+
+        let mut tampered = proof.clone();
+        tampered[1][0] ^= 1;
+        assert!(!verify(6, 11, &tampered));
+    }
+
+    #[test]
+    #[should_panic]
+    fn padded_trees_have_no_consistency_proofs() {
+        MerkleTree::<Sha256Hasher>::from_leaves(&["a", "b", "c"]).consistency_proof(1, 3);
+    }
+
+    #[test]
+    fn invalid_sizes_are_errors() {
+        let tree = MerkleTree::<Sha256Hasher>::from_leaves_with_shape(&["a", "b", "c"], TreeShape::Unbalanced);
+        assert_eq!(Err(MerkleError::SizeOutOfRange { size: 4, len: 3 }), tree.try_root_at(4));
+        assert_eq!(Err(MerkleError::SizeOutOfRange { size: 4, len: 3 }), tree.try_consistency_proof(1, 4));
+        assert_eq!(Err(MerkleError::SizeOutOfRange { size: 3, len: 2 }), tree.try_consistency_proof(3, 2));
+        assert_eq!(Ok(tree.root()), tree.try_root_at(3));
+
+        let padded = MerkleTree::<Sha256Hasher>::from_leaves(&["a", "b", "c"]);
+        assert_eq!(Err(MerkleError::UnbalancedShapeRequired), padded.try_consistency_proof(1, 3));
+    }
+}
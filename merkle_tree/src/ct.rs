@@ -0,0 +1,601 @@
+//! Certificate Transparency (RFC 6962 / RFC 9162) proofs and tree heads.
+//!
+//! A CT log is a [`TreeShape::Unbalanced`] tree hashed with [`CtHasher`], so its roots, audit paths
+//! and consistency proofs are the ones [`MerkleTree`] produces for such a tree. This module adds
+//! the inclusion proof verifier of RFC 9162 section 2.1.3.2 and the structures logs publish proofs
+//! and tree heads in: the JSON responses of the RFC 6962 API (`get-sth`, `get-proof-by-hash`,
+//! `get-sth-consistency`) with their TLS-encoded signatures, and the binary `InclusionProofDataV2`,
+//! `ConsistencyProofDataV2` and `SignedTreeHeadDataV2` of RFC 9162.
+//!
+//! Tree head signatures are parsed and the bytes they sign are reconstructed, but checking them
+//! requires the log's public key and signature scheme and is left to the caller.
+
+use crate::encoding::Reader;
+use crate::error::check_index;
+use crate::{
+    concatenate_hash_values, DomainSeparated, MerkleError, MerkleHasher, MerkleTree, Sha256Hasher, TreeShape,
+};
+use serde::{Deserialize, Serialize};
+
+/// The hasher of CT logs: SHA-256 with the `0x00` leaf and `0x01` node prefixes of RFC 6962.
+pub type CtHasher = DomainSeparated<Sha256Hasher>;
+
+/// An audit path: the siblings of a leaf from the bottom up, without directions, which follow
+/// from the leaf index and the tree size.
+pub type InclusionProof<D> = Vec<D>;
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    /// Returns the RFC 9162 audit path of the leaf at `index`, to be checked with
+    /// [`verify_inclusion`].
+    ///
+    /// Panics if the tree is not [`TreeShape::Unbalanced`] or the index is out of range.
+    pub fn inclusion_proof(&self, index: usize) -> InclusionProof<H::Digest> {
+        self.try_inclusion_proof(index).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`MerkleTree::inclusion_proof`], but returns an error instead of panicking.
+    pub fn try_inclusion_proof(&self, index: usize) -> Result<InclusionProof<H::Digest>, MerkleError> {
+        if self.shape() != TreeShape::Unbalanced {
+            return Err(MerkleError::UnbalancedShapeRequired);
+        }
+        Ok(self.try_compact_proof(index)?.siblings)
+    }
+}
+
+/// Checks that `leaf_hash` is the leaf at `leaf_index` of the tree of `tree_size` leaves with the
+/// given root, using the verification algorithm of RFC 9162 section 2.1.3.2.
+pub fn verify_inclusion<H: MerkleHasher>(
+    root: &H::Digest,
+    leaf_index: usize,
+    tree_size: usize,
+    leaf_hash: H::Digest,
+    audit_path: &[H::Digest],
+) -> Result<(), MerkleError> {
+    check_index(leaf_index, tree_size)?;
+    let mut fn_ = leaf_index;
+    let mut sn = tree_size - 1;
+    let mut r = leaf_hash;
+    for &p in audit_path {
+        if sn == 0 {
+            return Err(MerkleError::MalformedProof);
+        }
+        if fn_ & 1 == 1 || fn_ == sn {
+            r = concatenate_hash_values::<H>(p, r);
+            // Skip the levels where the node has no sibling and is promoted
+            while fn_ & 1 == 0 && fn_ != 0 {
+                fn_ >>= 1;
+                sn >>= 1;
+            }
+        } else {
+            r = concatenate_hash_values::<H>(r, p);
+        }
+        fn_ >>= 1;
+        sn >>= 1;
+    }
+
+    if sn != 0 {
+        return Err(MerkleError::MalformedProof);
+    }
+    if r != *root {
+        return Err(MerkleError::RootMismatch);
+    }
+    Ok(())
+}
+
+/// Serializes byte fields of the RFC 6962 JSON API as base64.
+mod base64_field {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        STANDARD.decode(String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+
+    pub fn decode_hash<E: Error>(encoded: &str) -> Result<[u8; 32], E> {
+        let bytes = STANDARD.decode(encoded).map_err(E::custom)?;
+        bytes.try_into().map_err(|bytes: Vec<u8>| E::custom(format!("expected 32 bytes, got {}", bytes.len())))
+    }
+
+    pub mod hash {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(hash: &[u8; 32], serializer: S) -> Result<S::Ok, S::Error> {
+            super::serialize(hash, serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 32], D::Error> {
+            decode_hash(&String::deserialize(deserializer)?)
+        }
+    }
+
+    pub mod hashes {
+        use super::*;
+        use serde::ser::SerializeSeq;
+
+        pub fn serialize<S: Serializer>(hashes: &[[u8; 32]], serializer: S) -> Result<S::Ok, S::Error> {
+            let mut seq = serializer.serialize_seq(Some(hashes.len()))?;
+            for hash in hashes {
+                seq.serialize_element(&STANDARD.encode(hash))?;
+            }
+            seq.end()
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<[u8; 32]>, D::Error> {
+            Vec::<String>::deserialize(deserializer)?.iter().map(|hash| decode_hash(hash)).collect()
+        }
+    }
+}
+
+/// The response of an RFC 6962 log to `get-sth`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignedTreeHead {
+    pub tree_size: u64,
+    /// Milliseconds since the epoch.
+    pub timestamp: u64,
+    #[serde(with = "base64_field::hash")]
+    pub sha256_root_hash: [u8; 32],
+    /// A TLS-encoded `DigitallySigned`, see [`SignedTreeHead::signature`].
+    #[serde(with = "base64_field")]
+    pub tree_head_signature: Vec<u8>,
+}
+
+impl SignedTreeHead {
+    /// The `TreeHeadSignature` structure the log signed: version v1 (0), signature type
+    /// `tree_hash` (1), the timestamp, the tree size and the root.
+    pub fn signed_data(&self) -> Vec<u8> {
+        let mut data = vec![0, 1];
+        data.extend_from_slice(&self.timestamp.to_be_bytes());
+        data.extend_from_slice(&self.tree_size.to_be_bytes());
+        data.extend_from_slice(&self.sha256_root_hash);
+        data
+    }
+
+    /// Parses the signature over [`SignedTreeHead::signed_data`].
+    pub fn signature(&self) -> Result<DigitallySigned, MerkleError> {
+        DigitallySigned::from_bytes(&self.tree_head_signature)
+    }
+}
+
+/// A TLS `DigitallySigned` structure: the hash and signature algorithms of RFC 5246 (e.g. 4 for
+/// SHA-256 and 3 for ECDSA) and the signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DigitallySigned {
+    pub hash_algorithm: u8,
+    pub signature_algorithm: u8,
+    pub signature: Vec<u8>,
+}
+
+impl DigitallySigned {
+    /// Fails if the signature is 2^16 bytes or longer.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MerkleError> {
+        let mut bytes = vec![self.hash_algorithm, self.signature_algorithm];
+        write_vector16(&mut bytes, &self.signature)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MerkleError> {
+        let mut reader = Reader::new(bytes);
+        let hash_algorithm = reader.u8()?;
+        let signature_algorithm = reader.u8()?;
+        let signature = read_vector16(&mut reader)?.to_vec();
+        reader.finish()?;
+        Ok(DigitallySigned { hash_algorithm, signature_algorithm, signature })
+    }
+}
+
+/// The response of an RFC 6962 log to `get-proof-by-hash`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetProofByHashResponse {
+    pub leaf_index: u64,
+    #[serde(with = "base64_field::hashes")]
+    pub audit_path: Vec<[u8; 32]>,
+}
+
+/// The response of an RFC 6962 log to `get-sth-consistency`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GetSthConsistencyResponse {
+    #[serde(with = "base64_field::hashes")]
+    pub consistency: Vec<[u8; 32]>,
+}
+
+/// `InclusionProofDataV2` of RFC 9162: the audit path of the leaf at `leaf_index` of a tree of
+/// `tree_size` leaves in the log identified by `log_id`, the DER-encoded contents of its OID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InclusionProofDataV2 {
+    pub log_id: Vec<u8>,
+    pub tree_size: u64,
+    pub leaf_index: u64,
+    pub inclusion_path: Vec<[u8; 32]>,
+}
+
+impl InclusionProofDataV2 {
+    /// Fails if the log ID is not 2 to 127 bytes long or the path does not fit in 2^16 bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MerkleError> {
+        let mut bytes = Vec::new();
+        write_log_id(&mut bytes, &self.log_id)?;
+        bytes.extend_from_slice(&self.tree_size.to_be_bytes());
+        bytes.extend_from_slice(&self.leaf_index.to_be_bytes());
+        write_path(&mut bytes, &self.inclusion_path)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MerkleError> {
+        let mut reader = Reader::new(bytes);
+        let log_id = read_log_id(&mut reader)?;
+        let tree_size = reader.u64()?;
+        let leaf_index = reader.u64()?;
+        let inclusion_path = read_path(&mut reader)?;
+        reader.finish()?;
+        Ok(InclusionProofDataV2 { log_id, tree_size, leaf_index, inclusion_path })
+    }
+}
+
+/// `ConsistencyProofDataV2` of RFC 9162: the proof that the tree of `tree_size_1` leaves is a
+/// prefix of the tree of `tree_size_2` leaves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsistencyProofDataV2 {
+    pub log_id: Vec<u8>,
+    pub tree_size_1: u64,
+    pub tree_size_2: u64,
+    pub consistency_path: Vec<[u8; 32]>,
+}
+
+impl ConsistencyProofDataV2 {
+    /// Fails if the log ID is not 2 to 127 bytes long or the path does not fit in 2^16 bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MerkleError> {
+        let mut bytes = Vec::new();
+        write_log_id(&mut bytes, &self.log_id)?;
+        bytes.extend_from_slice(&self.tree_size_1.to_be_bytes());
+        bytes.extend_from_slice(&self.tree_size_2.to_be_bytes());
+        write_path(&mut bytes, &self.consistency_path)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MerkleError> {
+        let mut reader = Reader::new(bytes);
+        let log_id = read_log_id(&mut reader)?;
+        let tree_size_1 = reader.u64()?;
+        let tree_size_2 = reader.u64()?;
+        let consistency_path = read_path(&mut reader)?;
+        reader.finish()?;
+        Ok(ConsistencyProofDataV2 { log_id, tree_size_1, tree_size_2, consistency_path })
+    }
+}
+
+/// `TreeHeadDataV2` of RFC 9162, the data a log signs. Extensions are kept in their encoded form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeHeadDataV2 {
+    pub timestamp: u64,
+    pub tree_size: u64,
+    pub root_hash: [u8; 32],
+    pub sth_extensions: Vec<u8>,
+}
+
+impl TreeHeadDataV2 {
+    /// The encoding the signature of a [`SignedTreeHeadDataV2`] is over.
+    ///
+    /// Fails if the extensions are 2^16 bytes or longer.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MerkleError> {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) -> Result<(), MerkleError> {
+        bytes.extend_from_slice(&self.timestamp.to_be_bytes());
+        bytes.extend_from_slice(&self.tree_size.to_be_bytes());
+        write_node_hash(bytes, &self.root_hash);
+        write_vector16(bytes, &self.sth_extensions)
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, MerkleError> {
+        let timestamp = reader.u64()?;
+        let tree_size = reader.u64()?;
+        let root_hash = read_node_hash(reader)?;
+        let sth_extensions = read_vector16(reader)?.to_vec();
+        Ok(TreeHeadDataV2 { timestamp, tree_size, root_hash, sth_extensions })
+    }
+}
+
+/// `SignedTreeHeadDataV2` of RFC 9162: a tree head and the log's signature over it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedTreeHeadDataV2 {
+    pub log_id: Vec<u8>,
+    pub tree_head: TreeHeadDataV2,
+    pub signature: Vec<u8>,
+}
+
+impl SignedTreeHeadDataV2 {
+    /// Fails on the inputs [`SignedTreeHeadDataV2::from_bytes`] rejects: a log ID that is not 2 to
+    /// 127 bytes long, an empty signature, or a field that does not fit in 2^16 bytes.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MerkleError> {
+        if self.signature.is_empty() {
+            return Err(MerkleError::InvalidEncoding);
+        }
+        let mut bytes = Vec::new();
+        write_log_id(&mut bytes, &self.log_id)?;
+        self.tree_head.write(&mut bytes)?;
+        write_vector16(&mut bytes, &self.signature)?;
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MerkleError> {
+        let mut reader = Reader::new(bytes);
+        let log_id = read_log_id(&mut reader)?;
+        let tree_head = TreeHeadDataV2::read(&mut reader)?;
+        let signature = read_vector16(&mut reader)?.to_vec();
+        if signature.is_empty() {
+            return Err(MerkleError::InvalidEncoding);
+        }
+        reader.finish()?;
+        Ok(SignedTreeHeadDataV2 { log_id, tree_head, signature })
+    }
+}
+
+/// Writes a TLS vector with a two-byte length, which holds fewer than 2^16 bytes.
+fn write_vector16(bytes: &mut Vec<u8>, data: &[u8]) -> Result<(), MerkleError> {
+    let len = u16::try_from(data.len()).map_err(|_| MerkleError::InvalidEncoding)?;
+    bytes.extend_from_slice(&len.to_be_bytes());
+    bytes.extend_from_slice(data);
+    Ok(())
+}
+
+fn read_vector16<'a>(reader: &mut Reader<'a>) -> Result<&'a [u8], MerkleError> {
+    let len = reader.u16()? as usize;
+    reader.take(len)
+}
+
+/// `opaque LogID<2..127>`.
+fn write_log_id(bytes: &mut Vec<u8>, log_id: &[u8]) -> Result<(), MerkleError> {
+    if !(2..=127).contains(&log_id.len()) {
+        return Err(MerkleError::InvalidEncoding);
+    }
+    bytes.push(log_id.len() as u8);
+    bytes.extend_from_slice(log_id);
+    Ok(())
+}
+
+fn read_log_id(reader: &mut Reader) -> Result<Vec<u8>, MerkleError> {
+    let len = reader.u8()? as usize;
+    if !(2..=127).contains(&len) {
+        return Err(MerkleError::InvalidEncoding);
+    }
+    Ok(reader.take(len)?.to_vec())
+}
+
+/// `opaque NodeHash<32..2^8-1>`, which is always 32 bytes for SHA-256.
+fn write_node_hash(bytes: &mut Vec<u8>, hash: &[u8; 32]) {
+    bytes.push(32);
+    bytes.extend_from_slice(hash);
+}
+
+fn read_node_hash(reader: &mut Reader) -> Result<[u8; 32], MerkleError> {
+    if reader.u8()? != 32 {
+        return Err(MerkleError::InvalidEncoding);
+    }
+    Ok(reader.take(32)?.try_into().unwrap())
+}
+
+/// `NodeHash path<0..2^16-1>`: the byte length of the encoded hashes, then the hashes.
+fn write_path(bytes: &mut Vec<u8>, path: &[[u8; 32]]) -> Result<(), MerkleError> {
+    let mut encoded = Vec::with_capacity(path.len() * 33);
+    for hash in path {
+        write_node_hash(&mut encoded, hash);
+    }
+    write_vector16(bytes, &encoded)
+}
+
+fn read_path(reader: &mut Reader) -> Result<Vec<[u8; 32]>, MerkleError> {
+    let mut hashes = Reader::new(read_vector16(reader)?);
+    let mut path = Vec::new();
+    while !hashes.is_empty() {
+        path.push(read_node_hash(&mut hashes)?);
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::tests::{rfc6962_leaves, RFC6962_ROOTS};
+    use crate::{verify_consistency, SipHasher64};
+
+    fn decode(hex: &str) -> [u8; 32] {
+        hex::decode(hex).unwrap().try_into().unwrap()
+    }
+
+    fn reference_tree() -> MerkleTree<CtHasher> {
+        MerkleTree::<CtHasher>::from_leaves_with_shape(&rfc6962_leaves(), TreeShape::Unbalanced)
+    }
+
+    /// Responses in the format of an RFC 6962 log's API for the reference tree of the RFC 6962
+    /// test vectors. The signature bytes are a placeholder.
+    const GET_STH: &str = r#"{
+        "tree_size": 8,
+        "timestamp": 1447891200000,
+        "sha256_root_hash": "XcnaeacGWamtVZy3Ad7ZoqudgjqtL0lgz+Nw7/RgQyg=",
+        "tree_head_signature": "BAMACAECAwQFBgcI"
+    }"#;
+    const GET_PROOF_BY_HASH: &str = r#"{
+        "leaf_index": 5,
+        "audit_path": [
+            "vBoGQ7EuTS18d5GPROD095qDi2z57FtcKD4fTYhZnms=",
+            "yoVOoSjtBQtBs1/8G4e46yveRh6eO1WW7Oa51ZdaCuA=",
+            "037kGJdt2VdTwcc4Yrk5j6Kiz5tP8P3+izDNlSCWFLc="
+        ]
+    }"#;
+    const GET_STH_CONSISTENCY: &str = r#"{
+        "consistency": [
+            "DrxdNDf74tsVi58Sah0RjjCBgQMdCpSfje3t68VY72o=",
+            "yoVOoSjtBQtBs1/8G4e46yveRh6eO1WW7Oa51ZdaCuA=",
+            "037kGJdt2VdTwcc4Yrk5j6Kiz5tP8P3+izDNlSCWFLc="
+        ]
+    }"#;
+
+    #[test]
+    fn audit_paths_match_the_reference_tree() {
+        let tree = reference_tree();
+        assert_eq!(
+            vec![
+                decode("96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7"),
+                decode("5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e"),
+                decode("6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4"),
+            ],
+            tree.inclusion_proof(0)
+        );
+        let three = MerkleTree::<CtHasher>::from_leaves_with_shape(&rfc6962_leaves()[..3], TreeShape::Unbalanced);
+        assert_eq!(vec![decode(RFC6962_ROOTS[1])], three.inclusion_proof(2));
+    }
+
+    #[test]
+    fn every_inclusion_proof_verifies() {
+        let words: Vec<String> = (0..40).map(|i| format!("word{}", i)).collect();
+        for size in 1..=words.len() {
+            let tree = MerkleTree::<SipHasher64>::from_leaves_with_shape(&words[..size], TreeShape::Unbalanced);
+            for index in 0..size {
+                let path = tree.inclusion_proof(index);
+                let leaf = tree.leaves()[index];
+                assert_eq!(Ok(()), verify_inclusion::<SipHasher64>(&tree.root(), index, size, leaf, &path));
+                if size > 1 {
+                    let other = (index + 1) % size;
+                    assert_ne!(Ok(()), verify_inclusion::<SipHasher64>(&tree.root(), other, size, leaf, &path));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_inclusion_proofs() {
+        let tree = reference_tree();
+        let root = tree.root();
+        let leaf = CtHasher::hash_leaf(&rfc6962_leaves()[5]);
+        let path = tree.inclusion_proof(5);
+
+        assert_eq!(Err(MerkleError::MalformedProof), verify_inclusion::<CtHasher>(&root, 5, 8, leaf, &path[..2]));
+        let longer = [&path[..], &[[0; 32]]].concat();
+        assert_eq!(Err(MerkleError::MalformedProof), verify_inclusion::<CtHasher>(&root, 5, 8, leaf, &longer));
+        assert_eq!(Err(MerkleError::RootMismatch), verify_inclusion::<CtHasher>(&root, 4, 8, leaf, &path));
+        assert_eq!(
+            Err(MerkleError::IndexOutOfRange { index: 8, len: 8 }),
+            verify_inclusion::<CtHasher>(&root, 8, 8, leaf, &path)
+        );
+
+        let padded = MerkleTree::<CtHasher>::from_leaves(&rfc6962_leaves());
+        assert_eq!(Err(MerkleError::UnbalancedShapeRequired), padded.try_inclusion_proof(0));
+    }
+
+    #[test]
+    fn verifies_log_responses() {
+        let sth: SignedTreeHead = serde_json::from_str(GET_STH).unwrap();
+        assert_eq!(decode(RFC6962_ROOTS[7]), sth.sha256_root_hash);
+        let signature = sth.signature().unwrap();
+        assert_eq!((4, 3), (signature.hash_algorithm, signature.signature_algorithm));
+        assert_eq!((1..=8).collect::<Vec<u8>>(), signature.signature);
+        assert_eq!(Ok(sth.tree_head_signature.clone()), signature.to_bytes());
+        let signed = sth.signed_data();
+        assert_eq!([0, 1], signed[..2]);
+        assert_eq!(1447891200000u64.to_be_bytes(), signed[2..10]);
+        assert_eq!(8u64.to_be_bytes(), signed[10..18]);
+        assert_eq!(sth.sha256_root_hash, signed[18..]);
+
+        let proof: GetProofByHashResponse = serde_json::from_str(GET_PROOF_BY_HASH).unwrap();
+        let leaf = CtHasher::hash_leaf(&rfc6962_leaves()[5]);
+        assert_eq!(
+            Ok(()),
+            verify_inclusion::<CtHasher>(
+                &sth.sha256_root_hash,
+                proof.leaf_index as usize,
+                sth.tree_size as usize,
+                leaf,
+                &proof.audit_path
+            )
+        );
+        assert_eq!(reference_tree().inclusion_proof(5), proof.audit_path);
+
+        let consistency: GetSthConsistencyResponse = serde_json::from_str(GET_STH_CONSISTENCY).unwrap();
+        let old_root = decode(RFC6962_ROOTS[5]);
+        assert!(verify_consistency::<CtHasher>(&old_root, &sth.sha256_root_hash, 6, 8, &consistency.consistency));
+
+        // Responses serialize back to the same JSON
+        let json: serde_json::Value = serde_json::from_str(GET_PROOF_BY_HASH).unwrap();
+        assert_eq!(json, serde_json::to_value(&proof).unwrap());
+        assert!(serde_json::from_str::<GetSthConsistencyResponse>(r#"{"consistency": ["AAAA"]}"#).is_err());
+    }
+
+    #[test]
+    fn v2_structures_round_trip() {
+        let tree = reference_tree();
+        let log_id = vec![0x2b, 0x06, 0x01, 0x04, 0x01];
+        let inclusion =
+            InclusionProofDataV2 { log_id: log_id.clone(), tree_size: 8, leaf_index: 5, inclusion_path: tree.inclusion_proof(5) };
+        let bytes = inclusion.to_bytes().unwrap();
+        // Log ID, the two sizes, then the path's byte length and each hash with its length
+        assert_eq!(&[5, 0x2b, 0x06, 0x01, 0x04, 0x01], &bytes[..6]);
+        assert_eq!(&[0, 0, 0, 0, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 5, 0, 99, 32], &bytes[6..25]);
+        assert_eq!(6 + 16 + 2 + 3 * 33, bytes.len());
+        assert_eq!(Ok(inclusion), InclusionProofDataV2::from_bytes(&bytes));
+
+        let consistency = ConsistencyProofDataV2 {
+            log_id: log_id.clone(),
+            tree_size_1: 6,
+            tree_size_2: 8,
+            consistency_path: tree.consistency_proof(6, 8),
+        };
+        assert_eq!(Ok(consistency.clone()), ConsistencyProofDataV2::from_bytes(&consistency.to_bytes().unwrap()));
+
+        let tree_head =
+            TreeHeadDataV2 { timestamp: 1447891200000, tree_size: 8, root_hash: tree.root(), sth_extensions: vec![] };
+        assert_eq!(8 + 8 + 33 + 2, tree_head.to_bytes().unwrap().len());
+        let sth = SignedTreeHeadDataV2 { log_id, tree_head, signature: vec![1, 2, 3] };
+        assert_eq!(Ok(sth.clone()), SignedTreeHeadDataV2::from_bytes(&sth.to_bytes().unwrap()));
+    }
+
+    #[test]
+    fn malformed_v2_structures() {
+        let inclusion = InclusionProofDataV2 {
+            log_id: vec![1, 2],
+            tree_size: 8,
+            leaf_index: 0,
+            inclusion_path: reference_tree().inclusion_proof(0),
+        };
+        let bytes = inclusion.to_bytes().unwrap();
+        assert_eq!(Err(MerkleError::TruncatedData), InclusionProofDataV2::from_bytes(&bytes[..bytes.len() - 1]));
+        assert_eq!(Err(MerkleError::TrailingData(1)), InclusionProofDataV2::from_bytes(&[&bytes[..], &[0]].concat()));
+        let mut short_hash = bytes.clone();
+        short_hash[21] = 31;
+        assert_eq!(Err(MerkleError::InvalidEncoding), InclusionProofDataV2::from_bytes(&short_hash));
+        let mut short_id = bytes;
+        short_id[0] = 1;
+        assert_eq!(Err(MerkleError::InvalidEncoding), InclusionProofDataV2::from_bytes(&short_id));
+
+        let sth = SignedTreeHeadDataV2 {
+            log_id: vec![1, 2],
+            tree_head: TreeHeadDataV2 { timestamp: 0, tree_size: 0, root_hash: [0; 32], sth_extensions: vec![] },
+            signature: vec![1],
+        };
+        let mut unsigned = sth.to_bytes().unwrap();
+        unsigned.truncate(unsigned.len() - 3);
+        unsigned.extend([0, 0]);
+        assert_eq!(Err(MerkleError::InvalidEncoding), SignedTreeHeadDataV2::from_bytes(&unsigned));
+
+        // Encoding rejects what decoding would
+        assert_eq!(Err(MerkleError::InvalidEncoding), SignedTreeHeadDataV2 { signature: vec![], ..sth.clone() }.to_bytes());
+        for log_id in [vec![1], vec![0; 128]] {
+            assert_eq!(Err(MerkleError::InvalidEncoding), SignedTreeHeadDataV2 { log_id: log_id.clone(), ..sth.clone() }.to_bytes());
+            assert_eq!(Err(MerkleError::InvalidEncoding), InclusionProofDataV2 { log_id, ..inclusion.clone() }.to_bytes());
+        }
+        let long_path = ConsistencyProofDataV2 {
+            log_id: vec![1, 2],
+            tree_size_1: 1,
+            tree_size_2: 2,
+            consistency_path: vec![[0; 32]; 65535 / 33 + 1],
+        };
+        assert_eq!(Err(MerkleError::InvalidEncoding), long_path.to_bytes());
+        let signature = DigitallySigned { hash_algorithm: 4, signature_algorithm: 3, signature: vec![0; 1 << 16] };
+        assert_eq!(Err(MerkleError::InvalidEncoding), signature.to_bytes());
+    }
+}
@@ -0,0 +1,462 @@
+//! A versioned binary encoding for proofs.
+//!
+//! Every encoded proof starts with the same header, followed by length-prefixed fields. All
+//! integers are big-endian and digests are written as [`MerkleDigest::to_bytes`] returns them.
+//!
+//! ```text
+//! u8   format version (PROOF_FORMAT_VERSION)
+//! u8   proof kind: 0 for a single proof, 1 for a compact multiproof, 2 for a compact proof,
+//!      3 for a k-ary proof, 4 for a k-ary multiproof, 5 for a compact k-ary proof
+//! u8   hash algorithm id (MerkleHasher::ALGORITHM_ID)
+//! u64  tree size
+//!
+//! single proof:        u32 sibling count, then per sibling u8 side (0 left, 1 right) and digest
+//! compact multiproof:  u32 index count, then u64 per index; u32 hash count, then the digests
+//! compact proof:       u64 leaf index; u32 sibling count, then the digests
+//! k-ary proof:         u8 arity; u32 level count, then per level u8 position and k - 1 digests
+//! k-ary multiproof:    u8 arity, then as a compact multiproof
+//! compact k-ary proof: u8 arity, then as a compact proof
+//! ```
+//!
+//! The k-ary kinds carry the arity of their tree, so their bytes have one meaning and do not depend
+//! on what the decoder assumes.
+//!
+//! Decoding is strict: the header must match the expected kind and hasher, and data that ends
+//! early or continues after the last field is rejected.
+
+use crate::{
+    Arity, CompactKaryProof, CompactMerkleMultiProof, CompactMerkleProof, KaryProof, KarySiblings, MerkleDigest, MerkleError, MerkleHasher,
+    MerkleProof, SiblingNode,
+};
+
+/// The version of the binary proof encoding written by this crate.
+pub const PROOF_FORMAT_VERSION: u8 = 1;
+
+const SINGLE_PROOF: u8 = 0;
+const COMPACT_MULTIPROOF: u8 = 1;
+const COMPACT_PROOF: u8 = 2;
+const KARY_PROOF: u8 = 3;
+const KARY_MULTIPROOF: u8 = 4;
+const COMPACT_KARY_PROOF: u8 = 5;
+
+const LEFT: u8 = 0;
+const RIGHT: u8 = 1;
+
+fn write_header<H: MerkleHasher>(out: &mut Vec<u8>, kind: u8, tree_size: usize) {
+    out.extend_from_slice(&[PROOF_FORMAT_VERSION, kind, H::ALGORITHM_ID]);
+    out.extend_from_slice(&(tree_size as u64).to_be_bytes());
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    let len = u32::try_from(len).expect("Proofs have fewer than 2^32 entries");
+    out.extend_from_slice(&len.to_be_bytes());
+}
+
+/// Reads the fields of an encoded proof from the front of the data.
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8], MerkleError> {
+        if self.bytes.len() < len {
+            return Err(MerkleError::TruncatedData);
+        }
+        let (field, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(field)
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, MerkleError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u16(&mut self) -> Result<u16, MerkleError> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, MerkleError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn usize(&mut self) -> Result<usize, MerkleError> {
+        usize::try_from(self.u64()?).map_err(|_| MerkleError::InvalidEncoding)
+    }
+
+    /// Reads a count of entries that take at least `entry_len` bytes each, rejecting counts the
+    /// rest of the data cannot hold before anything is allocated for them.
+    fn len(&mut self, entry_len: usize) -> Result<usize, MerkleError> {
+        let len = u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as usize;
+        if len.saturating_mul(entry_len) > self.bytes.len() {
+            return Err(MerkleError::TruncatedData);
+        }
+        Ok(len)
+    }
+
+    fn arity(&mut self) -> Result<Arity, MerkleError> {
+        Arity::try_from(self.u8()? as usize)
+    }
+
+    fn digest<D: MerkleDigest>(&mut self) -> Result<D, MerkleError> {
+        D::from_bytes(self.take(D::LEN)?).ok_or(MerkleError::InvalidEncoding)
+    }
+
+    /// Checks the header and returns the tree size.
+    fn header<H: MerkleHasher>(&mut self, kind: u8) -> Result<usize, MerkleError> {
+        let version = self.u8()?;
+        if version != PROOF_FORMAT_VERSION {
+            return Err(MerkleError::UnsupportedVersion(version));
+        }
+        let actual_kind = self.u8()?;
+        if actual_kind != kind {
+            return Err(MerkleError::UnexpectedProofKind(actual_kind));
+        }
+        let algorithm = self.u8()?;
+        if algorithm != H::ALGORITHM_ID {
+            return Err(MerkleError::HashAlgorithmMismatch { expected: H::ALGORITHM_ID, actual: algorithm });
+        }
+        self.usize()
+    }
+
+    pub(crate) fn finish(self) -> Result<(), MerkleError> {
+        match self.bytes.len() {
+            0 => Ok(()),
+            len => Err(MerkleError::TrailingData(len)),
+        }
+    }
+}
+
+/// Encodes a single proof for a leaf of a tree of `tree_size` leaves.
+pub fn proof_to_bytes<H: MerkleHasher>(proof: &MerkleProof<H::Digest>, tree_size: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(15 + proof.len() * (1 + H::Digest::LEN));
+    write_header::<H>(&mut out, SINGLE_PROOF, tree_size);
+    write_len(&mut out, proof.len());
+    for node in proof {
+        let (side, hash) = match node {
+            SiblingNode::Left(hash) => (LEFT, hash),
+            SiblingNode::Right(hash) => (RIGHT, hash),
+        };
+        out.push(side);
+        out.extend_from_slice(hash.to_bytes().as_ref());
+    }
+    out
+}
+
+/// Decodes a single proof encoded by [`proof_to_bytes`] and returns it with its tree size.
+pub fn proof_from_bytes<H: MerkleHasher>(bytes: &[u8]) -> Result<(MerkleProof<H::Digest>, usize), MerkleError> {
+    let mut reader = Reader::new(bytes);
+    let tree_size = reader.header::<H>(SINGLE_PROOF)?;
+    let len = reader.len(1 + H::Digest::LEN)?;
+    let mut proof = Vec::with_capacity(len);
+    for _ in 0..len {
+        let node = match reader.u8()? {
+            LEFT => SiblingNode::Left(reader.digest()?),
+            RIGHT => SiblingNode::Right(reader.digest()?),
+            _ => return Err(MerkleError::InvalidEncoding),
+        };
+        proof.push(node);
+    }
+    reader.finish()?;
+    Ok((proof, tree_size))
+}
+
+/// Encodes a proof for a leaf of a k-ary tree of `tree_size` leaves and the given arity.
+///
+/// Fails if a level does not have `k - 1` siblings or a position beyond `k - 1`.
+pub fn kary_proof_to_bytes<H: MerkleHasher>(
+    proof: &KaryProof<H::Digest>,
+    tree_size: usize,
+    arity: Arity,
+) -> Result<Vec<u8>, MerkleError> {
+    let k = arity.get();
+    let mut out = Vec::with_capacity(16 + proof.len() * (1 + (k - 1) * H::Digest::LEN));
+    write_header::<H>(&mut out, KARY_PROOF, tree_size);
+    out.push(k as u8);
+    write_len(&mut out, proof.len());
+    for level in proof {
+        if level.siblings.len() != k - 1 || level.position >= k {
+            return Err(MerkleError::MalformedProof);
+        }
+        out.push(level.position as u8);
+        for hash in &level.siblings {
+            out.extend_from_slice(hash.to_bytes().as_ref());
+        }
+    }
+    Ok(out)
+}
+
+/// Decodes a proof encoded by [`kary_proof_to_bytes`] and returns it with its tree size and arity.
+pub fn kary_proof_from_bytes<H: MerkleHasher>(
+    bytes: &[u8],
+) -> Result<(KaryProof<H::Digest>, usize, Arity), MerkleError> {
+    let mut reader = Reader::new(bytes);
+    let tree_size = reader.header::<H>(KARY_PROOF)?;
+    let arity = reader.arity()?;
+    let k = arity.get();
+    let len = reader.len(1 + (k - 1) * H::Digest::LEN)?;
+    let mut proof = Vec::with_capacity(len);
+    for _ in 0..len {
+        let position = reader.u8()? as usize;
+        if position >= k {
+            return Err(MerkleError::InvalidEncoding);
+        }
+        let siblings = (0..k - 1).map(|_| reader.digest()).collect::<Result<_, _>>()?;
+        proof.push(KarySiblings { position, siblings });
+    }
+    reader.finish()?;
+    Ok((proof, tree_size, arity))
+}
+
+impl<D: MerkleDigest> CompactMerkleMultiProof<D> {
+    /// Encodes the multiproof, which was made with the hasher `H`.
+    pub fn to_bytes<H: MerkleHasher<Digest = D>>(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(19 + self.leaf_indices.len() * 8 + self.hashes.len() * D::LEN);
+        write_header::<H>(&mut out, COMPACT_MULTIPROOF, self.tree_size);
+        self.write_fields(&mut out);
+        out
+    }
+
+    /// Decodes a multiproof encoded by [`CompactMerkleMultiProof::to_bytes`] with the hasher `H`.
+    pub fn from_bytes<H: MerkleHasher<Digest = D>>(bytes: &[u8]) -> Result<Self, MerkleError> {
+        let mut reader = Reader::new(bytes);
+        let tree_size = reader.header::<H>(COMPACT_MULTIPROOF)?;
+        let proof = Self::read_fields(&mut reader, tree_size)?;
+        reader.finish()?;
+        Ok(proof)
+    }
+
+    fn write_fields(&self, out: &mut Vec<u8>) {
+        write_len(out, self.leaf_indices.len());
+        for &index in &self.leaf_indices {
+            out.extend_from_slice(&(index as u64).to_be_bytes());
+        }
+        write_len(out, self.hashes.len());
+        for hash in &self.hashes {
+            out.extend_from_slice(hash.to_bytes().as_ref());
+        }
+    }
+
+    fn read_fields(reader: &mut Reader, tree_size: usize) -> Result<Self, MerkleError> {
+        let leaf_indices = (0..reader.len(8)?).map(|_| reader.usize()).collect::<Result<_, _>>()?;
+        let hashes = (0..reader.len(D::LEN)?).map(|_| reader.digest()).collect::<Result<_, _>>()?;
+        Ok(CompactMerkleMultiProof { leaf_indices, tree_size, hashes })
+    }
+}
+
+/// Encodes a multiproof of a k-ary tree of the given arity, as made by
+/// [`KaryMerkleTree::multiproof`](crate::KaryMerkleTree::multiproof).
+pub fn kary_multiproof_to_bytes<H: MerkleHasher>(proof: &CompactMerkleMultiProof<H::Digest>, arity: Arity) -> Vec<u8> {
+    let mut out = Vec::with_capacity(20 + proof.leaf_indices.len() * 8 + proof.hashes.len() * H::Digest::LEN);
+    write_header::<H>(&mut out, KARY_MULTIPROOF, proof.tree_size);
+    out.push(arity.get() as u8);
+    proof.write_fields(&mut out);
+    out
+}
+
+/// Decodes a multiproof encoded by [`kary_multiproof_to_bytes`] and returns it with the arity of
+/// its tree.
+pub fn kary_multiproof_from_bytes<H: MerkleHasher>(
+    bytes: &[u8],
+) -> Result<(CompactMerkleMultiProof<H::Digest>, Arity), MerkleError> {
+    let mut reader = Reader::new(bytes);
+    let tree_size = reader.header::<H>(KARY_MULTIPROOF)?;
+    let arity = reader.arity()?;
+    let proof = CompactMerkleMultiProof::read_fields(&mut reader, tree_size)?;
+    reader.finish()?;
+    Ok((proof, arity))
+}
+
+impl<D: MerkleDigest> CompactKaryProof<D> {
+    /// Encodes the proof, which was made with the hasher `H`.
+    pub fn to_bytes<H: MerkleHasher<Digest = D>>(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(24 + self.siblings.len() * D::LEN);
+        write_header::<H>(&mut out, COMPACT_KARY_PROOF, self.tree_size);
+        out.push(self.arity.get() as u8);
+        out.extend_from_slice(&(self.leaf_index as u64).to_be_bytes());
+        write_len(&mut out, self.siblings.len());
+        for hash in &self.siblings {
+            out.extend_from_slice(hash.to_bytes().as_ref());
+        }
+        out
+    }
+
+    /// Decodes a proof encoded by [`CompactKaryProof::to_bytes`] with the hasher `H`.
+    pub fn from_bytes<H: MerkleHasher<Digest = D>>(bytes: &[u8]) -> Result<Self, MerkleError> {
+        let mut reader = Reader::new(bytes);
+        let tree_size = reader.header::<H>(COMPACT_KARY_PROOF)?;
+        let arity = reader.arity()?;
+        let leaf_index = reader.usize()?;
+        let siblings = (0..reader.len(D::LEN)?).map(|_| reader.digest()).collect::<Result<_, _>>()?;
+        reader.finish()?;
+        Ok(CompactKaryProof { arity, leaf_index, tree_size, siblings })
+    }
+}
+
+impl<D: MerkleDigest> CompactMerkleProof<D> {
+    /// Encodes the proof, which was made with the hasher `H`.
+    pub fn to_bytes<H: MerkleHasher<Digest = D>>(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(23 + self.siblings.len() * D::LEN);
+        write_header::<H>(&mut out, COMPACT_PROOF, self.tree_size);
+        out.extend_from_slice(&(self.leaf_index as u64).to_be_bytes());
+        write_len(&mut out, self.siblings.len());
+        for hash in &self.siblings {
+            out.extend_from_slice(hash.to_bytes().as_ref());
+        }
+        out
+    }
+
+    /// Decodes a proof encoded by [`CompactMerkleProof::to_bytes`] with the hasher `H`.
+    pub fn from_bytes<H: MerkleHasher<Digest = D>>(bytes: &[u8]) -> Result<Self, MerkleError> {
+        let mut reader = Reader::new(bytes);
+        let tree_size = reader.header::<H>(COMPACT_PROOF)?;
+        let leaf_index = reader.usize()?;
+        let siblings = (0..reader.len(D::LEN)?).map(|_| reader.digest()).collect::<Result<_, _>>()?;
+        reader.finish()?;
+        Ok(CompactMerkleProof { leaf_index, tree_size, siblings })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generate_compact_multiproof, generate_compact_proof, generate_proof, validate_compact_proof, validate_compact_multiproof, validate_proof,
+        DomainSeparated, Sha256Hasher, SipHasher64,
+    };
+
+    type Hasher = DomainSeparated<Sha256Hasher>;
+
+    const SENTENCE: &str = "Here's an eight word sentence, special for you.";
+
+    #[test]
+    fn single_proof_layout() {
+        let proof = vec![SiblingNode::Left(0x0102030405060708u64), SiblingNode::Right(0xff)];
+        let bytes = proof_to_bytes::<SipHasher64>(&proof, 3);
+        let expected = [
+            vec![PROOF_FORMAT_VERSION, 0, 1],
+            vec![0, 0, 0, 0, 0, 0, 0, 3],
+            vec![0, 0, 0, 2],
+            vec![0, 8, 7, 6, 5, 4, 3, 2, 1],
+            vec![1, 0xff, 0, 0, 0, 0, 0, 0, 0],
+        ]
+        .concat();
+        assert_eq!(expected, bytes);
+        assert_eq!(Ok((proof, 3)), proof_from_bytes::<SipHasher64>(&bytes));
+    }
+
+    #[test]
+    fn multiproof_layout() {
+        let proof = CompactMerkleMultiProof { leaf_indices: vec![6, 0], tree_size: 7, hashes: vec![[0xab; 32]] };
+        let bytes = proof.to_bytes::<Hasher>();
+        let expected = [
+            vec![PROOF_FORMAT_VERSION, 1, 0x82],
+            vec![0, 0, 0, 0, 0, 0, 0, 7],
+            vec![0, 0, 0, 2],
+            vec![0, 0, 0, 0, 0, 0, 0, 6],
+            vec![0, 0, 0, 0, 0, 0, 0, 0],
+            vec![0, 0, 0, 1],
+            vec![0xab; 32],
+        ]
+        .concat();
+        assert_eq!(expected, bytes);
+        assert_eq!(Ok(proof), CompactMerkleMultiProof::from_bytes::<Hasher>(&bytes));
+    }
+
+    #[test]
+    fn compact_proof_layout() {
+        let proof = CompactMerkleProof { leaf_index: 2, tree_size: 3, siblings: vec![0x0102030405060708u64, 0xff] };
+        let bytes = proof.to_bytes::<SipHasher64>();
+        let expected = [
+            vec![PROOF_FORMAT_VERSION, 2, 1],
+            vec![0, 0, 0, 0, 0, 0, 0, 3],
+            vec![0, 0, 0, 0, 0, 0, 0, 2],
+            vec![0, 0, 0, 2],
+            vec![8, 7, 6, 5, 4, 3, 2, 1],
+            vec![0xff, 0, 0, 0, 0, 0, 0, 0],
+        ]
+        .concat();
+        assert_eq!(expected, bytes);
+        assert_eq!(Ok(proof), CompactMerkleProof::from_bytes::<SipHasher64>(&bytes));
+
+        for len in 0..bytes.len() {
+            assert_eq!(Err(MerkleError::TruncatedData), CompactMerkleProof::<u64>::from_bytes::<SipHasher64>(&bytes[..len]));
+        }
+        assert_eq!(Err(MerkleError::UnexpectedProofKind(2)), proof_from_bytes::<SipHasher64>(&bytes));
+    }
+
+    #[test]
+    fn decoded_proofs_still_validate() {
+        let (root, proof) = generate_proof::<Hasher>(SENTENCE, 5);
+        let (decoded, tree_size) = proof_from_bytes::<Hasher>(&proof_to_bytes::<Hasher>(&proof, 8)).unwrap();
+        assert_eq!(8, tree_size);
+        assert!(validate_proof::<Hasher>(&root, "special", decoded));
+
+        let (root, multiproof) = generate_compact_multiproof::<Hasher>(SENTENCE, vec![0, 1, 6]);
+        let decoded = CompactMerkleMultiProof::from_bytes::<Hasher>(&multiproof.to_bytes::<Hasher>()).unwrap();
+        assert!(validate_compact_multiproof::<Hasher>(&root, vec!["Here's", "an", "for"], decoded));
+
+        let (root, proof) = generate_compact_proof::<Hasher>(SENTENCE, 5);
+        let decoded = CompactMerkleProof::from_bytes::<Hasher>(&proof.to_bytes::<Hasher>()).unwrap();
+        assert!(validate_compact_proof::<Hasher>(&root, "special", decoded));
+    }
+
+    #[test]
+    fn truncated_and_trailing_data_is_rejected() {
+        let (_, proof) = generate_proof::<Hasher>(SENTENCE, 2);
+        let bytes = proof_to_bytes::<Hasher>(&proof, 8);
+        for len in 0..bytes.len() {
+            assert_eq!(Err(MerkleError::TruncatedData), proof_from_bytes::<Hasher>(&bytes[..len]));
+        }
+        let trailing = [bytes.as_slice(), &[0, 0]].concat();
+        assert_eq!(Err(MerkleError::TrailingData(2)), proof_from_bytes::<Hasher>(&trailing));
+
+        let (_, multiproof) = generate_compact_multiproof::<Hasher>(SENTENCE, vec![3, 4]);
+        let bytes = multiproof.to_bytes::<Hasher>();
+        for len in 0..bytes.len() {
+            assert_eq!(Err(MerkleError::TruncatedData), CompactMerkleMultiProof::from_bytes::<Hasher>(&bytes[..len]));
+        }
+        let trailing = [bytes.as_slice(), &[0]].concat();
+        assert_eq!(Err(MerkleError::TrailingData(1)), CompactMerkleMultiProof::from_bytes::<Hasher>(&trailing));
+    }
+
+    #[test]
+    fn mismatched_headers_are_rejected() {
+        let (_, proof) = generate_proof::<Hasher>(SENTENCE, 2);
+        let bytes = proof_to_bytes::<Hasher>(&proof, 8);
+
+        let mut version = bytes.clone();
+        version[0] = 2;
+        assert_eq!(Err(MerkleError::UnsupportedVersion(2)), proof_from_bytes::<Hasher>(&version));
+
+        // The plain hash function has the same digest size but a different id
+        assert_eq!(
+            Err(MerkleError::HashAlgorithmMismatch { expected: 2, actual: 0x82 }),
+            proof_from_bytes::<Sha256Hasher>(&bytes)
+        );
+        assert_eq!(Err(MerkleError::UnexpectedProofKind(0)), CompactMerkleMultiProof::from_bytes::<Hasher>(&bytes));
+
+        let mut side = bytes.clone();
+        side[15] = 2;
+        assert_eq!(Err(MerkleError::InvalidEncoding), proof_from_bytes::<Hasher>(&side));
+
+        // A huge count is rejected without allocating for it
+        let mut count = bytes;
+        count[11..15].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(Err(MerkleError::TruncatedData), proof_from_bytes::<Hasher>(&count));
+    }
+
+    #[test]
+    fn multiproof_serde_round_trip() {
+        let (_, multiproof) = generate_compact_multiproof::<SipHasher64>(SENTENCE, vec![0, 1, 6]);
+        let json = serde_json::to_string(&multiproof).unwrap();
+        assert_eq!(multiproof, serde_json::from_str(&json).unwrap());
+    }
+}
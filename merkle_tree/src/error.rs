@@ -0,0 +1,143 @@
+//! The error type of the fallible `try_` functions and of proof decoding.
+//!
+//! Every function that panics on bad input, like [`crate::generate_proof`] with an index beyond
+//! the sentence, has a `try_` counterpart that returns a [`MerkleError`] instead. The panicking
+//! functions are thin wrappers that panic with the error's message.
+
+use std::fmt;
+
+/// Why a proof could not be generated or checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MerkleError {
+    /// A leaf index is not smaller than the number of leaves.
+    IndexOutOfRange { index: usize, len: usize },
+    /// A leaf index was requested or proven more than once.
+    DuplicateIndex(usize),
+    /// The tree has no leaves, so there is nothing to prove.
+    EmptyTree,
+    /// A multiproof was requested or given for no leaves at all.
+    NoIndices,
+    /// The proof does not have the structure its tree requires, e.g. it has too few or too many
+    /// hashes.
+    MalformedProof,
+    /// The number of leaves given to a verifier differs from the number of indices in the proof.
+    ProofLengthMismatch { expected: usize, actual: usize },
+    /// A tree size is larger than the tree, or than the size it is compared to.
+    SizeOutOfRange { size: usize, len: usize },
+    /// The operation is only defined for [`crate::TreeShape::Unbalanced`] trees.
+    UnbalancedShapeRequired,
+    /// A proof has another number of siblings than the path of its leaf in the tree.
+    ProofDepthMismatch { expected: usize, actual: usize },
+    /// The sibling at `position` of a proof, counted from the leaf, is on the wrong side for the
+    /// leaf's index.
+    DirectionMismatch { position: usize },
+    /// The proof is well-formed for its leaf but leads to another root.
+    RootMismatch,
+    /// Two identical sibling subtrees were paired, as in the CVE-2012-2459 mutation of Bitcoin
+    /// blocks, so the tree has the root of another list of leaves.
+    MutatedTree,
+    /// The values of a Merkle sum tree, or of the leaves and siblings of a sum proof, add up to
+    /// more than `u64::MAX`.
+    SumOverflow,
+    /// The data given to a verifier has another length than the blocks it is checked against.
+    DataLengthMismatch { expected: usize, actual: usize },
+    /// A k-ary tree was requested with another number of children than 2, 4, 8 or 16.
+    UnsupportedArity(usize),
+    /// A range is empty or extends beyond `len`.
+    InvalidRange { start: usize, end: usize, len: usize },
+    /// An encoded proof has a format version this crate cannot read.
+    UnsupportedVersion(u8),
+    /// An encoded proof is of another kind than the one being decoded, e.g. a multiproof.
+    UnexpectedProofKind(u8),
+    /// An encoded proof was made with another hash function.
+    HashAlgorithmMismatch { expected: u8, actual: u8 },
+    /// An encoded proof ends before all of its fields were read.
+    TruncatedData,
+    /// An encoded proof is followed by this many unexpected bytes.
+    TrailingData(usize),
+    /// An encoded proof contains a value that cannot occur, such as an unknown sibling direction.
+    InvalidEncoding,
+}
+
+impl fmt::Display for MerkleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MerkleError::IndexOutOfRange { index, len } => {
+                write!(f, "Index {} is out of bounds for {} leaves", index, len)
+            }
+            MerkleError::DuplicateIndex(index) => write!(f, "Index {} is duplicated", index),
+            MerkleError::EmptyTree => write!(f, "The tree has no leaves"),
+            MerkleError::NoIndices => write!(f, "No leaf indices were given"),
+            MerkleError::MalformedProof => write!(f, "The proof is malformed"),
+            MerkleError::ProofLengthMismatch { expected, actual } => {
+                write!(f, "Expected {} leaves for the proof but got {}", expected, actual)
+            }
+            MerkleError::SizeOutOfRange { size, len } => {
+                write!(f, "Size {} is out of bounds for {} leaves", size, len)
+            }
+            MerkleError::UnbalancedShapeRequired => write!(f, "The tree must be unbalanced"),
+            MerkleError::ProofDepthMismatch { expected, actual } => {
+                write!(f, "Expected {} siblings in the proof but got {}", expected, actual)
+            }
+            MerkleError::DirectionMismatch { position } => {
+                write!(f, "Sibling {} of the proof is on the wrong side for the leaf's index", position)
+            }
+            MerkleError::RootMismatch => write!(f, "The proof does not lead to the root"),
+            MerkleError::MutatedTree => write!(f, "The tree pairs two identical subtrees"),
+            MerkleError::SumOverflow => write!(f, "The sums of the tree overflow"),
+            MerkleError::DataLengthMismatch { expected, actual } => {
+                write!(f, "Expected {} bytes of data but got {}", expected, actual)
+            }
+            MerkleError::UnsupportedArity(arity) => {
+                write!(f, "Arity {} is not one of 2, 4, 8 or 16", arity)
+            }
+            MerkleError::InvalidRange { start, end, len } => {
+                write!(f, "Range {}..{} is empty or out of bounds for length {}", start, end, len)
+            }
+            MerkleError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported proof format version {}", version)
+            }
+            MerkleError::UnexpectedProofKind(kind) => write!(f, "Unexpected proof kind {}", kind),
+            MerkleError::HashAlgorithmMismatch { expected, actual } => write!(
+                f,
+                "The proof was made with hash algorithm {} instead of {}",
+                actual, expected
+            ),
+            MerkleError::TruncatedData => write!(f, "The encoded proof is truncated"),
+            MerkleError::TrailingData(len) => {
+                write!(f, "The encoded proof is followed by {} trailing bytes", len)
+            }
+            MerkleError::InvalidEncoding => write!(f, "The encoded proof is invalid"),
+        }
+    }
+}
+
+impl std::error::Error for MerkleError {}
+
+/// Checks that `index` is a leaf of a tree with `len` leaves.
+pub(crate) fn check_index(index: usize, len: usize) -> Result<(), MerkleError> {
+    if len == 0 {
+        return Err(MerkleError::EmptyTree);
+    }
+    if index >= len {
+        return Err(MerkleError::IndexOutOfRange { index, len });
+    }
+    Ok(())
+}
+
+/// Checks that `indices` are distinct leaves of a tree with `len` leaves and returns them sorted.
+pub(crate) fn check_indices(indices: &[usize], len: usize) -> Result<Vec<usize>, MerkleError> {
+    if len == 0 {
+        return Err(MerkleError::EmptyTree);
+    }
+    let mut sorted = indices.to_vec();
+    sorted.sort_unstable();
+    if let Some(pair) = sorted.windows(2).find(|pair| pair[0] == pair[1]) {
+        return Err(MerkleError::DuplicateIndex(pair[0]));
+    }
+    match sorted.last() {
+        None => Err(MerkleError::NoIndices),
+        Some(&max) if max >= len => Err(MerkleError::IndexOutOfRange { index: max, len }),
+        Some(_) => Ok(sorted),
+    }
+}
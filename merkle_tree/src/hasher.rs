@@ -0,0 +1,381 @@
+//! Hash backends the Merkle tree functions can be instantiated with.
+//!
+//! Every tree function is generic over a [`MerkleHasher`], which decides how leaves and interior
+//! nodes are hashed and what the resulting digest looks like. [`SipHasher64`] reproduces the
+//! original `u64` hashing of this crate and is kept for compatibility; the other backends are
+//! cryptographic and should be used whenever the root is relied upon for integrity.
+//!
+//! None of the backends distinguish leaves from interior nodes on their own. Wrap them in
+//! [`DomainSeparated`] to get the RFC 6962 scheme, which prevents interior nodes from being passed
+//! off as leaves.
+
+use sha2::Digest;
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt::Debug,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+};
+
+/// A digest produced by a [`MerkleHasher`].
+pub trait MerkleDigest: Copy + Eq + Hash + Debug + Default + Send + Sync {
+    /// The fixed-size byte representation of the digest.
+    type Bytes: AsRef<[u8]>;
+
+    /// The length of [`MerkleDigest::Bytes`].
+    const LEN: usize;
+
+    /// Returns the bytes of the digest, as they are fed into the parent node's hash.
+    fn to_bytes(&self) -> Self::Bytes;
+
+    /// Reads a digest back from the bytes returned by [`MerkleDigest::to_bytes`], or returns
+    /// `None` if there are not exactly [`MerkleDigest::LEN`] of them.
+    fn from_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+impl MerkleDigest for u64 {
+    type Bytes = [u8; 8];
+    const LEN: usize = 8;
+
+    fn to_bytes(&self) -> [u8; 8] {
+        self.to_le_bytes()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<u64> {
+        Some(u64::from_le_bytes(bytes.try_into().ok()?))
+    }
+}
+
+impl MerkleDigest for [u8; 32] {
+    type Bytes = [u8; 32];
+    const LEN: usize = 32;
+
+    fn to_bytes(&self) -> [u8; 32] {
+        *self
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<[u8; 32]> {
+        bytes.try_into().ok()
+    }
+}
+
+/// A hash function used to build Merkle trees.
+///
+/// Implementors only have to provide [`MerkleHasher::hash_parts`]; leaves are hashed as their raw
+/// bytes and interior nodes as the concatenation of their children's digests unless overridden.
+pub trait MerkleHasher {
+    /// The type of a node in the tree.
+    type Digest: MerkleDigest;
+
+    /// Identifies the hasher in binary proof encodings, so that a proof is not checked with a
+    /// different hash function than it was made with. Hashers without an assigned id use 0.
+    const ALGORITHM_ID: u8 = 0;
+
+    /// Hashes the concatenation of `parts`.
+    fn hash_parts(parts: &[&[u8]]) -> Self::Digest;
+
+    /// Hashes a data block into a leaf node.
+    fn hash_leaf(data: &[u8]) -> Self::Digest {
+        Self::hash_parts(&[data])
+    }
+
+    /// Combines two child nodes into their parent.
+    fn hash_nodes(left: &Self::Digest, right: &Self::Digest) -> Self::Digest {
+        Self::hash_parts(&[left.to_bytes().as_ref(), right.to_bytes().as_ref()])
+    }
+
+    /// Combines the children of a node of a [`crate::KaryMerkleTree`] into their parent. Two
+    /// children are combined with [`MerkleHasher::hash_nodes`], so binary trees agree with the
+    /// rest of the crate, and more are hashed as their concatenation unless overridden.
+    fn hash_children(children: &[Self::Digest]) -> Self::Digest {
+        match children {
+            [left, right] => Self::hash_nodes(left, right),
+            _ => {
+                let bytes: Vec<_> = children.iter().map(MerkleDigest::to_bytes).collect();
+                Self::hash_parts(&bytes.iter().map(AsRef::as_ref).collect::<Vec<_>>())
+            }
+        }
+    }
+}
+
+/// Rust's built-in `DefaultHasher`, which returns a `u64`.
+///
+/// This is the hashing the crate originally shipped with: it is fast but neither collision
+/// resistant nor stable across Rust releases, so it must not be used for integrity guarantees.
+/// Leaves hash like `str` does, so `SipHasher64::hash_leaf(word.as_bytes()) == hash(&word)`, and
+/// interior nodes hex-encode both children before hashing them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SipHasher64;
+
+impl MerkleHasher for SipHasher64 {
+    type Digest = u64;
+    const ALGORITHM_ID: u8 = 1;
+
+    fn hash_parts(parts: &[&[u8]]) -> u64 {
+        let mut s = DefaultHasher::new();
+        for part in parts {
+            s.write(part);
+        }
+        // Same terminator `str` writes, so leaves agree with `hash(&word)`
+        s.write_u8(0xff);
+        s.finish()
+    }
+
+    fn hash_nodes(left: &u64, right: &u64) -> u64 {
+        let combined = format!(
+            "{}{}",
+            hex::encode(left.to_le_bytes()),
+            hex::encode(right.to_le_bytes())
+        );
+        let mut s = DefaultHasher::new();
+        combined.hash(&mut s);
+        s.finish()
+    }
+}
+
+/// SHA-256.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sha256Hasher;
+
+impl MerkleHasher for Sha256Hasher {
+    type Digest = [u8; 32];
+    const ALGORITHM_ID: u8 = 2;
+
+    fn hash_parts(parts: &[&[u8]]) -> [u8; 32] {
+        let mut hasher = sha2::Sha256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().into()
+    }
+}
+
+/// SHA-512/256, the SHA-512 compression function truncated to 256 bits. It is usually faster
+/// than SHA-256 on 64-bit machines without SHA extensions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Sha512_256Hasher;
+
+impl MerkleHasher for Sha512_256Hasher {
+    type Digest = [u8; 32];
+    const ALGORITHM_ID: u8 = 3;
+
+    fn hash_parts(parts: &[&[u8]]) -> [u8; 32] {
+        let mut hasher = sha2::Sha512Trunc256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().into()
+    }
+}
+
+/// BLAKE3 with its default 256-bit output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Blake3Hasher;
+
+impl MerkleHasher for Blake3Hasher {
+    type Digest = [u8; 32];
+    const ALGORITHM_ID: u8 = 4;
+
+    fn hash_parts(parts: &[&[u8]]) -> [u8; 32] {
+        let mut hasher = blake3::Hasher::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().into()
+    }
+}
+
+/// Keccak-256, the original Keccak submission Ethereum uses. It differs from the standardized
+/// SHA3-256 only in its padding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Keccak256Hasher;
+
+impl MerkleHasher for Keccak256Hasher {
+    type Digest = [u8; 32];
+    const ALGORITHM_ID: u8 = 5;
+
+    fn hash_parts(parts: &[&[u8]]) -> [u8; 32] {
+        let mut hasher = sha3::Keccak256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().into()
+    }
+}
+
+/// Prefix of a leaf's data in [`DomainSeparated`], as in RFC 6962.
+pub const LEAF_PREFIX: u8 = 0x00;
+
+/// Prefix of an interior node's children in [`DomainSeparated`], as in RFC 6962.
+pub const NODE_PREFIX: u8 = 0x01;
+
+/// Hardens another hasher with RFC 6962 domain separation: leaves are hashed as
+/// `H(0x00 || data)` and interior nodes as `H(0x01 || left || right)`, or with all of their
+/// children after the prefix in a [`crate::KaryMerkleTree`].
+///
+/// Without the prefixes, the concatenation of two child hashes is a valid leaf whose hash is the
+/// parent node, so anybody can prove that the parent is a leaf with a proof that is one level
+/// shorter (a second-preimage attack). The mode is chosen per tree through the hasher type, e.g.
+/// `MerkleTree<DomainSeparated<Sha256Hasher>>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DomainSeparated<H>(PhantomData<H>);
+
+impl<H: MerkleHasher> MerkleHasher for DomainSeparated<H> {
+    type Digest = H::Digest;
+    // The high bit tells the domain-separated variant apart from the plain hash function
+    const ALGORITHM_ID: u8 = H::ALGORITHM_ID | 0x80;
+
+    fn hash_parts(parts: &[&[u8]]) -> H::Digest {
+        H::hash_parts(parts)
+    }
+
+    fn hash_leaf(data: &[u8]) -> H::Digest {
+        H::hash_parts(&[&[LEAF_PREFIX], data])
+    }
+
+    fn hash_nodes(left: &H::Digest, right: &H::Digest) -> H::Digest {
+        H::hash_parts(&[&[NODE_PREFIX], left.to_bytes().as_ref(), right.to_bytes().as_ref()])
+    }
+
+    fn hash_children(children: &[H::Digest]) -> H::Digest {
+        let bytes: Vec<_> = children.iter().map(MerkleDigest::to_bytes).collect();
+        let mut parts: Vec<&[u8]> = vec![&[NODE_PREFIX]];
+        parts.extend(bytes.iter().map(AsRef::as_ref));
+        H::hash_parts(&parts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        calculate_merkle_root, calculate_merkle_root_from_leaves, generate_proof,
+        generate_proof_from_leaves, hash, validate_proof, validate_proof_for_leaf, SiblingNode,
+    };
+
+    #[test]
+    fn sip_leaf_matches_legacy_hash() {
+        for word in ["", "a", "trust", "right?"] {
+            assert_eq!(hash(&word), SipHasher64::hash_leaf(word.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn sha256_known_vector() {
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            hex::encode(Sha256Hasher::hash_leaf(b"abc"))
+        );
+    }
+
+    #[test]
+    fn sha512_256_known_vector() {
+        assert_eq!(
+            "53048e2681941ef99b2e29b76b4c7dabe4c2d0c634fc6d46e0e2f13107e7af23",
+            hex::encode(Sha512_256Hasher::hash_leaf(b"abc"))
+        );
+    }
+
+    #[test]
+    fn blake3_known_vector() {
+        assert_eq!(
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            hex::encode(Blake3Hasher::hash_leaf(b"abc"))
+        );
+    }
+
+    #[test]
+    fn keccak256_known_vectors() {
+        assert_eq!(
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+            hex::encode(Keccak256Hasher::hash_leaf(b""))
+        );
+        assert_eq!(
+            "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45",
+            hex::encode(Keccak256Hasher::hash_leaf(b"abc"))
+        );
+    }
+
+    #[test]
+    fn hash_nodes_concatenates_children() {
+        let left = Sha256Hasher::hash_leaf(b"a");
+        let right = Sha256Hasher::hash_leaf(b"b");
+        let mut concatenated = left.to_vec();
+        concatenated.extend_from_slice(&right);
+        assert_eq!(
+            Sha256Hasher::hash_leaf(&concatenated),
+            Sha256Hasher::hash_nodes(&left, &right)
+        );
+    }
+
+    #[test]
+    fn domain_separated_known_vectors() {
+        // Leaf hash of the empty string and root of the first two leaves of the RFC 6962 test data
+        assert_eq!(
+            "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+            hex::encode(DomainSeparated::<Sha256Hasher>::hash_leaf(b""))
+        );
+        let leaves: [&[u8]; 2] = [b"", &[0x00]];
+        assert_eq!(
+            "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+            hex::encode(calculate_merkle_root_from_leaves::<DomainSeparated<Sha256Hasher>, _>(&leaves))
+        );
+    }
+
+    /// Presents the concatenation of the first two leaf hashes of a four-leaf tree as a leaf,
+    /// together with the proof of their parent node.
+    fn interior_node_attack<H: MerkleHasher>() -> bool {
+        let leaves: [&[u8]; 4] = [b"a", b"b", b"c", b"d"];
+        let root = calculate_merkle_root_from_leaves::<H, _>(&leaves);
+
+        let (_, proof) = generate_proof_from_leaves::<H, _>(&leaves, 0);
+        let left = H::hash_leaf(leaves[0]);
+        let right = H::hash_leaf(leaves[1]);
+        let mut forged_leaf = left.to_bytes().as_ref().to_vec();
+        forged_leaf.extend_from_slice(right.to_bytes().as_ref());
+
+        // Only the sibling of the parent node is needed
+        validate_proof_for_leaf::<H>(&root, &forged_leaf, proof[1..].to_vec())
+    }
+
+    #[test]
+    fn second_preimage_attack_succeeds_without_domain_separation() {
+        assert!(interior_node_attack::<Sha256Hasher>());
+        assert!(interior_node_attack::<Sha512_256Hasher>());
+        assert!(interior_node_attack::<Blake3Hasher>());
+    }
+
+    #[test]
+    fn second_preimage_attack_fails_with_domain_separation() {
+        assert!(!interior_node_attack::<DomainSeparated<Sha256Hasher>>());
+        assert!(!interior_node_attack::<DomainSeparated<Sha512_256Hasher>>());
+        assert!(!interior_node_attack::<DomainSeparated<Blake3Hasher>>());
+    }
+
+    #[test]
+    fn second_preimage_attack_on_legacy_sentences() {
+        // The legacy interior hash is the hash of the hex-encoded children, which is a valid word
+        let sentence = "You trust me, right?";
+        let root = calculate_merkle_root::<SipHasher64>(sentence);
+        let (_, proof) = generate_proof::<SipHasher64>(sentence, 0);
+        let forged_word = format!(
+            "{}{}",
+            hex::encode(hash(&"You").to_le_bytes()),
+            hex::encode(hash(&"trust").to_le_bytes())
+        );
+        assert!(validate_proof::<SipHasher64>(&root, &forged_word, proof[1..].to_vec()));
+
+        type Hardened = DomainSeparated<SipHasher64>;
+        let root = calculate_merkle_root::<Hardened>(sentence);
+        let (_, proof) = generate_proof::<Hardened>(sentence, 0);
+        let left = Hardened::hash_leaf(b"You");
+        let right = Hardened::hash_leaf(b"trust");
+        let forged_word = format!("{}{}", hex::encode(left.to_le_bytes()), hex::encode(right.to_le_bytes()));
+        assert!(!validate_proof::<Hardened>(&root, &forged_word, proof[1..].to_vec()));
+        assert!(validate_proof::<Hardened>(&root, "trust", generate_proof::<Hardened>(sentence, 1).1));
+        assert_eq!(SiblingNode::Right(Hardened::hash_nodes(
+            &Hardened::hash_leaf(b"me,"),
+            &Hardened::hash_leaf(b"right?")
+        )), proof[1]);
+    }
+}
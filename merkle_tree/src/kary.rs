@@ -0,0 +1,572 @@
+//! Merkle trees whose nodes have 2, 4, 8 or 16 children.
+//!
+//! A wider tree is shallower, so a proof has fewer levels, but each level carries the `k - 1`
+//! siblings of the node instead of one. Which arity gives the smallest proofs depends on how many
+//! leaves are proven at once: single proofs are smallest in binary trees, while multiproofs for
+//! many leaves share more siblings in wider ones. See [`crate::compare_proof_sizes_with_arity`].
+//!
+//! Like the default [`TreeShape::Padded`](crate::TreeShape::Padded) binary tree, a
+//! [`KaryMerkleTree`] is padded with empty leaves, here up to the next power of its arity, so
+//! every node has exactly `k` children. Nodes are combined with [`MerkleHasher::hash_children`],
+//! and a tree of arity 2 has the same root and multiproofs as the padded binary tree.
+
+use crate::error::{check_index, check_indices};
+use crate::parallel::map_indices;
+use crate::{hash_leaves, CompactMerkleMultiProof, MerkleError, MerkleHasher};
+use serde::{Deserialize, Serialize};
+
+/// The number of children of every interior node of a [`KaryMerkleTree`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Arity {
+    #[default]
+    Two,
+    Four,
+    Eight,
+    Sixteen,
+}
+
+impl Arity {
+    /// Every supported arity, from the narrowest to the widest.
+    pub const ALL: [Arity; 4] = [Arity::Two, Arity::Four, Arity::Eight, Arity::Sixteen];
+
+    /// The number of children.
+    pub fn get(self) -> usize {
+        match self {
+            Arity::Two => 2,
+            Arity::Four => 4,
+            Arity::Eight => 8,
+            Arity::Sixteen => 16,
+        }
+    }
+
+    /// The number of levels above the leaves of a tree of `len` leaves, which is padded to the
+    /// next power of the arity. Even an empty tree has one (padding) leaf.
+    pub fn height(self, len: usize) -> usize {
+        let mut height = 0;
+        let mut width = 1;
+        while width < len {
+            width = width.saturating_mul(self.get());
+            height += 1;
+        }
+        height
+    }
+}
+
+impl TryFrom<usize> for Arity {
+    type Error = MerkleError;
+
+    fn try_from(arity: usize) -> Result<Self, MerkleError> {
+        Arity::ALL
+            .into_iter()
+            .find(|candidate| candidate.get() == arity)
+            .ok_or(MerkleError::UnsupportedArity(arity))
+    }
+}
+
+/// One level of a [`KaryProof`]: the position of the node among its siblings, from 0 to `k - 1`,
+/// and the other `k - 1` children of its parent in order.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct KarySiblings<D> {
+    pub position: usize,
+    pub siblings: Vec<D>,
+}
+
+/// A proof for a leaf of a [`KaryMerkleTree`], from the leaf up.
+pub type KaryProof<D> = Vec<KarySiblings<D>>;
+
+/// A [`KaryProof`] without the positions, which follow from the leaf index as they do for a
+/// [`CompactMerkleProof`](crate::CompactMerkleProof): the siblings of every level, from the leaf up,
+/// `k - 1` per level.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct CompactKaryProof<D> {
+    pub arity: Arity,
+    pub leaf_index: usize,
+    pub tree_size: usize,
+    pub siblings: Vec<D>,
+}
+
+impl<D: Copy> CompactKaryProof<D> {
+    /// Drops the positions of `proof`, a proof for the leaf at `leaf_index` of a tree of
+    /// `tree_size` leaves and the given arity.
+    ///
+    /// Fails if the index is beyond the tree, or the levels or positions are not the ones it
+    /// requires.
+    pub fn from_proof(
+        proof: &KaryProof<D>,
+        leaf_index: usize,
+        tree_size: usize,
+        arity: Arity,
+    ) -> Result<Self, MerkleError> {
+        check_index(leaf_index, tree_size)?;
+        let height = arity.height(tree_size);
+        if proof.len() != height {
+            return Err(MerkleError::ProofDepthMismatch { expected: height, actual: proof.len() });
+        }
+        let k = arity.get();
+        let mut idx = leaf_index;
+        let mut siblings = Vec::with_capacity(height * (k - 1));
+        for (position, level) in proof.iter().enumerate() {
+            if level.siblings.len() != k - 1 {
+                return Err(MerkleError::MalformedProof);
+            }
+            if level.position != idx % k {
+                return Err(MerkleError::DirectionMismatch { position });
+            }
+            siblings.extend_from_slice(&level.siblings);
+            idx /= k;
+        }
+        Ok(CompactKaryProof { arity, leaf_index, tree_size, siblings })
+    }
+
+    /// Restores the positions of the proof.
+    ///
+    /// Fails if the index is beyond the tree or the number of siblings is not `k - 1` per level.
+    pub fn to_proof(&self) -> Result<KaryProof<D>, MerkleError> {
+        check_index(self.leaf_index, self.tree_size)?;
+        let k = self.arity.get();
+        let height = self.arity.height(self.tree_size);
+        if self.siblings.len() != height * (k - 1) {
+            return Err(MerkleError::ProofDepthMismatch { expected: height * (k - 1), actual: self.siblings.len() });
+        }
+        let mut idx = self.leaf_index;
+        let proof = self
+            .siblings
+            .chunks(k - 1)
+            .map(|siblings| {
+                let level = KarySiblings { position: idx % k, siblings: siblings.to_vec() };
+                idx /= k;
+                level
+            })
+            .collect();
+        Ok(proof)
+    }
+}
+
+/// A Merkle tree of a given [`Arity`] over a list of data blocks, padded with empty leaves up to a
+/// power of the arity.
+///
+/// `levels[0]` holds the padded leaf hashes and the last level holds only the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KaryMerkleTree<H: MerkleHasher> {
+    levels: Vec<Vec<H::Digest>>,
+    arity: Arity,
+    len: usize,
+}
+
+impl<H: MerkleHasher> KaryMerkleTree<H> {
+    /// Builds the tree over `leaves`, one leaf per data block.
+    pub fn from_leaves<T: AsRef<[u8]>>(leaves: &[T], arity: Arity) -> Self {
+        Self::from_leaf_digests(hash_leaves::<H, T>(leaves), arity)
+    }
+
+    /// Builds the tree over leaves that were already hashed with [`MerkleHasher::hash_leaf`].
+    pub fn from_leaf_digests(mut leaves: Vec<H::Digest>, arity: Arity) -> Self {
+        let len = leaves.len();
+        let k = arity.get();
+        let height = arity.height(len);
+        leaves.resize(k.pow(height as u32), H::hash_leaf(&[]));
+
+        let mut levels = vec![leaves];
+        for _ in 0..height {
+            let children = levels.last().unwrap();
+            let next_level = map_indices(children.len() / k, |i| H::hash_children(&children[i * k..(i + 1) * k]));
+            levels.push(next_level);
+        }
+        KaryMerkleTree { levels, arity, len }
+    }
+
+    pub fn root(&self) -> H::Digest {
+        self.levels[self.height()][0]
+    }
+
+    pub fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    /// The number of leaves, not counting padding.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The number of levels above the leaves.
+    pub fn height(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    /// Every level of the tree, from the (padded) leaves up to the root.
+    pub fn levels(&self) -> &[Vec<H::Digest>] {
+        &self.levels
+    }
+
+    /// Returns the proof for the leaf at `index`: at every level, its position and the other
+    /// children of its parent.
+    ///
+    /// Panics if the index is out of range.
+    pub fn proof(&self, index: usize) -> KaryProof<H::Digest> {
+        self.try_proof(index).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`KaryMerkleTree::proof`], but returns an error instead of panicking.
+    pub fn try_proof(&self, index: usize) -> Result<KaryProof<H::Digest>, MerkleError> {
+        check_index(index, self.len)?;
+        let k = self.arity.get();
+        let mut idx = index;
+        let mut proof = Vec::with_capacity(self.height());
+        for level in &self.levels[..self.height()] {
+            let first = idx - idx % k;
+            let siblings = (first..first + k).filter(|&i| i != idx).map(|i| level[i]).collect();
+            proof.push(KarySiblings { position: idx % k, siblings });
+            idx /= k;
+        }
+        Ok(proof)
+    }
+
+    /// Returns a compact multiproof for the leaves at `indices`, which are not necessarily sorted.
+    /// The hashes are the children the verifier cannot compute, level by level from the leaves up
+    /// and from left to right within a level, as for binary trees.
+    ///
+    /// Panics if no index is given, any index is beyond the number of leaves, or any index is
+    /// duplicated.
+    pub fn multiproof(&self, indices: &[usize]) -> CompactMerkleMultiProof<H::Digest> {
+        self.try_multiproof(indices).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`KaryMerkleTree::multiproof`], but returns an error instead of panicking.
+    pub fn try_multiproof(&self, indices: &[usize]) -> Result<CompactMerkleMultiProof<H::Digest>, MerkleError> {
+        let mut known = check_indices(indices, self.len)?;
+        let k = self.arity.get();
+        let mut hashes = Vec::new();
+        for level in &self.levels[..self.height()] {
+            let mut i = 0;
+            while i < known.len() {
+                // The known children of one parent are next to each other, the others are added
+                let first = known[i] - known[i] % k;
+                for (child, &hash) in level.iter().enumerate().skip(first).take(k) {
+                    if known.get(i) == Some(&child) {
+                        i += 1;
+                    } else {
+                        hashes.push(hash);
+                    }
+                }
+            }
+            known = known.iter().map(|idx| idx / k).collect();
+            known.dedup();
+        }
+
+        Ok(CompactMerkleMultiProof { leaf_indices: indices.to_vec(), tree_size: self.len, hashes })
+    }
+}
+
+/// Calculates the root of the tree of the given arity over `leaves`, without keeping the levels.
+pub fn calculate_kary_merkle_root<H: MerkleHasher, T: AsRef<[u8]>>(leaves: &[T], arity: Arity) -> H::Digest {
+    let k = arity.get();
+    let mut level = hash_leaves::<H, T>(leaves);
+    level.resize(k.pow(arity.height(leaves.len()) as u32), H::hash_leaf(&[]));
+    while level.len() > 1 {
+        level = map_indices(level.len() / k, |i| H::hash_children(&level[i * k..(i + 1) * k]));
+    }
+    level[0]
+}
+
+/// Checks that `leaf_hash` is the leaf at `index` of the tree of `tree_size` leaves and the given
+/// arity with the given root.
+///
+/// The proof must have one entry per level of the tree, each with `k - 1` siblings and the
+/// position the index requires, so a proof for one leaf cannot be passed off as a proof for
+/// another.
+pub fn verify_kary_proof<H: MerkleHasher>(
+    root: &H::Digest,
+    arity: Arity,
+    index: usize,
+    tree_size: usize,
+    leaf_hash: H::Digest,
+    proof: &KaryProof<H::Digest>,
+) -> Result<(), MerkleError> {
+    check_index(index, tree_size)?;
+    let height = arity.height(tree_size);
+    if proof.len() != height {
+        return Err(MerkleError::ProofDepthMismatch { expected: height, actual: proof.len() });
+    }
+
+    let k = arity.get();
+    let mut idx = index;
+    let mut hash = leaf_hash;
+    for (position, level) in proof.iter().enumerate() {
+        if level.siblings.len() != k - 1 {
+            return Err(MerkleError::MalformedProof);
+        }
+        if level.position != idx % k {
+            return Err(MerkleError::DirectionMismatch { position });
+        }
+        let mut children = level.siblings.clone();
+        children.insert(level.position, hash);
+        hash = H::hash_children(&children);
+        idx /= k;
+    }
+
+    if hash != *root {
+        return Err(MerkleError::RootMismatch);
+    }
+    Ok(())
+}
+
+/// Checks a multiproof made by [`KaryMerkleTree::multiproof`] for leaf hashes given in the same
+/// order as the indices of the proof.
+pub fn verify_kary_multiproof<H: MerkleHasher>(
+    root: &H::Digest,
+    arity: Arity,
+    leaf_hashes: Vec<H::Digest>,
+    proof: &CompactMerkleMultiProof<H::Digest>,
+) -> Result<(), MerkleError> {
+    check_indices(&proof.leaf_indices, proof.tree_size)?;
+    if leaf_hashes.len() != proof.leaf_indices.len() {
+        return Err(MerkleError::ProofLengthMismatch { expected: proof.leaf_indices.len(), actual: leaf_hashes.len() });
+    }
+
+    let mut known: Vec<(usize, H::Digest)> = proof.leaf_indices.iter().copied().zip(leaf_hashes).collect();
+    known.sort_unstable_by_key(|&(index, _)| index);
+
+    let k = arity.get();
+    let mut hashes = proof.hashes.iter();
+    for _ in 0..arity.height(proof.tree_size) {
+        let mut next_level = Vec::with_capacity(known.len());
+        let mut i = 0;
+        while i < known.len() {
+            let first = known[i].0 - known[i].0 % k;
+            let mut children = Vec::with_capacity(k);
+            for child in first..first + k {
+                match known.get(i) {
+                    Some(&(index, hash)) if index == child => {
+                        children.push(hash);
+                        i += 1;
+                    }
+                    _ => children.push(*hashes.next().ok_or(MerkleError::MalformedProof)?),
+                }
+            }
+            next_level.push((first / k, H::hash_children(&children)));
+        }
+        known = next_level;
+    }
+
+    if hashes.next().is_some() {
+        return Err(MerkleError::MalformedProof);
+    }
+    if known[0].1 != *root {
+        return Err(MerkleError::RootMismatch);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        calculate_merkle_root_from_digests, kary_multiproof_from_bytes, kary_multiproof_to_bytes, kary_proof_from_bytes,
+        kary_proof_to_bytes, CompactMerkleProof, DomainSeparated, MerkleTree, Sha256Hasher, SipHasher64,
+        PROOF_FORMAT_VERSION,
+    };
+    use crate::tree::tests::leaf_digests;
+    use rand::rngs::SmallRng;
+    use rand::seq::index::sample;
+    use rand::SeedableRng;
+
+    #[test]
+    fn arities_and_heights() {
+        assert_eq!(Ok(Arity::Eight), Arity::try_from(8));
+        assert_eq!(Err(MerkleError::UnsupportedArity(3)), Arity::try_from(3));
+        assert_eq!([0, 0, 1, 2, 2, 3], [0, 1, 2, 3, 4, 5].map(|n| Arity::Two.height(n)));
+        assert_eq!([0, 1, 1, 2, 2], [1, 2, 16, 17, 256].map(|n| Arity::Sixteen.height(n)));
+    }
+
+    #[test]
+    fn binary_trees_match_the_padded_tree() {
+        for n in 0..=33 {
+            let tree = KaryMerkleTree::<SipHasher64>::from_leaf_digests(leaf_digests(n), Arity::Two);
+            assert_eq!(calculate_merkle_root_from_digests::<SipHasher64>(leaf_digests(n)), tree.root());
+            if n > 0 {
+                let indices = [0, n / 2, n - 1];
+                let indices = &indices[..if n > 2 { 3 } else { n }];
+                let binary = MerkleTree::<SipHasher64>::from_leaf_digests(leaf_digests(n));
+                assert_eq!(binary.multiproof(indices), tree.multiproof(indices));
+            }
+        }
+    }
+
+    #[test]
+    fn nodes_hash_all_children() {
+        type Hasher = DomainSeparated<Sha256Hasher>;
+        let tree = KaryMerkleTree::<Hasher>::from_leaves(&["a", "b", "c", "d", "e"], Arity::Four);
+        assert_eq!(2, tree.height());
+        assert_eq!(16, tree.levels()[0].len());
+        let level = &tree.levels()[0];
+        let empty = Hasher::hash_leaf(&[]);
+        assert_eq!(empty, level[5]);
+
+        let first = Sha256Hasher::hash_parts(&[&[0x01], &level[0], &level[1], &level[2], &level[3]]);
+        assert_eq!(first, tree.levels()[1][0]);
+        let padding = Hasher::hash_children(&[empty; 4]);
+        let second = Hasher::hash_children(&[level[4], empty, empty, empty]);
+        assert_eq!(Hasher::hash_children(&[first, second, padding, padding]), tree.root());
+        assert_eq!(tree.root(), calculate_kary_merkle_root::<Hasher, _>(&["a", "b", "c", "d", "e"], Arity::Four));
+    }
+
+    #[test]
+    fn every_proof_verifies() {
+        for arity in Arity::ALL {
+            for n in 1..=70 {
+                let tree = KaryMerkleTree::<SipHasher64>::from_leaf_digests(leaf_digests(n), arity);
+                let data: Vec<[u8; 8]> = (0..n).map(|i| i.to_le_bytes()).collect();
+                assert_eq!(calculate_kary_merkle_root::<SipHasher64, _>(&data, arity), tree.root());
+                for index in 0..n {
+                    let proof = tree.proof(index);
+                    assert!(proof.iter().all(|level| level.siblings.len() == arity.get() - 1));
+                    let leaf = leaf_digests(n)[index];
+                    assert_eq!(Ok(()), verify_kary_proof::<SipHasher64>(&tree.root(), arity, index, n, leaf, &proof));
+
+                    let bytes = kary_proof_to_bytes::<SipHasher64>(&proof, n, arity).unwrap();
+                    assert_eq!(Ok((proof.clone(), n, arity)), kary_proof_from_bytes::<SipHasher64>(&bytes));
+
+                    let compact = CompactKaryProof::from_proof(&proof, index, n, arity).unwrap();
+                    let decoded = CompactKaryProof::from_bytes::<SipHasher64>(&compact.to_bytes::<SipHasher64>());
+                    assert_eq!(Ok(proof), decoded.unwrap().to_proof());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_multiproof_verifies() {
+        for arity in Arity::ALL {
+            for n in 1..=9 {
+                let tree = KaryMerkleTree::<SipHasher64>::from_leaf_digests(leaf_digests(n), arity);
+                for subset in 1..1u32 << n {
+                    let indices: Vec<usize> = (0..n).filter(|&i| subset & (1 << i) != 0).rev().collect();
+                    let multiproof = tree.multiproof(&indices);
+                    let known = indices.iter().map(|&i| leaf_digests(n)[i]).collect();
+                    assert_eq!(Ok(()), verify_kary_multiproof::<SipHasher64>(&tree.root(), arity, known, &multiproof));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn random_multiproofs_over_several_levels() {
+        let n = 300;
+        let mut rng = SmallRng::seed_from_u64(300);
+        for arity in Arity::ALL {
+            let tree = KaryMerkleTree::<SipHasher64>::from_leaf_digests(leaf_digests(n), arity);
+            assert!(tree.height() >= 3);
+            for count in [1, 2, 3, 5, 17, 40, 150, 299, 300] {
+                let indices = sample(&mut rng, n, count).into_vec();
+                let multiproof = tree.multiproof(&indices);
+                let known: Vec<u64> = indices.iter().map(|&i| leaf_digests(n)[i]).collect();
+                assert_eq!(Ok(()), verify_kary_multiproof::<SipHasher64>(&tree.root(), arity, known.clone(), &multiproof));
+
+                let bytes = kary_multiproof_to_bytes::<SipHasher64>(&multiproof, arity);
+                let (decoded, decoded_arity) = kary_multiproof_from_bytes::<SipHasher64>(&bytes).unwrap();
+                assert_eq!((&multiproof, arity), (&decoded, decoded_arity));
+
+                let mut tampered = multiproof.clone();
+                if let Some(hash) = tampered.hashes.last_mut() {
+                    *hash ^= 1;
+                    assert_eq!(
+                        Err(MerkleError::RootMismatch),
+                        verify_kary_multiproof::<SipHasher64>(&tree.root(), arity, known, &tampered)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn encodings_carry_the_arity() {
+        let tree = KaryMerkleTree::<SipHasher64>::from_leaf_digests(leaf_digests(20), Arity::Four);
+        let bytes = kary_multiproof_to_bytes::<SipHasher64>(&tree.multiproof(&[3, 6, 17]), Arity::Four);
+        assert_eq!([PROOF_FORMAT_VERSION, 4, 1], bytes[..3]);
+        assert_eq!(4, bytes[11]);
+        // None of the k-ary encodings decodes as a binary proof
+        assert_eq!(Err(MerkleError::UnexpectedProofKind(4)), CompactMerkleMultiProof::from_bytes::<SipHasher64>(&bytes));
+        let mut arity = bytes.clone();
+        arity[11] = 3;
+        assert_eq!(Err(MerkleError::UnsupportedArity(3)), kary_multiproof_from_bytes::<SipHasher64>(&arity));
+
+        let bytes = kary_proof_to_bytes::<SipHasher64>(&tree.proof(6), 20, Arity::Four).unwrap();
+        assert_eq!([PROOF_FORMAT_VERSION, 3, 1], bytes[..3]);
+        assert_eq!(4, bytes[11]);
+        assert_eq!(Ok((tree.proof(6), 20, Arity::Four)), kary_proof_from_bytes::<SipHasher64>(&bytes));
+        let mut arity = bytes.clone();
+        arity[11] = 8;
+        assert_eq!(Err(MerkleError::TruncatedData), kary_proof_from_bytes::<SipHasher64>(&arity));
+
+        let compact = CompactKaryProof::from_proof(&tree.proof(6), 6, 20, Arity::Four).unwrap();
+        let bytes = compact.to_bytes::<SipHasher64>();
+        assert_eq!([PROOF_FORMAT_VERSION, 5, 1], bytes[..3]);
+        assert_eq!(4, bytes[11]);
+        assert_eq!(Err(MerkleError::UnexpectedProofKind(5)), CompactMerkleProof::<u64>::from_bytes::<SipHasher64>(&bytes));
+        for len in 0..bytes.len() {
+            assert_eq!(Err(MerkleError::TruncatedData), CompactKaryProof::<u64>::from_bytes::<SipHasher64>(&bytes[..len]));
+        }
+
+        assert_eq!(
+            Err(MerkleError::DirectionMismatch { position: 0 }),
+            CompactKaryProof::from_proof(&tree.proof(6), 7, 20, Arity::Four)
+        );
+        let mut short = compact;
+        short.siblings.pop();
+        assert_eq!(Err(MerkleError::ProofDepthMismatch { expected: 9, actual: 8 }), short.to_proof());
+    }
+
+    #[test]
+    fn wider_trees_are_shallower() {
+        let n = 4096;
+        let sizes: Vec<(usize, usize)> = Arity::ALL
+            .into_iter()
+            .map(|arity| {
+                let tree = KaryMerkleTree::<SipHasher64>::from_leaf_digests(leaf_digests(n), arity);
+                let proof = tree.proof(1234);
+                (proof.len(), proof.iter().map(|level| level.siblings.len()).sum())
+            })
+            .collect();
+        assert_eq!(vec![(12, 12), (6, 18), (4, 28), (3, 45)], sizes);
+    }
+
+    #[test]
+    fn malformed_proofs() {
+        let tree = KaryMerkleTree::<SipHasher64>::from_leaf_digests(leaf_digests(20), Arity::Four);
+        let root = tree.root();
+        let leaf = leaf_digests(20)[6];
+        let proof = tree.proof(6);
+        let verify =
+            |index, proof: &KaryProof<u64>| verify_kary_proof::<SipHasher64>(&root, Arity::Four, index, 20, leaf, proof);
+
+        assert_eq!(Err(MerkleError::DirectionMismatch { position: 0 }), verify(7, &proof));
+        assert_eq!(Err(MerkleError::ProofDepthMismatch { expected: 3, actual: 2 }), verify(6, &proof[..2].to_vec()));
+        let mut short = proof.clone();
+        short[1].siblings.pop();
+        assert_eq!(Err(MerkleError::MalformedProof), verify(6, &short));
+        let mut tampered = proof.clone();
+        tampered[2].siblings[0] ^= 1;
+        assert_eq!(Err(MerkleError::RootMismatch), verify(6, &tampered));
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 20, len: 20 }), tree.try_proof(20));
+        // Levels with another number of siblings than the arity gives are not encoded
+        assert_eq!(Err(MerkleError::MalformedProof), kary_proof_to_bytes::<SipHasher64>(&short, 20, Arity::Four));
+        assert_eq!(Err(MerkleError::MalformedProof), kary_proof_to_bytes::<SipHasher64>(&proof, 20, Arity::Eight));
+
+        let multiproof = tree.multiproof(&[3, 6, 17]);
+        let known = vec![leaf_digests(20)[3], leaf, leaf_digests(20)[17]];
+        let verify_multi = |arity, multiproof| verify_kary_multiproof::<SipHasher64>(&root, arity, known.clone(), multiproof);
+        let mut extra = multiproof.clone();
+        extra.hashes.push(0);
+        assert_eq!(Err(MerkleError::MalformedProof), verify_multi(Arity::Four, &extra));
+        let mut missing = multiproof.clone();
+        missing.hashes.pop();
+        assert_eq!(Err(MerkleError::MalformedProof), verify_multi(Arity::Four, &missing));
+        // A binary tree of 20 leaves needs other hashes
+        assert_eq!(Err(MerkleError::MalformedProof), verify_multi(Arity::Two, &multiproof));
+        assert_eq!(Err(MerkleError::DuplicateIndex(6)), tree.try_multiproof(&[6, 6]));
+    }
+}
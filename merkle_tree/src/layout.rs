@@ -0,0 +1,320 @@
+//! The position of every node in a tree of a given size and shape.
+//!
+//! Roots, proofs and multiproofs all have to agree on how many nodes each level has, which node is
+//! whose sibling and which nodes are promoted without one. [`TreeLayout`] answers these questions
+//! once, and the free functions, [`MerkleTree`](crate::MerkleTree) and the verifiers all go through
+//! it, so a tree of any size has the same root no matter which function computed it.
+
+use crate::parallel::map_indices;
+use crate::{
+    calculate_merkle_root_rec, concatenate_hash_values, pad_leaf_digests, MerkleError, MerkleHasher,
+    MerkleProof, SiblingNode, TreeShape,
+};
+
+/// The layout of a tree of `len` leaves with the given [`TreeShape`].
+///
+/// Levels are numbered by their height above the leaves. A padded tree has a power of two number
+/// of leaves, the missing ones being `H::hash_leaf(&[])`, so every node but the root has a
+/// sibling. In an unbalanced tree the last node of a level with an odd number of nodes has no
+/// sibling and is promoted to the next level unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TreeLayout {
+    len: usize,
+    shape: TreeShape,
+}
+
+/// Computes the parent of the node at `2 * index` of `children`, promoting it if it has no sibling.
+pub(crate) fn parent<H: MerkleHasher>(children: &[H::Digest], index: usize) -> H::Digest {
+    match children.get(2 * index + 1) {
+        Some(&right) => concatenate_hash_values::<H>(children[2 * index], right),
+        None => children[2 * index],
+    }
+}
+
+impl TreeLayout {
+    pub fn new(len: usize, shape: TreeShape) -> Self {
+        TreeLayout { len, shape }
+    }
+
+    /// The layout of a tree padded to a power of two, as built by [`crate::calculate_merkle_root`].
+    pub fn padded(len: usize) -> Self {
+        Self::new(len, TreeShape::Padded)
+    }
+
+    /// The layout of an unbalanced tree, as in RFC 6962.
+    pub fn unbalanced(len: usize) -> Self {
+        Self::new(len, TreeShape::Unbalanced)
+    }
+
+    /// The number of leaves, not counting padding.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn shape(&self) -> TreeShape {
+        self.shape
+    }
+
+    /// The number of nodes at `height`, including padding. Even an empty padded tree has one
+    /// (padding) leaf, while an empty unbalanced tree has no nodes at all.
+    pub fn width(&self, height: usize) -> usize {
+        let leaves = match self.shape {
+            TreeShape::Padded => self.len.next_power_of_two(),
+            TreeShape::Unbalanced => self.len,
+        };
+        match leaves.checked_sub(1) {
+            None => 0,
+            Some(last) => last.checked_shr(height as u32).unwrap_or(0) + 1,
+        }
+    }
+
+    /// The number of levels above the leaves.
+    pub fn height(&self) -> usize {
+        match self.width(0) {
+            0 => 0,
+            leaves => (usize::BITS - (leaves - 1).leading_zeros()) as usize,
+        }
+    }
+
+    /// The index of the sibling of the node at `index` within the level at `height`, or `None` if
+    /// the node is promoted (or is the root).
+    pub fn sibling(&self, height: usize, index: usize) -> Option<usize> {
+        let sibling = index ^ 1;
+        (height < self.height() && sibling < self.width(height)).then_some(sibling)
+    }
+
+    /// Returns, from the leaf up, whether each sibling on the path of the leaf at `index` is on the
+    /// left, i.e. the directions of its proof. Promoted nodes have no sibling and no entry.
+    pub fn sibling_sides(&self, index: usize) -> Vec<bool> {
+        let mut idx = index;
+        let mut sides = Vec::with_capacity(self.height());
+        for height in 0..self.height() {
+            if self.sibling(height, idx).is_some() {
+                sides.push(!idx.is_multiple_of(2));
+            }
+            idx /= 2;
+        }
+        sides
+    }
+
+    /// Checks that `proof` has as many siblings as the path of the leaf at `index`, each on the
+    /// side the index requires.
+    pub(crate) fn check_proof<D>(&self, index: usize, proof: &MerkleProof<D>) -> Result<(), MerkleError> {
+        let sides = self.sibling_sides(index);
+        if sides.len() != proof.len() {
+            return Err(MerkleError::ProofDepthMismatch { expected: sides.len(), actual: proof.len() });
+        }
+        let position = sides.iter().zip(proof).position(|(&left, node)| left != matches!(node, SiblingNode::Left(_)));
+        match position {
+            Some(position) => Err(MerkleError::DirectionMismatch { position }),
+            None => Ok(()),
+        }
+    }
+
+    /// Pads already hashed leaves as the layout requires and builds every level of the tree, from
+    /// the leaves up to the root.
+    ///
+    /// Panics if the number of leaves differs from the layout's.
+    pub fn build_levels<H: MerkleHasher>(&self, mut leaves: Vec<H::Digest>) -> Vec<Vec<H::Digest>> {
+        assert_eq!(self.len, leaves.len(), "The layout is for {} leaves", self.len);
+        if self.shape == TreeShape::Padded {
+            pad_leaf_digests::<H>(&mut leaves);
+        }
+
+        let mut levels = vec![leaves];
+        for height in 0..self.height() {
+            let children = &levels[height];
+            let next_level = map_indices(self.width(height + 1), |i| parent::<H>(children, i));
+            levels.push(next_level);
+        }
+        levels
+    }
+
+    /// Computes the root of already hashed leaves without keeping the levels. An empty unbalanced
+    /// tree has the hash of the empty string as its root.
+    ///
+    /// Panics if the number of leaves differs from the layout's.
+    pub fn root<H: MerkleHasher>(&self, mut leaves: Vec<H::Digest>) -> H::Digest {
+        assert_eq!(self.len, leaves.len(), "The layout is for {} leaves", self.len);
+        match self.shape {
+            TreeShape::Padded => pad_leaf_digests::<H>(&mut leaves),
+            TreeShape::Unbalanced if leaves.is_empty() => return H::hash_parts(&[]),
+            TreeShape::Unbalanced => {}
+        }
+        // The recursion promotes a node without a sibling, which is all the layout needs
+        calculate_merkle_root_rec::<H>(leaves)
+    }
+
+    /// Returns the proof for the leaf at `index` from the levels built by
+    /// [`TreeLayout::build_levels`]. Promoted nodes have no sibling to add.
+    pub(crate) fn proof<H: MerkleHasher>(&self, levels: &[Vec<H::Digest>], index: usize) -> MerkleProof<H::Digest> {
+        let mut idx = index;
+        let mut proof = Vec::with_capacity(self.height());
+        for (height, level) in levels[..self.height()].iter().enumerate() {
+            if let Some(sibling) = self.sibling(height, idx) {
+                if idx.is_multiple_of(2) {
+                    proof.push(SiblingNode::Right(level[sibling]));
+                } else {
+                    proof.push(SiblingNode::Left(level[sibling]));
+                }
+            }
+            idx /= 2;
+        }
+        proof
+    }
+
+    /// Returns the hashes of a compact multiproof for the sorted, distinct leaves `known`, from the
+    /// levels built by [`TreeLayout::build_levels`]. See [`crate::generate_compact_multiproof`] for
+    /// their order.
+    pub(crate) fn multiproof_hashes<H: MerkleHasher>(
+        &self,
+        levels: &[Vec<H::Digest>],
+        mut known: Vec<usize>,
+    ) -> Vec<H::Digest> {
+        let mut hashes = Vec::new();
+        for (height, level) in levels[..self.height()].iter().enumerate() {
+            let mut i = 0;
+            while i < known.len() {
+                let idx = known[i];
+                if idx.is_multiple_of(2) && known.get(i + 1) == Some(&(idx + 1)) {
+                    // Both children are known, nothing to add
+                    i += 2;
+                } else {
+                    // A node without a sibling is promoted and needs nothing either
+                    if let Some(sibling) = self.sibling(height, idx) {
+                        hashes.push(level[sibling]);
+                    }
+                    i += 1;
+                }
+            }
+
+            known = known.iter().map(|idx| idx / 2).collect();
+            known.dedup();
+        }
+        hashes
+    }
+
+    /// Recomputes the root from the sorted, distinct (index, leaf hash) pairs of a compact
+    /// multiproof and its hashes.
+    ///
+    /// Each level is walked once, combining two known siblings or a known node with the next proof
+    /// hash and promoting nodes without a sibling, so this takes O(k log n) time for k leaves.
+    pub(crate) fn multiproof_root<H: MerkleHasher>(
+        &self,
+        known: Vec<(usize, H::Digest)>,
+        hashes: Vec<H::Digest>,
+    ) -> Result<H::Digest, MerkleError> {
+        let mut known = known;
+        let mut hashes = hashes.into_iter();
+
+        for height in 0..self.height() {
+            let mut next_level = Vec::with_capacity(known.len());
+            let mut i = 0;
+            while i < known.len() {
+                let (index, hash) = known[i];
+                let parent = if index.is_multiple_of(2) && known.get(i + 1).map(|&(next, _)| next) == Some(index + 1) {
+                    i += 1;
+                    concatenate_hash_values::<H>(hash, known[i].1)
+                } else if self.sibling(height, index).is_none() {
+                    hash
+                } else {
+                    let sibling = hashes.next().ok_or(MerkleError::MalformedProof)?;
+                    if index.is_multiple_of(2) {
+                        concatenate_hash_values::<H>(hash, sibling)
+                    } else {
+                        concatenate_hash_values::<H>(sibling, hash)
+                    }
+                };
+                next_level.push((index / 2, parent));
+                i += 1;
+            }
+            known = next_level;
+        }
+
+        if hashes.next().is_some() {
+            return Err(MerkleError::MalformedProof);
+        }
+        known.first().map(|&(_, root)| root).ok_or(MerkleError::NoIndices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        calculate_merkle_root_from_digests, calculate_merkle_root_with_shape, generate_compact_multiproof_from_digests,
+        generate_proof_from_digests, validate_compact_multiproof_for_digests,
+        validate_compact_multiproof_with_shape, validate_proof_for_digest, MerkleTree, SipHasher64,
+    };
+    use crate::tree::tests::leaf_digests;
+
+    #[test]
+    fn widths_and_heights() {
+        let padded = TreeLayout::padded(5);
+        assert_eq!([8, 4, 2, 1, 1], [0, 1, 2, 3, 4].map(|h| padded.width(h)));
+        assert_eq!(3, padded.height());
+        assert_eq!(Some(5), padded.sibling(0, 4));
+
+        let unbalanced = TreeLayout::unbalanced(5);
+        assert_eq!([5, 3, 2, 1, 1], [0, 1, 2, 3, 4].map(|h| unbalanced.width(h)));
+        assert_eq!(3, unbalanced.height());
+        assert_eq!(None, unbalanced.sibling(0, 4));
+        assert_eq!(None, unbalanced.sibling(1, 2));
+        assert_eq!(Some(0), unbalanced.sibling(2, 1));
+        assert_eq!(None, unbalanced.sibling(3, 0));
+
+        assert_eq!((1, 0), (TreeLayout::padded(0).width(0), TreeLayout::padded(0).height()));
+        assert_eq!((0, 0), (TreeLayout::unbalanced(0).width(0), TreeLayout::unbalanced(0).height()));
+        assert_eq!(1, TreeLayout::padded(usize::MAX / 2).width(usize::BITS as usize));
+
+        assert_eq!(vec![false, true, false], padded.sibling_sides(2));
+        assert_eq!(vec![false, true, false], unbalanced.sibling_sides(2));
+        assert_eq!(vec![true], unbalanced.sibling_sides(4));
+    }
+
+    /// Every way to compute a root must agree, for every size and both shapes.
+    #[test]
+    fn roots_agree_for_every_size() {
+        for n in 1..=1024usize {
+            let leaves = leaf_digests(n);
+            let probes = [0, n / 3, n / 2, n - 1];
+
+            let padded = TreeLayout::padded(n);
+            let root = padded.root::<SipHasher64>(leaves.clone());
+            assert_eq!(root, calculate_merkle_root_from_digests::<SipHasher64>(leaves.clone()));
+            assert_eq!(root, MerkleTree::<SipHasher64>::from_leaf_digests(leaves.clone()).root());
+            let levels = padded.build_levels::<SipHasher64>(leaves.clone());
+            assert_eq!(vec![root], levels[padded.height()]);
+
+            let (proof_root, proof) = generate_proof_from_digests::<SipHasher64>(leaves.clone(), n - 1);
+            assert_eq!(root, proof_root);
+            assert!(validate_proof_for_digest::<SipHasher64>(&root, leaves[n - 1], proof));
+
+            let mut indices = probes.to_vec();
+            indices.dedup();
+            let (multiproof_root, multiproof) =
+                generate_compact_multiproof_from_digests::<SipHasher64>(leaves.clone(), indices.clone());
+            assert_eq!(root, multiproof_root);
+            let proven: Vec<u64> = indices.iter().map(|&i| leaves[i]).collect();
+            assert!(validate_compact_multiproof_for_digests::<SipHasher64>(&root, proven.clone(), multiproof));
+
+            let unbalanced = TreeLayout::unbalanced(n);
+            let root = unbalanced.root::<SipHasher64>(leaves.clone());
+            let tree = MerkleTree::<SipHasher64>::from_leaf_digests_with_shape(leaves.clone(), TreeShape::Unbalanced);
+            assert_eq!(root, tree.root());
+            assert_eq!(root, calculate_merkle_root_with_shape::<SipHasher64>(leaves.clone(), TreeShape::Unbalanced));
+            assert_eq!(root, tree.root_at(n));
+            assert!(validate_proof_for_digest::<SipHasher64>(&root, leaves[n / 2], tree.proof(n / 2)));
+            assert!(validate_compact_multiproof_with_shape::<SipHasher64>(
+                &root,
+                proven,
+                tree.multiproof(&indices),
+                TreeShape::Unbalanced
+            ));
+        }
+    }
+}
@@ -4,21 +4,89 @@
 #![allow(unused_variables)]
 #![allow(unused_imports)]
 
-use hex::encode;
+mod bitcoin;
+mod builder;
+mod cdc;
+mod chunking;
+mod compact;
+mod consistency;
+mod ct;
+mod encoding;
+mod error;
+mod hasher;
+mod kary;
+mod layout;
+mod mmr;
+mod openzeppelin;
+mod parallel;
+mod range;
+mod sparse;
+mod sum;
+mod tree;
+
+pub use bitcoin::{
+    bitcoin_merkle_root, header_merkle_root, txid_from_hex, txid_to_hex, DoubleSha256Hasher, MatchedTransactions,
+    PartialMerkleTree,
+};
+pub use builder::MerkleBuilder;
+pub use cdc::{ContentChunks, FastCdc};
+pub use chunking::{verify_byte_range, verify_chunk, ChunkedFile, DEFAULT_CHUNK_SIZE};
+pub use compact::{
+    generate_compact_proof, try_generate_compact_proof, validate_compact_proof, validate_compact_proof_for_digest,
+    validate_compact_proof_with_shape, CompactMerkleProof,
+};
+pub use consistency::{verify_consistency, ConsistencyProof};
+pub use ct::{
+    verify_inclusion, ConsistencyProofDataV2, CtHasher, DigitallySigned, GetProofByHashResponse, GetSthConsistencyResponse,
+    InclusionProof, InclusionProofDataV2, SignedTreeHead, SignedTreeHeadDataV2, TreeHeadDataV2,
+};
+pub use encoding::{
+    kary_multiproof_from_bytes, kary_multiproof_to_bytes, kary_proof_from_bytes, kary_proof_to_bytes, proof_from_bytes,
+    proof_to_bytes, PROOF_FORMAT_VERSION,
+};
+pub use error::MerkleError;
+pub use hasher::{
+    Blake3Hasher, DomainSeparated, Keccak256Hasher, MerkleDigest, MerkleHasher, Sha256Hasher, Sha512_256Hasher,
+    SipHasher64, LEAF_PREFIX, NODE_PREFIX,
+};
+pub use mmr::{
+    bag_peaks, verify_mmr_consistency, verify_mmr_proof, verify_mmr_proof_for_digest, MerkleMountainRange,
+    MmrConsistencyProof, MmrProof,
+};
+pub use openzeppelin::{
+    verify_openzeppelin_multiproof, verify_openzeppelin_proof, OpenZeppelinHasher, OpenZeppelinMultiProof,
+    OpenZeppelinTree,
+};
+pub use range::{verify_range, verify_range_for_digests, verify_range_with_shape, RangeProof};
+pub use sparse::{
+    verify_sparse_inclusion, verify_sparse_inclusion_for_digest, verify_sparse_non_inclusion,
+    SparseKey, SparseMerkleProof, SparseMerkleTree, SPARSE_TREE_DEPTH,
+};
+pub use sum::{
+    verify_sum_multiproof, verify_sum_multiproof_with_shape, verify_sum_proof, verify_sum_proof_with_shape,
+    MerkleSumProof, SumHasher, SumNode,
+};
+pub use kary::{
+    calculate_kary_merkle_root, verify_kary_multiproof, verify_kary_proof, Arity, CompactKaryProof, KaryMerkleTree,
+    KaryProof, KarySiblings,
+};
+pub use layout::TreeLayout;
+pub use parallel::PARALLEL_THRESHOLD;
+pub use tree::{MerkleTree, TreeShape};
+
 use rand::SeedableRng;
 use std::{
     collections::hash_map::DefaultHasher,
     hash::{Hash, Hasher},
     mem,
 };
+use error::{check_index, check_indices};
+use layout::parent;
 use serde::{Serialize, Deserialize};
 
-/// We'll use Rust's built-in hashing which returns a u64 type.
-/// This alias just helps us understand when we're treating the number as a hash
-pub type HashValue = u64;
-
-/// Helper function that makes the hashing interface easier to understand.
-pub fn hash<T: Hash>(t: &T) -> HashValue {
+/// Helper function that makes Rust's built-in hashing easier to use. This is the `u64` hash
+/// behind [`SipHasher64`]; it is not suitable for integrity checks.
+pub fn hash<T: Hash>(t: &T) -> u64 {
     let mut s = DefaultHasher::new();
     t.hash(&mut s);
     s.finish()
@@ -47,32 +115,40 @@ pub fn pad_base_layer(blocks: &mut Vec<&str>) {
     }
 }
 
+/// Same as [`pad_base_layer`] for leaves that are already hashed: pads with the hash of the empty
+/// leaf, so the result is the same as padding the data and hashing afterwards.
+pub fn pad_leaf_digests<H: MerkleHasher>(digests: &mut Vec<H::Digest>) {
+    let empty = H::hash_leaf(&[]);
+    while !is_power_of_two(digests.len()) {
+        digests.push(empty);
+    }
+}
+
+/// Hashes every data block into a leaf node.
+fn hash_leaves<H: MerkleHasher, T: AsRef<[u8]>>(leaves: &[T]) -> Vec<H::Digest> {
+    // Borrowing the bytes first lets the blocks be hashed across threads even if `T` is not `Sync`
+    let leaves: Vec<&[u8]> = leaves.iter().map(AsRef::as_ref).collect();
+    parallel::map_indices(leaves.len(), |i| H::hash_leaf(leaves[i]))
+}
+
 /// Helper function to combine two hashes and compute the hash of the combination.
 /// This will be useful when building the intermediate nodes in the Merkle tree.
 ///
-/// Our implementation will hex-encode the hashes (as little-endian uints) into strings, concatenate
-/// the strings, and then hash that string.
-pub fn concatenate_hash_values(left: HashValue, right: HashValue) -> HashValue {
-    let combined = format!("{}{}", encode(left.to_le_bytes()), encode(right.to_le_bytes()));
-    hash(&combined)
+/// How the children are combined is up to the hasher, see [`MerkleHasher::hash_nodes`].
+pub fn concatenate_hash_values<H: MerkleHasher>(left: H::Digest, right: H::Digest) -> H::Digest {
+    H::hash_nodes(&left, &right)
 }
 
-fn calculate_merkle_root_rec(hashes: Vec<HashValue>) -> HashValue {
+fn calculate_merkle_root_rec<H: MerkleHasher>(hashes: Vec<H::Digest>) -> H::Digest {
     match hashes.len() {
-        0 => 0,
+        0 => H::Digest::default(),
         1 => hashes[0],
         _ => {
-            let mut parent_level_hashes = Vec::new();
-
-            for pair in hashes.chunks(2) {
-                match pair.len() {
-                    1 => parent_level_hashes.push(pair[0]),
-                    _ => parent_level_hashes.push(concatenate_hash_values(pair[0], pair[1])),
-                }
-            }
+            // A node without a sibling is promoted to the parent level unchanged
+            let parent_level_hashes = parallel::map_indices(hashes.len().div_ceil(2), |i| parent::<H>(&hashes, i));
 
             // Recursing on the upper level
-            calculate_merkle_root_rec(parent_level_hashes) 
+            calculate_merkle_root_rec::<H>(parent_level_hashes)
         }
     }
 }
@@ -85,34 +161,37 @@ fn calculate_merkle_root_rec(hashes: Vec<HashValue>) -> HashValue {
 /// "You", "trust", "me," "right?"
 /// Notice that the punctuation like the comma and exclamation point are included in the words
 /// but the spaces are not.
-pub fn calculate_merkle_root(sentence: &str) -> HashValue {
+pub fn calculate_merkle_root<H: MerkleHasher>(sentence: &str) -> H::Digest {
     //todo!()
     // Spliting sentence where there are spaces
-    let mut words: Vec<&str> = sentence.split_whitespace().collect();
+    let words: Vec<&str> = sentence.split_whitespace().collect();
 
-    // Nb of hashes is a 2^k number - adding empty strings to the base layer
-    pad_base_layer(&mut words);
-    
-    // Computing the hash of each word
-    let hashes: Vec<HashValue> = words.iter().map(|word| hash(word)).collect();
-    
-    //println!("Hashes len:: {:?}", hashes);
-    
-    // Calculating Merkle root recursively
-    calculate_merkle_root_rec(hashes)
+    calculate_merkle_root_from_leaves::<H, _>(&words)
+}
+
+/// Calculates the Merkle root of a list of data blocks, one leaf per block. The blocks can be any
+/// bytes, e.g. `&[u8]`, `Vec<u8>` or `&str`.
+pub fn calculate_merkle_root_from_leaves<H: MerkleHasher, T: AsRef<[u8]>>(leaves: &[T]) -> H::Digest {
+    calculate_merkle_root_from_digests::<H>(hash_leaves::<H, T>(leaves))
+}
+
+/// Calculates the Merkle root of leaves that were already hashed with [`MerkleHasher::hash_leaf`].
+pub fn calculate_merkle_root_from_digests<H: MerkleHasher>(hashes: Vec<H::Digest>) -> H::Digest {
+    // Nb of hashes is a 2^k number - the layout adds empty leaves to the base layer
+    TreeLayout::padded(hashes.len()).root::<H>(hashes)
 }
 
 /// A representation of a sibling node along the Merkle path from the data
 /// to the root. It is necessary to specify which side the sibling is on
 /// so that the hash values can be combined in the same order.
 #[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
-pub enum SiblingNode {
-    Left(HashValue),
-    Right(HashValue),
+pub enum SiblingNode<D> {
+    Left(D),
+    Right(D),
 }
 
 /// A proof is just an alias for a vec of sibling nodes.
-pub type MerkleProof = Vec<SiblingNode>;
+pub type MerkleProof<D> = Vec<SiblingNode<D>>;
 
 /// Generates a Merkle proof that one particular word is contained
 /// in the given sentence. You provide the sentence and the index of the word
@@ -121,73 +200,168 @@ pub type MerkleProof = Vec<SiblingNode>;
 /// Panics if the index is beyond the length of the sentence.
 ///
 /// Example: I want to prove that the word "trust" is in the sentence "You trust me, right?"
-/// So I call generate_proof("You trust me, right?", 1)
+/// So I call generate_proof::<Sha256Hasher>("You trust me, right?", 1)
 /// And I get back the merkle root and list of intermediate nodes from which the
 /// root can be reconstructed.
-pub fn generate_proof(sentence: &str, index: usize) -> (HashValue, MerkleProof) {
+pub fn generate_proof<H: MerkleHasher>(sentence: &str, index: usize) -> (H::Digest, MerkleProof<H::Digest>) {
     //todo!()
-    let mut words: Vec<&str> = sentence.split_whitespace().collect();
-    pad_base_layer(&mut words);
-    
-    let mut hashes: Vec<HashValue> = words.iter().map(|word| hash(word)).collect();
-    
-    let mut proof = Vec::new();
-    let mut idx = index;
+    try_generate_proof::<H>(sentence, index).unwrap_or_else(|err| panic!("{}", err))
+}
 
-    while hashes.len() > 1 {
-        let is_right_sibling = idx % 2 == 0;
-        let sibling_idx = if is_right_sibling { idx + 1 } else { idx - 1 };
+/// Same as [`generate_proof`], but returns an error instead of panicking.
+pub fn try_generate_proof<H: MerkleHasher>(
+    sentence: &str,
+    index: usize,
+) -> Result<(H::Digest, MerkleProof<H::Digest>), MerkleError> {
+    let words: Vec<&str> = sentence.split_whitespace().collect();
+    try_generate_proof_from_leaves::<H, _>(&words, index)
+}
 
-        if is_right_sibling {
-            proof.push(SiblingNode::Right(hashes[sibling_idx]));
-        } else {
-            proof.push(SiblingNode::Left(hashes[sibling_idx]));
-        }
+/// Generates a Merkle proof for the data block at `index` in `leaves`.
+///
+/// Panics if the index is beyond the number of leaves.
+pub fn generate_proof_from_leaves<H: MerkleHasher, T: AsRef<[u8]>>(
+    leaves: &[T],
+    index: usize,
+) -> (H::Digest, MerkleProof<H::Digest>) {
+    try_generate_proof_from_leaves::<H, T>(leaves, index).unwrap_or_else(|err| panic!("{}", err))
+}
 
-        idx /= 2;
+/// Same as [`generate_proof_from_leaves`], but returns an error instead of panicking.
+pub fn try_generate_proof_from_leaves<H: MerkleHasher, T: AsRef<[u8]>>(
+    leaves: &[T],
+    index: usize,
+) -> Result<(H::Digest, MerkleProof<H::Digest>), MerkleError> {
+    check_index(index, leaves.len())?;
+    try_generate_proof_from_digests::<H>(hash_leaves::<H, T>(leaves), index)
+}
 
-        let mut next_level = Vec::new();
-        for pair in hashes.chunks(2) {
-            next_level.push(concatenate_hash_values(pair[0], pair[1]));
-        }
-        hashes = next_level;
-    }
+/// Generates a Merkle proof for the leaf at `index` of leaves that were already hashed.
+///
+/// Panics if the index is beyond the number of leaves.
+pub fn generate_proof_from_digests<H: MerkleHasher>(
+    hashes: Vec<H::Digest>,
+    index: usize,
+) -> (H::Digest, MerkleProof<H::Digest>) {
+    try_generate_proof_from_digests::<H>(hashes, index).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Same as [`generate_proof_from_digests`], but returns an error instead of panicking.
+pub fn try_generate_proof_from_digests<H: MerkleHasher>(
+    hashes: Vec<H::Digest>,
+    index: usize,
+) -> Result<(H::Digest, MerkleProof<H::Digest>), MerkleError> {
+    check_index(index, hashes.len())?;
 
-    (hashes[0], proof)
+    let layout = TreeLayout::padded(hashes.len());
+    let levels = layout.build_levels::<H>(hashes);
+    Ok((levels[layout.height()][0], layout.proof::<H>(&levels, index)))
 }
 
 /// Checks whether the given word is contained in a sentence, without knowing the whole sentence.
 /// Rather we only know the merkle root of the sentence and a proof.
-pub fn validate_proof(root: &HashValue, word: &str, proof: MerkleProof) -> bool {
+pub fn validate_proof<H: MerkleHasher>(root: &H::Digest, word: &str, proof: MerkleProof<H::Digest>) -> bool {
     //todo!()
-    let mut hash = hash(&word);
+    validate_proof_for_leaf::<H>(root, word.as_bytes(), proof)
+}
+
+/// Checks whether the given data block is contained in the tree with the given root.
+pub fn validate_proof_for_leaf<H: MerkleHasher>(
+    root: &H::Digest,
+    leaf: &[u8],
+    proof: MerkleProof<H::Digest>,
+) -> bool {
+    validate_proof_for_digest::<H>(root, H::hash_leaf(leaf), proof)
+}
+
+/// Checks whether the given leaf hash is contained in the tree with the given root.
+pub fn validate_proof_for_digest<H: MerkleHasher>(
+    root: &H::Digest,
+    leaf_hash: H::Digest,
+    proof: MerkleProof<H::Digest>,
+) -> bool {
+    root_from_proof::<H>(leaf_hash, &proof) == *root
+}
+
+/// Hashes the leaf with the siblings of the proof, from the leaf up.
+fn root_from_proof<H: MerkleHasher>(leaf_hash: H::Digest, proof: &MerkleProof<H::Digest>) -> H::Digest {
+    let mut hash = leaf_hash;
 
     for node in proof {
-        hash = match node {
-            SiblingNode::Left(sibling_hash) => concatenate_hash_values(sibling_hash, hash),
-            SiblingNode::Right(sibling_hash) => concatenate_hash_values(hash, sibling_hash),
+        hash = match *node {
+            SiblingNode::Left(sibling_hash) => concatenate_hash_values::<H>(sibling_hash, hash),
+            SiblingNode::Right(sibling_hash) => concatenate_hash_values::<H>(hash, sibling_hash),
         };
     }
 
-    hash == *root
+    hash
+}
+
+/// Checks that `leaf` is the data block at `index` of a tree of `tree_size` leaves with the given
+/// root.
+///
+/// Unlike [`validate_proof_for_leaf`], which accepts any directions that happen to lead to the
+/// root, this checks that the proof has one sibling per level of the tree and that each one is on
+/// the side the index requires, so a proof for one position cannot be passed off as a proof for
+/// another. The error says which check failed.
+pub fn verify_at<H: MerkleHasher>(
+    root: &H::Digest,
+    index: usize,
+    tree_size: usize,
+    leaf: &[u8],
+    proof: &MerkleProof<H::Digest>,
+) -> Result<(), MerkleError> {
+    verify_digest_at::<H>(root, index, tree_size, H::hash_leaf(leaf), proof)
+}
+
+/// Same as [`verify_at`], for a leaf that was already hashed.
+pub fn verify_digest_at<H: MerkleHasher>(
+    root: &H::Digest,
+    index: usize,
+    tree_size: usize,
+    leaf_hash: H::Digest,
+    proof: &MerkleProof<H::Digest>,
+) -> Result<(), MerkleError> {
+    verify_digest_at_with_shape::<H>(root, index, tree_size, leaf_hash, proof, TreeShape::Padded)
+}
+
+/// Same as [`verify_digest_at`], for a tree of the given shape. An unbalanced tree has no sibling
+/// for a promoted node, so the proof is shorter there.
+pub fn verify_digest_at_with_shape<H: MerkleHasher>(
+    root: &H::Digest,
+    index: usize,
+    tree_size: usize,
+    leaf_hash: H::Digest,
+    proof: &MerkleProof<H::Digest>,
+    shape: TreeShape,
+) -> Result<(), MerkleError> {
+    check_index(index, tree_size)?;
+    TreeLayout::new(tree_size, shape).check_proof(index, proof)?;
+    if root_from_proof::<H>(leaf_hash, proof) != *root {
+        return Err(MerkleError::RootMismatch);
+    }
+    Ok(())
 }
 
 /// A compact Merkle multiproof is used to prove multiple entries in a Merkle tree in a highly
 /// space-efficient manner.
-#[derive(Debug, PartialEq, Eq)]
-pub struct CompactMerkleMultiProof {
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct CompactMerkleMultiProof<D> {
     // The indices requested in the initial proof generation
     pub leaf_indices: Vec<usize>,
+    // The number of leaves in the tree, not counting padding, which gives its layout
+    pub tree_size: usize,
     // The additional hashes necessary for computing the proof, given in order from
     // lower to higher index, lower in the tree to higher in the tree.
-    pub hashes: Vec<HashValue>,
+    pub hashes: Vec<D>,
 }
 
 /// Generate a compact multiproof that some words are contained in the given sentence. Returns the
 /// root of the merkle tree, and the compact multiproof. You provide the words at `indices` in the
 /// same order as within `indices` to verify the proof. `indices` is not necessarily sorted.
 ///
-/// Panics if any index is beyond the length of the sentence, or any index is duplicated.
+/// Panics if no index is given, any index is beyond the length of the sentence, or any index is
+/// duplicated. See [`try_generate_compact_multiproof`] for a version that returns an error instead.
 ///
 /// ## Explanation
 ///
@@ -226,13 +400,14 @@ pub struct CompactMerkleMultiProof {
 /// ```ignore
 /// CompactMerkleMultiProof {
 ///     leaf_indices: [0, 1, 6],
+///     tree_size: 8,
 ///     hashes: [H_0, H_1, H_2]
 /// }
 /// ```
-pub fn generate_compact_multiproof(
+pub fn generate_compact_multiproof<H: MerkleHasher>(
     sentence: &str,
     indices: Vec<usize>,
-) -> (HashValue, CompactMerkleMultiProof) {
+) -> (H::Digest, CompactMerkleMultiProof<H::Digest>) {
     //todo!()
 
     // For each of the indices, takes the index of its immediate neighbor, 
@@ -240,78 +415,93 @@ pub fn generate_compact_multiproof(
     // looks at the differences between pair indices and indices
     // appends the hash for given values to the multiproof
 
-    let words: Vec<&str> = sentence.split_whitespace().collect();
-
-    // Panics if any index is beyond the length of the sentence, or any index is duplicated.
-    for &index in &indices {
-        if index >= words.len() {
-            panic!("Index {} is out of bounds", index);
-        }
-    }
-
-    // Hashes the words into leaf nodes
-    let mut nodes: Vec<HashValue> = words.iter().map(|&word| hash(&word)).collect();
-    let mut hashes = Vec::new();
-    let mut leaf_indices = indices.clone();
-
-    // Builds the tree, layer by layer, remembering which hashes are needed for the proof
-    while nodes.len() > 1 {
-        let mut next_level_nodes = Vec::new();
-        let mut next_level_indices = Vec::new();
-
-        for i in 0..nodes.len() / 2 {
-            let left_child = i * 2;
-            let right_child = left_child + 1;
+    try_generate_compact_multiproof::<H>(sentence, indices).unwrap_or_else(|err| panic!("{}", err))
+}
 
-            // Compute the new hash for this node
-            let new_hash = concatenate_hash_values(nodes[left_child], nodes[right_child]);
-            next_level_nodes.push(new_hash);
+/// Same as [`generate_compact_multiproof`], but returns an error instead of panicking.
+pub fn try_generate_compact_multiproof<H: MerkleHasher>(
+    sentence: &str,
+    indices: Vec<usize>,
+) -> Result<(H::Digest, CompactMerkleMultiProof<H::Digest>), MerkleError> {
+    let words: Vec<&str> = sentence.split_whitespace().collect();
+    try_generate_compact_multiproof_from_leaves::<H, _>(&words, indices)
+}
 
-            // If either child is in the index set, this node's index needs to be in the next level's index set
-            if leaf_indices.contains(&left_child) || leaf_indices.contains(&right_child) {
-                next_level_indices.push(i);
-            }
+/// Generate a compact multiproof that the data blocks at `indices` are contained in `leaves`.
+///
+/// Panics if no index is given, any index is beyond the number of leaves, or any index is
+/// duplicated.
+pub fn generate_compact_multiproof_from_leaves<H: MerkleHasher, T: AsRef<[u8]>>(
+    leaves: &[T],
+    indices: Vec<usize>,
+) -> (H::Digest, CompactMerkleMultiProof<H::Digest>) {
+    try_generate_compact_multiproof_from_leaves::<H, T>(leaves, indices).unwrap_or_else(|err| panic!("{}", err))
+}
 
-            // If the right child's hash is known but the left child's is not, include the right child's hash in the proof
-            if leaf_indices.contains(&right_child) && !leaf_indices.contains(&left_child) {
-                hashes.push(nodes[left_child]);
-            }
+/// Same as [`generate_compact_multiproof_from_leaves`], but returns an error instead of panicking.
+pub fn try_generate_compact_multiproof_from_leaves<H: MerkleHasher, T: AsRef<[u8]>>(
+    leaves: &[T],
+    indices: Vec<usize>,
+) -> Result<(H::Digest, CompactMerkleMultiProof<H::Digest>), MerkleError> {
+    check_indices(&indices, leaves.len())?;
+    try_generate_compact_multiproof_from_digests::<H>(hash_leaves::<H, T>(leaves), indices)
+}
 
-            // And vice versa
-            if leaf_indices.contains(&left_child) && !leaf_indices.contains(&right_child) {
-                hashes.push(nodes[right_child]);
-            }
-        }
+/// Generate a compact multiproof for the leaves at `indices` of leaves that were already hashed.
+///
+/// Panics if no index is given, any index is beyond the number of leaves, or any index is
+/// duplicated.
+pub fn generate_compact_multiproof_from_digests<H: MerkleHasher>(
+    nodes: Vec<H::Digest>,
+    indices: Vec<usize>,
+) -> (H::Digest, CompactMerkleMultiProof<H::Digest>) {
+    try_generate_compact_multiproof_from_digests::<H>(nodes, indices).unwrap_or_else(|err| panic!("{}", err))
+}
 
-        //println!("Nodes: {:?}", nodes);
+/// Same as [`generate_compact_multiproof_from_digests`], but returns an error instead of
+/// panicking.
+pub fn try_generate_compact_multiproof_from_digests<H: MerkleHasher>(
+    nodes: Vec<H::Digest>,
+    indices: Vec<usize>,
+) -> Result<(H::Digest, CompactMerkleMultiProof<H::Digest>), MerkleError> {
+    let known = check_indices(&indices, nodes.len())?;
 
-        nodes = next_level_nodes;
-        leaf_indices = next_level_indices;
-    }
+    // Builds the tree, layer by layer, then picks the hashes the verifier cannot compute
+    let layout = TreeLayout::padded(nodes.len());
+    let levels = layout.build_levels::<H>(nodes);
+    let hashes = layout.multiproof_hashes::<H>(&levels, known);
 
     // The root of the tree is the remaining node
-    let root = nodes[0];
+    let root = levels[layout.height()][0];
 
     // The proof consists of the original indices and the hashes we collected
     let proof = CompactMerkleMultiProof {
         leaf_indices: indices,
+        tree_size: layout.len(),
         hashes,
     };
 
-    //println!("Root: {:?}", root);
-
-    (root, proof)
-
+    Ok((root, proof))
 }
 
 /// Validate a compact merkle multiproof to check whether a list of words is contained in a sentence, based on the merkle root of the sentence.
 /// The words must be in the same order as the indices passed in to generate the multiproof.
 /// Duplicate indices in the proof are rejected by returning false.
-pub fn validate_compact_multiproof(
-    root: &HashValue,
+pub fn validate_compact_multiproof<H: MerkleHasher>(
+    root: &H::Digest,
     words: Vec<&str>,
-    proof: CompactMerkleMultiProof,
+    proof: CompactMerkleMultiProof<H::Digest>,
 ) -> bool {
+    try_validate_compact_multiproof::<H>(root, words, proof).unwrap_or(false)
+}
+
+/// Same as [`validate_compact_multiproof`], but tells a malformed proof apart from one that is
+/// well-formed and does not match the root: the former is an error, the latter `Ok(false)`.
+pub fn try_validate_compact_multiproof<H: MerkleHasher>(
+    root: &H::Digest,
+    words: Vec<&str>,
+    proof: CompactMerkleMultiProof<H::Digest>,
+) -> Result<bool, MerkleError> {
     //todo!()
     // Step 1. recunstruct the merkle tree from the given words and proof:
     //  - for each indices take the index of its immediate neighbor
@@ -323,98 +513,94 @@ pub fn validate_compact_multiproof(
     //  - repeat
     // Step 2. compare the given root with the root of the reconstructed tree
 
-    // Step 1: Reconstruct the Merkle tree from the given words and proof
+    try_validate_compact_multiproof_for_leaves::<H, _>(root, &words, proof)
+}
 
-    // Compute hashes for the provided words
-    let mut nodes: Vec<HashValue> = words.iter().map(|&word| hash(&word)).collect();
-    let mut leaf_indices = proof.leaf_indices;
-    let mut proof_hashes = proof.hashes;
+/// Validate a compact merkle multiproof for a list of data blocks, given in the same order as the
+/// indices passed in to generate the multiproof.
+pub fn validate_compact_multiproof_for_leaves<H: MerkleHasher, T: AsRef<[u8]>>(
+    root: &H::Digest,
+    leaves: &[T],
+    proof: CompactMerkleMultiProof<H::Digest>,
+) -> bool {
+    try_validate_compact_multiproof_for_leaves::<H, T>(root, leaves, proof).unwrap_or(false)
+}
 
-    // Check for duplicate indices
-    let mut seen = std::collections::HashSet::new();
-    for &index in &leaf_indices {
-        if !seen.insert(index) {
-            return false; // Duplicate index found
-        }
-    }
+/// Same as [`validate_compact_multiproof_for_leaves`], but returns an error for malformed proofs.
+pub fn try_validate_compact_multiproof_for_leaves<H: MerkleHasher, T: AsRef<[u8]>>(
+    root: &H::Digest,
+    leaves: &[T],
+    proof: CompactMerkleMultiProof<H::Digest>,
+) -> Result<bool, MerkleError> {
+    try_validate_compact_multiproof_for_digests::<H>(root, hash_leaves::<H, T>(leaves), proof)
+}
 
-    // Return false if the lengths of leaf_indices and nodes don't match
-    if leaf_indices.len() != nodes.len() {
-        return false;
-    }
-
-    // Max leaf index to control loop
-    let mut max_leaf_index: usize = *leaf_indices.iter().max().unwrap();
-
-    // Process the levels of the Merkle tree
-    while !proof_hashes.is_empty() || max_leaf_index > 1 {
-        let mut next_level_nodes = Vec::new();
-        let mut next_level_indices = Vec::new();
-
-        max_leaf_index = *leaf_indices.iter().max().unwrap_or(&0);
-
-        for i in 0..=max_leaf_index {
-            let left_child = i * 2;
-            let right_child = left_child + 1;
-
-            match (leaf_indices.contains(&left_child), leaf_indices.contains(&right_child)) {
-                (false, false) => continue,
-                (true, false) => {
-                     // Ensure the left child index is valid
-                    if let Some(left_child_index) = leaf_indices.iter().position(|&x| x == left_child) {
-                        let left_hash = nodes[left_child_index];
-                        if !proof_hashes.is_empty() {
-                            let right_hash = proof_hashes.remove(0);
-                            next_level_nodes.push(concatenate_hash_values(left_hash, right_hash));
-                        } else {
-                            next_level_indices.push(i);
-                        }
-                    } else {
-                        return false; // Invalid left child index
-                    }
-                },
-                (false, true) => {
-                    if let Some(right_child_index) = leaf_indices.iter().position(|&x| x == right_child) {
-                        if !proof_hashes.is_empty() {
-                            let left_hash = proof_hashes.remove(0);
-                            let right_hash = nodes[right_child_index];
-                            next_level_nodes.push(concatenate_hash_values(left_hash, right_hash));
-                        } else {
-                            return false;
-                        }
-                    } else {
-                        return false;
-                    }
-                },
-                (true, true) => {
-                    if let (Some(left_child_index), Some(right_child_index)) = (
-                        leaf_indices.iter().position(|&x| x == left_child),
-                        leaf_indices.iter().position(|&x| x == right_child),
-                    ) {
-                        if left_child_index < nodes.len() && right_child_index < nodes.len() {
-                            let left_hash = nodes[left_child_index];
-                            let right_hash = nodes[right_child_index];
-                            next_level_nodes.push(concatenate_hash_values(left_hash, right_hash));
-                        } else {
-                            return false;
-                        }
-                    } else {
-                        return false;
-                    }
-                },
-            };
-        
-            next_level_indices.push(i);
-        }
+/// Validate a compact merkle multiproof for a list of leaf hashes, given in the same order as the
+/// indices passed in to generate the multiproof.
+pub fn validate_compact_multiproof_for_digests<H: MerkleHasher>(
+    root: &H::Digest,
+    nodes: Vec<H::Digest>,
+    proof: CompactMerkleMultiProof<H::Digest>,
+) -> bool {
+    try_validate_compact_multiproof_for_digests::<H>(root, nodes, proof).unwrap_or(false)
+}
 
-        nodes = next_level_nodes;
-        leaf_indices = next_level_indices;
+/// Same as [`validate_compact_multiproof_for_digests`], but returns an error for malformed
+/// proofs.
+///
+/// The (index, hash) pairs are sorted once and then walked up the tree level by level: two known
+/// siblings are combined, any other node takes the next proof hash as its sibling. This takes
+/// O(k log n) hashes and comparisons for k leaves in a tree of n leaves.
+pub fn try_validate_compact_multiproof_for_digests<H: MerkleHasher>(
+    root: &H::Digest,
+    nodes: Vec<H::Digest>,
+    proof: CompactMerkleMultiProof<H::Digest>,
+) -> Result<bool, MerkleError> {
+    try_validate_compact_multiproof_with_shape::<H>(root, nodes, proof, TreeShape::Padded)
+}
+
+/// Calculates the Merkle root of already hashed leaves in a tree of the given shape. For
+/// [`TreeShape::Padded`] this is the same as [`calculate_merkle_root_from_digests`].
+pub fn calculate_merkle_root_with_shape<H: MerkleHasher>(
+    hashes: Vec<H::Digest>,
+    shape: TreeShape,
+) -> H::Digest {
+    TreeLayout::new(hashes.len(), shape).root::<H>(hashes)
+}
+
+/// Validate a compact merkle multiproof for a list of leaf hashes in a tree of the given shape, as
+/// generated by [`MerkleTree::multiproof`]. The tree size in the proof tells the verifier which
+/// nodes have no sibling and were promoted without a proof hash.
+pub fn validate_compact_multiproof_with_shape<H: MerkleHasher>(
+    root: &H::Digest,
+    leaf_hashes: Vec<H::Digest>,
+    proof: CompactMerkleMultiProof<H::Digest>,
+    shape: TreeShape,
+) -> bool {
+    try_validate_compact_multiproof_with_shape::<H>(root, leaf_hashes, proof, shape).unwrap_or(false)
+}
+
+/// Same as [`validate_compact_multiproof_with_shape`], but tells a malformed proof apart from one
+/// that is well-formed and does not match the root: the former is an error, the latter `Ok(false)`.
+pub fn try_validate_compact_multiproof_with_shape<H: MerkleHasher>(
+    root: &H::Digest,
+    leaf_hashes: Vec<H::Digest>,
+    proof: CompactMerkleMultiProof<H::Digest>,
+    shape: TreeShape,
+) -> Result<bool, MerkleError> {
+    check_indices(&proof.leaf_indices, proof.tree_size)?;
+    if leaf_hashes.len() != proof.leaf_indices.len() {
+        return Err(MerkleError::ProofLengthMismatch {
+            expected: proof.leaf_indices.len(),
+            actual: leaf_hashes.len(),
+        });
     }
 
-    //println!("Root: {:?}", nodes[0]);
+    let mut known: Vec<(usize, H::Digest)> = proof.leaf_indices.into_iter().zip(leaf_hashes).collect();
+    known.sort_unstable_by_key(|&(index, _)| index);
 
-    // The root of the tree is the remaining node
-    nodes[0] == *root
+    let layout = TreeLayout::new(proof.tree_size, shape);
+    Ok(layout.multiproof_root::<H>(known, proof.hashes)? == *root)
 }
 
 // Now that we have a normal and compact method to generate proofs, let's compare how
@@ -434,42 +620,116 @@ pub fn string_of_random_words(n: usize) -> String {
     ret
 }
 
+/// The encoded sizes in bytes of the proofs for the same leaves in each form, as returned by
+/// [`compare_proof_sizes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProofSizes {
+    /// One compact multiproof for all of the leaves.
+    pub compact_multiproof: usize,
+    /// A [`MerkleProof`] per leaf, with the direction of every sibling.
+    pub proofs: usize,
+    /// A [`CompactMerkleProof`] per leaf, whose directions follow from its index.
+    pub compact_proofs: usize,
+}
+
 /// Given a string of words, and the length of the words from which to generate proofs, generate
 /// proofs for `num_proofs` random indices in `[0, length)`.  Uses `rng_seed` as the rng seed, if
 /// replicability is desired.
 ///
-/// Return the size of the compact multiproof, and then the combined size of the standard merkle proofs.
+/// Return the encoded sizes of the compact multiproof, and of the standard and compact proofs for
+/// each index combined.
 ///
 /// This function assumes the proof generation is correct, and does not validate them.
-pub fn compare_proof_sizes(
+pub fn compare_proof_sizes<H: MerkleHasher>(
     words: &str,
     length: usize,
     num_proofs: usize,
     rng_seed: u64,
-) -> (usize, usize) {
+) -> ProofSizes {
     assert!(
         num_proofs <= length,
         "Cannot make more proofs than available indices!"
     );
+    try_compare_proof_sizes::<H>(words, length, num_proofs, rng_seed).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Same as [`compare_proof_sizes`], but returns an error instead of panicking, e.g. if `length`
+/// is beyond the number of words.
+pub fn try_compare_proof_sizes<H: MerkleHasher>(
+    words: &str,
+    length: usize,
+    num_proofs: usize,
+    rng_seed: u64,
+) -> Result<ProofSizes, MerkleError> {
+    if num_proofs > length {
+        return Err(MerkleError::SizeOutOfRange { size: num_proofs, len: length });
+    }
 
     let mut rng = rand::rngs::SmallRng::seed_from_u64(rng_seed);
     let indices = rand::seq::index::sample(&mut rng, length, num_proofs).into_vec();
-    let (_, compact_proof) = generate_compact_multiproof(words, indices.clone());
-    // Manually calculate memory sizes
-    let compact_size = mem::size_of::<usize>() * compact_proof.leaf_indices.len()
-        + mem::size_of::<HashValue>() * compact_proof.hashes.len()
-        + mem::size_of::<Vec<usize>>() * 2;
+    let (_, compact_proof) = try_generate_compact_multiproof::<H>(words, indices.clone())?;
+    let mut sizes = ProofSizes {
+        compact_multiproof: compact_proof.to_bytes::<H>().len(),
+        proofs: 0,
+        compact_proofs: 0,
+    };
 
-    let mut individual_size = 0;
+    let tree_size = words.split_whitespace().count();
     for i in indices {
-        //println!("Index: {:?}", i);
-        //println!("Words: {:?}", words);
-        let (_, proof) = generate_proof(words, i);
-        individual_size +=
-            mem::size_of::<Vec<usize>>() + mem::size_of::<SiblingNode>() * proof.len();
+        let (_, proof) = try_generate_proof::<H>(words, i)?;
+        sizes.compact_proofs += CompactMerkleProof::from_proof(&proof, i, tree_size)?.to_bytes::<H>().len();
+        sizes.proofs += proof_to_bytes::<H>(&proof, tree_size).len();
     }
 
-    (compact_size, individual_size)
+    Ok(sizes)
+}
+
+/// Same as [`compare_proof_sizes`], for a tree of the given arity: the multiproof is the one of
+/// [`KaryMerkleTree::multiproof`], the proofs are [`KaryProof`]s with `k - 1` siblings and a
+/// position per level, and the compact proofs are [`CompactKaryProof`]s with the same siblings and
+/// no positions, which follow from the index. Every k-ary encoding carries its arity, so for
+/// [`Arity::Two`] each proof is one byte larger than in [`compare_proof_sizes`]. Calling this for every arity in [`Arity::ALL`] shows which one gives
+/// the smallest proofs.
+pub fn compare_proof_sizes_with_arity<H: MerkleHasher>(
+    words: &str,
+    length: usize,
+    num_proofs: usize,
+    rng_seed: u64,
+    arity: Arity,
+) -> ProofSizes {
+    try_compare_proof_sizes_with_arity::<H>(words, length, num_proofs, rng_seed, arity)
+        .unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Same as [`compare_proof_sizes_with_arity`], but returns an error instead of panicking.
+pub fn try_compare_proof_sizes_with_arity<H: MerkleHasher>(
+    words: &str,
+    length: usize,
+    num_proofs: usize,
+    rng_seed: u64,
+    arity: Arity,
+) -> Result<ProofSizes, MerkleError> {
+    if num_proofs > length {
+        return Err(MerkleError::SizeOutOfRange { size: num_proofs, len: length });
+    }
+
+    let words: Vec<&str> = words.split_whitespace().collect();
+    let tree = KaryMerkleTree::<H>::from_leaves(&words, arity);
+    let mut rng = rand::rngs::SmallRng::seed_from_u64(rng_seed);
+    let indices = rand::seq::index::sample(&mut rng, length, num_proofs).into_vec();
+    let mut sizes = ProofSizes {
+        compact_multiproof: kary_multiproof_to_bytes::<H>(&tree.try_multiproof(&indices)?, arity).len(),
+        proofs: 0,
+        compact_proofs: 0,
+    };
+
+    for i in indices {
+        let proof = tree.try_proof(i)?;
+        sizes.proofs += kary_proof_to_bytes::<H>(&proof, words.len(), arity)?.len();
+        sizes.compact_proofs += CompactKaryProof::from_proof(&proof, i, words.len(), arity)?.to_bytes::<H>().len();
+    }
+
+    Ok(sizes)
 }
 
 #[test]
@@ -489,11 +749,11 @@ fn student_test_to_compare_sizes() {
     let num_proofs = 3;
     let rng_seed = 12345678;
 
-    let (compact_size, individual_size) = compare_proof_sizes(&sentence, length, num_proofs, rng_seed);
-    println!("Compact size: {}", compact_size);
-    println!("Individual size: {}", individual_size);
+    let sizes = compare_proof_sizes::<SipHasher64>(&sentence, length, num_proofs, rng_seed);
+    println!("Compact size: {}", sizes.compact_multiproof);
+    println!("Individual size: {}", sizes.proofs);
 
-    assert!(compact_size <= individual_size);
+    assert!(sizes.compact_multiproof <= sizes.proofs);
     
 }
 
@@ -514,19 +774,19 @@ mod tests {
     fn concatenate_hash_values_sanity_check() {
         let left = hash(&"a");
         let right = hash(&"b");
-        assert_eq!(13491948173500414413, concatenate_hash_values(left, right));
+        assert_eq!(13491948173500414413, concatenate_hash_values::<SipHasher64>(left, right));
     }
 
     #[test]
     fn calculate_merkle_root_sanity_check() {
         let sentence = "You trust me, right?";
-        assert_eq!(4373588283528574023, calculate_merkle_root(sentence));
+        assert_eq!(4373588283528574023, calculate_merkle_root::<SipHasher64>(sentence));
     }
 
     #[test]
     fn proof_generation_sanity_check_2() {
         let sentence = "apex rite gite mite gleg meno merl nard bile ills hili";
-        generate_proof(sentence, 1);
+        generate_proof::<SipHasher64>(sentence, 1);
     }
 
     #[test]
@@ -539,7 +799,7 @@ mod tests {
                 SiblingNode::Right(2769272874327709143),
             ],
         );
-        assert_eq!(expected, generate_proof(sentence, 1));
+        assert_eq!(expected, generate_proof::<SipHasher64>(sentence, 1));
     }
 
     #[test]
@@ -550,13 +810,13 @@ mod tests {
             SiblingNode::Left(4099928055547683737),
             SiblingNode::Right(2769272874327709143),
         ];
-        assert!(validate_proof(&root, word, proof));
+        assert!(validate_proof::<SipHasher64>(&root, word, proof));
     }
 
     #[test]
     fn calculate_merkle_root_sanity_check_2() {
         let sentence = "You trust me?";
-        assert_eq!(8656240816105094750, calculate_merkle_root(sentence));
+        assert_eq!(8656240816105094750, calculate_merkle_root::<SipHasher64>(sentence));
     }
 
     #[test]
@@ -567,6 +827,7 @@ mod tests {
             14965309246218747603,
             CompactMerkleMultiProof {
                 leaf_indices: vec![0, 1, 6],
+                tree_size: 8,
                 hashes: vec![
                     1513025021886310739,
                     7640678380001893133,
@@ -574,7 +835,7 @@ mod tests {
                 ],
             },
         );
-        assert_eq!(expected, generate_compact_multiproof(sentence, indices));
+        assert_eq!(expected, generate_compact_multiproof::<SipHasher64>(sentence, indices));
     }
 
     #[test]
@@ -583,6 +844,7 @@ mod tests {
             14965309246218747603u64,
             CompactMerkleMultiProof {
                 leaf_indices: vec![0, 1, 6],
+                tree_size: 8,
                 hashes: vec![
                     1513025021886310739,
                     7640678380001893133,
@@ -591,7 +853,7 @@ mod tests {
             },
         );
         let words = vec!["Here's", "an", "for"];
-        assert_eq!(true, validate_compact_multiproof(&proof.0, words, proof.1));
+        assert!(validate_compact_multiproof::<SipHasher64>(&proof.0, words, proof.1));
     }
 
 }
@@ -604,7 +866,7 @@ mod additional_tests {
     #[test]
     fn test_single_word_merkle_root() {
         let sentence = "hello";
-        let root = calculate_merkle_root(sentence);
+        let root = calculate_merkle_root::<SipHasher64>(sentence);
         let expected_root = hash(&"hello");
         assert_eq!(root, expected_root);
     }
@@ -612,7 +874,7 @@ mod additional_tests {
     #[test]
     fn test_empty_string_merkle_root() {
         let sentence = "";
-        let root = calculate_merkle_root(sentence);
+        let root = calculate_merkle_root::<SipHasher64>(sentence);
         let expected_root = hash(&"");
         assert_eq!(root, expected_root);
     }
@@ -620,7 +882,7 @@ mod additional_tests {
     #[test]
     fn test_large_input_merkle_root() {
         let sentence = string_of_random_words(1024);
-        let root = calculate_merkle_root(&sentence);
+        let root = calculate_merkle_root::<SipHasher64>(&sentence);
         assert_ne!(root, 0);
     }
 
@@ -628,27 +890,27 @@ mod additional_tests {
     fn test_proof_for_last_word() {
         let sentence = "this is a test sentence with multiple words for merkle tree validation";
         let index = 11;
-        let (root, proof) = generate_proof(sentence, index);
+        let (root, proof) = generate_proof::<SipHasher64>(sentence, index);
         let word = "validation";
-        assert!(validate_proof(&root, word, proof));
+        assert!(validate_proof::<SipHasher64>(&root, word, proof));
     }
 
     #[test]
     fn test_generate_and_validate_proof() {
         let sentence = "the quick brown fox jumps over the lazy dog";
         for i in 0..9 {
-            let (root, proof) = generate_proof(sentence, i);
+            let (root, proof) = generate_proof::<SipHasher64>(sentence, i);
             let word = sentence.split_whitespace().nth(i).unwrap();
-            assert!(validate_proof(&root, word, proof));
+            assert!(validate_proof::<SipHasher64>(&root, word, proof));
         }
     }
 
     #[test]
     fn test_invalid_proof() {
         let sentence = "the quick brown fox jumps over the lazy dog";
-        let (root, proof) = generate_proof(sentence, 0);
+        let (root, proof) = generate_proof::<SipHasher64>(sentence, 0);
         let invalid_word = "invalid";
-        assert!(!validate_proof(&root, invalid_word, proof));
+        assert!(!validate_proof::<SipHasher64>(&root, invalid_word, proof));
     }
 
     #[test]
@@ -656,18 +918,17 @@ mod additional_tests {
         let sentence = "this is another test sentence for multiproof validation";
         let indices = vec![1, 3, 6];
         let words = vec!["is", "test", "multiproof"];
-        let (root, multiproof) = generate_compact_multiproof(sentence, indices.clone());
-        assert!(validate_compact_multiproof(&root, words, multiproof));
+        let (root, multiproof) = generate_compact_multiproof::<SipHasher64>(sentence, indices.clone());
+        assert!(validate_compact_multiproof::<SipHasher64>(&root, words, multiproof));
     }
 
     #[test]
     fn test_invalid_multiproof() {
         let sentence = "this is another test sentence for multiproof validation";
         let indices = vec![1, 3, 6];
-        let words = vec!["is", "test", "multiproof"];
-        let (root, multiproof) = generate_compact_multiproof(sentence, indices.clone());
+        let (root, multiproof) = generate_compact_multiproof::<SipHasher64>(sentence, indices.clone());
         let invalid_words = vec!["invalid", "multiproof", "random"];
-        assert!(!validate_compact_multiproof(&root, invalid_words, multiproof));
+        assert!(!validate_compact_multiproof::<SipHasher64>(&root, invalid_words, multiproof));
     }
 
     #[test]
@@ -675,8 +936,8 @@ mod additional_tests {
         let sentence = "edge case with only one word";
         let indices = vec![0];
         let words = vec!["edge"];
-        let (root, multiproof) = generate_compact_multiproof(sentence, indices.clone());
-        assert!(validate_compact_multiproof(&root, words, multiproof));
+        let (root, multiproof) = generate_compact_multiproof::<SipHasher64>(sentence, indices.clone());
+        assert!(validate_compact_multiproof::<SipHasher64>(&root, words, multiproof));
     }
 
     #[test]
@@ -684,8 +945,12 @@ mod additional_tests {
         let sentence = "this sentence has duplicate words this sentence";
         let indices = vec![0, 4, 5, 6];
         let words = vec!["this", "words", "this", "sentence"];
-        let (root, multiproof) = generate_compact_multiproof(sentence, indices.clone());
-        assert!(!validate_compact_multiproof(&root, words, multiproof));
+        let (root, multiproof) = generate_compact_multiproof::<SipHasher64>(sentence, indices.clone());
+        // Repeated words are fine, it is the positions that must be distinct. The last word of
+        // this seven-word sentence used to be dropped from the tree, which made the proof fail.
+        assert_eq!(calculate_merkle_root::<SipHasher64>(sentence), root);
+        assert!(validate_compact_multiproof::<SipHasher64>(&root, words, multiproof.clone()));
+        assert!(!validate_compact_multiproof::<SipHasher64>(&root, vec!["this", "words", "sentence", "this"], multiproof));
     }
 
     #[test]
@@ -694,28 +959,308 @@ mod additional_tests {
         let length = 1024;
         let num_proofs = 10;
         let rng_seed = 12345678;
-        let (compact_size, individual_size) = compare_proof_sizes(&sentence, length, num_proofs, rng_seed);
-        assert!(compact_size < individual_size);
+        let sizes = compare_proof_sizes::<SipHasher64>(&sentence, length, num_proofs, rng_seed);
+        assert!(sizes.compact_multiproof < sizes.proofs);
+        // Ten proofs with ten 8 byte siblings each, with or without the direction of each sibling
+        assert_eq!(10 * (15 + 10 * 9), sizes.proofs);
+        assert_eq!(10 * (23 + 10 * 8), sizes.compact_proofs);
+
+        // The same proofs, with the arity in every encoding
+        let two = compare_proof_sizes_with_arity::<SipHasher64>(&sentence, length, num_proofs, rng_seed, Arity::Two);
+        assert_eq!(sizes.proofs + 10, two.proofs);
+        assert_eq!(sizes.compact_proofs + 10, two.compact_proofs);
+        assert_eq!(sizes.compact_multiproof + 1, two.compact_multiproof);
+        // Five levels of three siblings, or ten of one: wider trees make larger single proofs
+        let four = compare_proof_sizes_with_arity::<SipHasher64>(&sentence, length, num_proofs, rng_seed, Arity::Four);
+        assert_eq!(10 * (16 + 5 * (1 + 3 * 8)), four.proofs);
+        assert_eq!(10 * (24 + 5 * 3 * 8), four.compact_proofs);
+        assert!(four.compact_multiproof > sizes.compact_multiproof);
     }
 
     #[test]
     fn test_calculate_generate_and_validate_proof() {
         // Step 1: Calculate Merkle root
         let sentence = "the quick brown fox jumps over the lazy dog";
-        let root = calculate_merkle_root(sentence);
+        let root = calculate_merkle_root::<SipHasher64>(sentence);
         assert_ne!(root, 0, "Merkle root should not be zero");
 
         // Step 2: Generate proof for a specific word
         let index = 3; // Let's choose the word "fox"
-        let (generated_root, proof) = generate_proof(sentence, index);
+        let (generated_root, proof) = generate_proof::<SipHasher64>(sentence, index);
         let word = "fox";
         
         // Ensure the generated root matches the calculated root
         assert_eq!(root, generated_root, "Generated root should match the calculated root");
 
         // Step 3: Validate the proof
-        let is_valid = validate_proof(&root, word, proof);
+        let is_valid = validate_proof::<SipHasher64>(&root, word, proof);
         assert!(is_valid, "The proof should be valid for the word 'fox'");
     }
 
+    #[test]
+    fn test_proofs_with_cryptographic_hashers() {
+        fn round_trip<H: MerkleHasher>() {
+            let sentence = "the quick brown fox jumps over the lazy dog";
+            let root = calculate_merkle_root::<H>(sentence);
+            for (i, word) in sentence.split_whitespace().enumerate() {
+                let (generated_root, proof) = generate_proof::<H>(sentence, i);
+                assert_eq!(root, generated_root);
+                assert!(validate_proof::<H>(&root, word, proof.clone()));
+                assert!(!validate_proof::<H>(&root, "cat", proof));
+            }
+
+            let (multiproof_root, multiproof) =
+                generate_compact_multiproof::<H>("Here's an eight word sentence, special for you.", vec![0, 1, 6]);
+            assert!(validate_compact_multiproof::<H>(&multiproof_root, vec!["Here's", "an", "for"], multiproof));
+        }
+
+        round_trip::<Sha256Hasher>();
+        round_trip::<Sha512_256Hasher>();
+        round_trip::<Blake3Hasher>();
+    }
+
+    #[test]
+    fn test_sentence_functions_wrap_leaf_functions() {
+        let sentence = "the quick brown fox jumps over the lazy dog";
+        let words: Vec<&str> = sentence.split_whitespace().collect();
+        let digests: Vec<u64> = words.iter().map(hash).collect();
+
+        let root = calculate_merkle_root::<SipHasher64>(sentence);
+        assert_eq!(root, calculate_merkle_root_from_leaves::<SipHasher64, _>(&words));
+        assert_eq!(root, calculate_merkle_root_from_digests::<SipHasher64>(digests.clone()));
+
+        let (proof_root, proof) = generate_proof_from_digests::<SipHasher64>(digests.clone(), 3);
+        assert_eq!((proof_root, proof.clone()), generate_proof::<SipHasher64>(sentence, 3));
+        assert!(validate_proof_for_digest::<SipHasher64>(&root, hash(&"fox"), proof));
+
+        let indices = vec![1, 3, 6];
+        let (multiproof_root, multiproof) =
+            generate_compact_multiproof_from_digests::<SipHasher64>(digests, indices.clone());
+        assert_eq!(
+            (multiproof_root, multiproof),
+            generate_compact_multiproof::<SipHasher64>(sentence, indices)
+        );
+    }
+
+    #[test]
+    fn test_binary_leaves() {
+        // Not valid UTF-8, so these could never be words of a sentence
+        let leaves: Vec<Vec<u8>> = vec![vec![0xff, 0x00], vec![0xc3], vec![], vec![0x80; 64], vec![1, 2, 3]];
+        let root = calculate_merkle_root_from_leaves::<Sha256Hasher, _>(&leaves);
+
+        for (i, leaf) in leaves.iter().enumerate() {
+            let (generated_root, proof) = generate_proof_from_leaves::<Sha256Hasher, _>(&leaves, i);
+            assert_eq!(root, generated_root);
+            assert!(validate_proof_for_leaf::<Sha256Hasher>(&root, leaf, proof.clone()));
+            assert!(!validate_proof_for_leaf::<Sha256Hasher>(&root, b"other", proof));
+        }
+
+        let leaves = &leaves[..4];
+        let (multiproof_root, multiproof) =
+            generate_compact_multiproof_from_leaves::<Sha256Hasher, _>(leaves, vec![3, 0]);
+        assert!(validate_compact_multiproof_for_leaves::<Sha256Hasher, _>(
+            &multiproof_root,
+            &[&leaves[3], &leaves[0]],
+            multiproof
+        ));
+    }
+
+    #[test]
+    fn test_try_functions_return_errors() {
+        let sentence = "You trust me?";
+        assert_eq!(
+            Err(MerkleError::IndexOutOfRange { index: 3, len: 3 }),
+            try_generate_proof::<SipHasher64>(sentence, 3)
+        );
+        assert_eq!(Err(MerkleError::EmptyTree), try_generate_proof::<SipHasher64>("", 0));
+        assert_eq!(Ok(generate_proof::<SipHasher64>(sentence, 2)), try_generate_proof::<SipHasher64>(sentence, 2));
+
+        assert_eq!(
+            Err(MerkleError::DuplicateIndex(1)),
+            try_generate_compact_multiproof::<SipHasher64>(sentence, vec![1, 0, 1])
+        );
+        assert_eq!(
+            Err(MerkleError::IndexOutOfRange { index: 5, len: 3 }),
+            try_generate_compact_multiproof::<SipHasher64>(sentence, vec![0, 5])
+        );
+        assert_eq!(Err(MerkleError::NoIndices), try_generate_compact_multiproof::<SipHasher64>(sentence, vec![]));
+        assert_eq!(
+            Err(MerkleError::SizeOutOfRange { size: 4, len: 3 }),
+            try_compare_proof_sizes::<SipHasher64>(sentence, 3, 4, 0)
+        );
+        assert_eq!(
+            Err(MerkleError::IndexOutOfRange { index: 3, len: 3 }),
+            try_compare_proof_sizes::<SipHasher64>(sentence, 4, 4, 0)
+        );
+        assert_eq!(
+            Err(MerkleError::IndexOutOfRange { index: 3, len: 3 }),
+            try_compare_proof_sizes_with_arity::<SipHasher64>(sentence, 4, 4, 0, Arity::Eight)
+        );
+    }
+
+    #[test]
+    fn test_verify_at() {
+        let sentence = "Here's an eight word sentence, special for you.";
+        let (root, proof) = generate_proof::<SipHasher64>(sentence, 1);
+        assert_eq!(Ok(()), verify_at::<SipHasher64>(&root, 1, 8, b"an", &proof));
+        assert!(validate_proof::<SipHasher64>(&root, "an", proof.clone()));
+
+        assert_eq!(Err(MerkleError::RootMismatch), verify_at::<SipHasher64>(&root, 1, 8, b"eight", &proof));
+        assert_eq!(
+            Err(MerkleError::DirectionMismatch { position: 0 }),
+            verify_at::<SipHasher64>(&root, 0, 8, b"an", &proof)
+        );
+        assert_eq!(
+            Err(MerkleError::DirectionMismatch { position: 1 }),
+            verify_at::<SipHasher64>(&root, 3, 8, b"an", &proof)
+        );
+        assert_eq!(
+            Err(MerkleError::ProofDepthMismatch { expected: 4, actual: 3 }),
+            verify_at::<SipHasher64>(&root, 1, 9, b"an", &proof)
+        );
+        assert_eq!(
+            Err(MerkleError::IndexOutOfRange { index: 8, len: 8 }),
+            verify_at::<SipHasher64>(&root, 8, 8, b"an", &proof)
+        );
+        assert_eq!(Err(MerkleError::EmptyTree), verify_at::<SipHasher64>(&root, 0, 0, b"an", &proof));
+    }
+
+    /// The directions of a proof are not bound to an index by `validate_proof`, so a proof made
+    /// for one position can be passed off for another; `verify_at` catches it.
+    #[test]
+    fn test_verify_at_rejects_proofs_for_another_index() {
+        let sentence = "You trust me, right?";
+        let root = calculate_merkle_root::<SipHasher64>(sentence);
+        let words: Vec<&str> = sentence.split_whitespace().collect();
+        for (index, word) in words.iter().enumerate() {
+            let (_, proof) = generate_proof::<SipHasher64>(sentence, index);
+            for claimed in 0..words.len() {
+                let result = verify_at::<SipHasher64>(&root, claimed, words.len(), word.as_bytes(), &proof);
+                assert_eq!(claimed == index, result.is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_at_with_shape() {
+        let leaves = ["a", "b", "c", "d", "e"];
+        let tree = MerkleTree::<SipHasher64>::from_leaves_with_shape(&leaves, TreeShape::Unbalanced);
+        let leaf_hash = SipHasher64::hash_leaf(b"e");
+        let proof = tree.proof(4);
+        assert_eq!(
+            Ok(()),
+            verify_digest_at_with_shape::<SipHasher64>(&tree.root(), 4, 5, leaf_hash, &proof, TreeShape::Unbalanced)
+        );
+        assert_eq!(
+            Err(MerkleError::ProofDepthMismatch { expected: 3, actual: 1 }),
+            verify_digest_at::<SipHasher64>(&tree.root(), 4, 5, leaf_hash, &proof)
+        );
+    }
+
+    #[test]
+    fn test_try_validate_compact_multiproof() {
+        let sentence = "this is another test sentence for multiproof validation";
+        let (root, multiproof) = generate_compact_multiproof::<SipHasher64>(sentence, vec![1, 3, 6]);
+        let words = vec!["is", "test", "multiproof"];
+        assert_eq!(Ok(true), try_validate_compact_multiproof::<SipHasher64>(&root, words.clone(), multiproof.clone()));
+        assert_eq!(
+            Ok(false),
+            try_validate_compact_multiproof::<SipHasher64>(&root, vec!["is", "test", "other"], multiproof.clone())
+        );
+        assert_eq!(
+            Err(MerkleError::ProofLengthMismatch { expected: 3, actual: 2 }),
+            try_validate_compact_multiproof::<SipHasher64>(&root, words[..2].to_vec(), multiproof.clone())
+        );
+
+        // An empty proof used to panic instead of being rejected
+        let empty = CompactMerkleMultiProof { leaf_indices: vec![], tree_size: 8, hashes: vec![] };
+        assert_eq!(Err(MerkleError::NoIndices), try_validate_compact_multiproof::<SipHasher64>(&root, vec![], empty.clone()));
+        assert!(!validate_compact_multiproof::<SipHasher64>(&root, vec![], empty));
+
+        let duplicated = CompactMerkleMultiProof { leaf_indices: vec![1, 1], tree_size: 8, hashes: multiproof.hashes.clone() };
+        assert_eq!(
+            Err(MerkleError::DuplicateIndex(1)),
+            try_validate_compact_multiproof::<SipHasher64>(&root, vec!["is", "is"], duplicated)
+        );
+
+        let mut missing_hash = multiproof.clone();
+        missing_hash.hashes.clear();
+        assert_eq!(
+            Err(MerkleError::MalformedProof),
+            try_validate_compact_multiproof::<SipHasher64>(&root, vec!["is", "test", "multiproof"], missing_hash)
+        );
+    }
+
+    #[test]
+    fn test_try_validate_compact_multiproof_with_shape() {
+        let leaves: Vec<u64> = ["a", "b", "c", "d", "e"].iter().map(hash).collect();
+        let tree = MerkleTree::<SipHasher64>::from_leaf_digests_with_shape(leaves.clone(), TreeShape::Unbalanced);
+        let proof = tree.multiproof(&[0, 4]);
+        let validate = |hashes: Vec<u64>, proof: CompactMerkleMultiProof<u64>, tree_size| {
+            let proof = CompactMerkleMultiProof { tree_size, ..proof };
+            try_validate_compact_multiproof_with_shape::<SipHasher64>(&tree.root(), hashes, proof, TreeShape::Unbalanced)
+        };
+
+        assert_eq!(Ok(true), validate(vec![leaves[0], leaves[4]], proof.clone(), 5));
+        assert_eq!(Ok(false), validate(vec![leaves[1], leaves[4]], proof.clone(), 5));
+        assert_eq!(
+            Err(MerkleError::IndexOutOfRange { index: 4, len: 4 }),
+            validate(vec![leaves[0], leaves[4]], proof.clone(), 4)
+        );
+        assert_eq!(
+            Err(MerkleError::ProofLengthMismatch { expected: 2, actual: 1 }),
+            validate(vec![leaves[0]], proof.clone(), 5)
+        );
+        let mut extra_hash = proof.clone();
+        extra_hash.hashes.push(0);
+        assert_eq!(Err(MerkleError::MalformedProof), validate(vec![leaves[0], leaves[4]], extra_hash, 5));
+        assert_eq!(Err(MerkleError::EmptyTree), validate(vec![leaves[0], leaves[4]], proof, 0));
+    }
+
+    #[test]
+    fn test_multiproof_of_every_leaf() {
+        // Needs no proof hashes at all, which the verifier used to mistake for a deeper tree
+        for sentence in ["You trust", "You trust me, right?"] {
+            let n = sentence.split_whitespace().count();
+            let indices: Vec<usize> = (0..n).rev().collect();
+            let (root, multiproof) = generate_compact_multiproof::<SipHasher64>(sentence, indices);
+            assert!(multiproof.hashes.is_empty());
+            let words: Vec<&str> = sentence.split_whitespace().rev().collect();
+            assert!(validate_compact_multiproof::<SipHasher64>(&root, words, multiproof));
+        }
+    }
+
+    #[test]
+    fn test_large_multiproof_validation() {
+        let leaves: Vec<[u8; 32]> = (0u32..1 << 14).map(|i| Sha256Hasher::hash_leaf(&i.to_le_bytes())).collect();
+        let tree = MerkleTree::<Sha256Hasher>::from_leaf_digests(leaves.clone());
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(7);
+        let indices = rand::seq::index::sample(&mut rng, leaves.len(), 5000).into_vec();
+        let proof = tree.multiproof(&indices);
+        let mut proven: Vec<[u8; 32]> = indices.iter().map(|&i| leaves[i]).collect();
+        assert!(validate_compact_multiproof_for_digests::<Sha256Hasher>(&tree.root(), proven.clone(), proof.clone()));
+
+        proven[1234][0] ^= 1;
+        assert!(!validate_compact_multiproof_for_digests::<Sha256Hasher>(&tree.root(), proven, proof));
+    }
+
+    #[test]
+    #[should_panic(expected = "Index 1 is duplicated")]
+    fn test_multiproof_generation_rejects_duplicates() {
+        generate_compact_multiproof::<SipHasher64>("You trust me?", vec![1, 1]);
+    }
+
+    #[test]
+    fn test_hashers_produce_different_roots() {
+        let sentence = "You trust me, right?";
+        assert_ne!(
+            calculate_merkle_root::<Sha256Hasher>(sentence),
+            calculate_merkle_root::<Sha512_256Hasher>(sentence)
+        );
+        assert_ne!(
+            calculate_merkle_root::<Sha256Hasher>(sentence),
+            calculate_merkle_root::<Blake3Hasher>(sentence)
+        );
+    }
+
 }
@@ -0,0 +1,549 @@
+//! Merkle Mountain Ranges, an append-only accumulator.
+//!
+//! A [`MerkleMountainRange`] keeps every complete subtree of 2^h leaves its leaves fill. The
+//! largest ones that are not part of a larger subtree are its peaks, one for every bit set in the
+//! number of leaves, largest (and leftmost) first. Appending a leaf merges peaks of equal height
+//! like a binary counter carries, so no existing node ever changes: a path from a leaf up to its
+//! peak stays valid as the range grows, and only gets longer when its peak is merged.
+//!
+//! The peaks are bagged into a single root from the right, `hash(p0, hash(p1, .. hash(pk-1, pk)))`,
+//! which is how an unbalanced tree promotes its last nodes, so the root of a range equals the root
+//! of the [`TreeShape::Unbalanced`](crate::TreeShape) tree of the same leaves.
+
+use crate::error::check_index;
+use crate::{concatenate_hash_values, MerkleError, MerkleHasher};
+use serde::{Deserialize, Serialize};
+
+/// A proof that a leaf is part of a range of `size` leaves: the siblings from the leaf up to its
+/// peak, and the other peaks of the range, largest first.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct MmrProof<D> {
+    pub leaf_index: usize,
+    pub size: usize,
+    pub path: Vec<D>,
+    pub peaks: Vec<D>,
+}
+
+/// A proof that the range of the first `old_size` leaves is a prefix of the range of the first
+/// `new_size` leaves.
+///
+/// The old peaks bag into the old root. The smallest one, with the siblings of `path` and the old
+/// peaks it merges with, climbs to the new peak that covers the last old leaf; the larger old peaks
+/// are new peaks themselves and `new_peaks` are the ones right of it, so all new peaks bag into the
+/// new root.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct MmrConsistencyProof<D> {
+    pub old_size: usize,
+    pub new_size: usize,
+    pub old_peaks: Vec<D>,
+    pub path: Vec<D>,
+    pub new_peaks: Vec<D>,
+}
+
+/// The heights of the peaks of a range of `size` leaves, largest first.
+fn peak_heights(size: usize) -> impl Iterator<Item = usize> {
+    (0..usize::BITS as usize).rev().filter(move |&height| (size >> height) & 1 == 1)
+}
+
+/// Returns the position among the peaks of a range of `size` leaves and the height of the peak
+/// over the leaf at `index`, which must be below `size`.
+fn peak_of(index: usize, size: usize) -> (usize, usize) {
+    let mut start = 0;
+    for (position, height) in peak_heights(size).enumerate() {
+        start += 1 << height;
+        if index < start {
+            return (position, height);
+        }
+    }
+    unreachable!("leaf {} is not in a range of {} leaves", index, size)
+}
+
+/// Bags peaks, largest first, into the root of a range. The root of an empty range is the root of
+/// an empty unbalanced tree.
+pub fn bag_peaks<H: MerkleHasher>(peaks: &[H::Digest]) -> H::Digest {
+    match peaks.split_last() {
+        Some((&smallest, larger)) => {
+            larger.iter().rev().fold(smallest, |right, &left| concatenate_hash_values::<H>(left, right))
+        }
+        None => H::hash_parts(&[]),
+    }
+}
+
+/// An append-only Merkle Mountain Range.
+#[derive(Debug, Clone)]
+pub struct MerkleMountainRange<H: MerkleHasher> {
+    // The complete subtrees of 2^h leaves at index h, from left to right. The first are the leaves
+    levels: Vec<Vec<H::Digest>>,
+}
+
+impl<H: MerkleHasher> Default for MerkleMountainRange<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H: MerkleHasher> MerkleMountainRange<H> {
+    /// Creates an empty range.
+    pub fn new() -> Self {
+        MerkleMountainRange { levels: vec![Vec::new()] }
+    }
+
+    /// The number of leaves appended so far.
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The leaf hashes, in the order they were appended.
+    pub fn leaves(&self) -> &[H::Digest] {
+        &self.levels[0]
+    }
+
+    /// Hashes a data block with [`MerkleHasher::hash_leaf`] and appends it. Returns its index.
+    pub fn push<T: AsRef<[u8]>>(&mut self, leaf: T) -> usize {
+        self.push_digest(H::hash_leaf(leaf.as_ref()))
+    }
+
+    /// Appends a leaf that was already hashed and returns its index.
+    pub fn push_digest(&mut self, leaf: H::Digest) -> usize {
+        self.levels[0].push(leaf);
+        let mut height = 0;
+        // An even number of subtrees at a height means the last two were just completed as a pair
+        while self.levels[height].len().is_multiple_of(2) {
+            let level = &self.levels[height];
+            let node = concatenate_hash_values::<H>(level[level.len() - 2], level[level.len() - 1]);
+            height += 1;
+            if height == self.levels.len() {
+                self.levels.push(Vec::new());
+            }
+            self.levels[height].push(node);
+        }
+        self.len() - 1
+    }
+
+    /// Returns the peaks of the range, largest first.
+    pub fn peaks(&self) -> Vec<H::Digest> {
+        self.peaks_at(self.len())
+    }
+
+    /// The peaks the range had when it held `size <= len` leaves. Complete subtrees never change,
+    /// so they are still there.
+    fn peaks_at(&self, size: usize) -> Vec<H::Digest> {
+        peak_heights(size).map(|height| self.levels[height][(size >> height) - 1]).collect()
+    }
+
+    /// Returns the root of the range, its bagged peaks.
+    pub fn root(&self) -> H::Digest {
+        bag_peaks::<H>(&self.peaks())
+    }
+
+    /// The root the range had when it held `size` leaves.
+    ///
+    /// Panics if `size` is beyond the number of leaves.
+    pub fn root_at(&self, size: usize) -> H::Digest {
+        self.try_root_at(size).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`MerkleMountainRange::root_at`], but returns an error instead of panicking.
+    pub fn try_root_at(&self, size: usize) -> Result<H::Digest, MerkleError> {
+        if size > self.len() {
+            return Err(MerkleError::SizeOutOfRange { size, len: self.len() });
+        }
+        Ok(bag_peaks::<H>(&self.peaks_at(size)))
+    }
+
+    /// Returns the proof for the leaf at `index` against the current root.
+    ///
+    /// Panics if the index is out of range.
+    pub fn proof(&self, index: usize) -> MmrProof<H::Digest> {
+        self.try_proof(index).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`MerkleMountainRange::proof`], but returns an error instead of panicking.
+    pub fn try_proof(&self, index: usize) -> Result<MmrProof<H::Digest>, MerkleError> {
+        check_index(index, self.len())?;
+        let (position, height) = peak_of(index, self.len());
+        let path = (0..height).map(|h| self.levels[h][(index >> h) ^ 1]).collect();
+        let mut peaks = self.peaks();
+        peaks.remove(position);
+        Ok(MmrProof { leaf_index: index, size: self.len(), path, peaks })
+    }
+
+    /// Returns the proof that the range of the first `old_size` leaves is a prefix of the range of
+    /// the first `new_size` leaves.
+    ///
+    /// Panics if `old_size > new_size` or `new_size` is beyond the number of leaves.
+    pub fn consistency_proof(&self, old_size: usize, new_size: usize) -> MmrConsistencyProof<H::Digest> {
+        self.try_consistency_proof(old_size, new_size).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`MerkleMountainRange::consistency_proof`], but returns an error instead of
+    /// panicking.
+    pub fn try_consistency_proof(
+        &self,
+        old_size: usize,
+        new_size: usize,
+    ) -> Result<MmrConsistencyProof<H::Digest>, MerkleError> {
+        if new_size > self.len() {
+            return Err(MerkleError::SizeOutOfRange { size: new_size, len: self.len() });
+        }
+        if old_size > new_size {
+            return Err(MerkleError::SizeOutOfRange { size: old_size, len: new_size });
+        }
+
+        let old_peaks = self.peaks_at(old_size);
+        let new_peaks = self.peaks_at(new_size);
+        if old_size == 0 {
+            return Ok(MmrConsistencyProof { old_size, new_size, old_peaks, path: Vec::new(), new_peaks });
+        }
+        // Climb from the smallest old peak, taking the right siblings the old range did not have
+        let (position, top) = peak_of(old_size - 1, new_size);
+        let path = (old_size.trailing_zeros() as usize..top)
+            .map(|height| ((old_size - 1) >> height, height))
+            .filter(|(index, _)| index.is_multiple_of(2))
+            .map(|(index, height)| self.levels[height][index + 1])
+            .collect();
+        let new_peaks = new_peaks[position + 1..].to_vec();
+        Ok(MmrConsistencyProof { old_size, new_size, old_peaks, path, new_peaks })
+    }
+}
+
+impl<H: MerkleHasher, T: AsRef<[u8]>> Extend<T> for MerkleMountainRange<H> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, leaves: I) {
+        for leaf in leaves {
+            self.push(leaf);
+        }
+    }
+}
+
+/// Checks that `leaf` is the data block at `proof.leaf_index` of the range with the given root.
+pub fn verify_mmr_proof<H: MerkleHasher>(
+    root: &H::Digest,
+    leaf: &[u8],
+    proof: &MmrProof<H::Digest>,
+) -> Result<(), MerkleError> {
+    verify_mmr_proof_for_digest::<H>(root, H::hash_leaf(leaf), proof)
+}
+
+/// Same as [`verify_mmr_proof`], for a leaf that was already hashed.
+pub fn verify_mmr_proof_for_digest<H: MerkleHasher>(
+    root: &H::Digest,
+    leaf_hash: H::Digest,
+    proof: &MmrProof<H::Digest>,
+) -> Result<(), MerkleError> {
+    check_index(proof.leaf_index, proof.size)?;
+    let (position, height) = peak_of(proof.leaf_index, proof.size);
+    if proof.path.len() != height {
+        return Err(MerkleError::ProofDepthMismatch { expected: height, actual: proof.path.len() });
+    }
+    if proof.peaks.len() + 1 != proof.size.count_ones() as usize {
+        return Err(MerkleError::MalformedProof);
+    }
+
+    let peak = proof.path.iter().enumerate().fold(leaf_hash, |node, (h, &sibling)| {
+        if (proof.leaf_index >> h).is_multiple_of(2) {
+            concatenate_hash_values::<H>(node, sibling)
+        } else {
+            concatenate_hash_values::<H>(sibling, node)
+        }
+    });
+    let mut peaks = proof.peaks.clone();
+    peaks.insert(position, peak);
+    if bag_peaks::<H>(&peaks) != *root {
+        return Err(MerkleError::RootMismatch);
+    }
+    Ok(())
+}
+
+/// The new peaks a consistency proof leads to, and for every height from the smallest old peak up
+/// to the new peak covering the last old leaf, the node on the way up and its sibling.
+struct Climb<D> {
+    new_peaks: Vec<D>,
+    steps: Vec<(D, D)>,
+}
+
+/// Recomputes the new peaks from a consistency proof, without checking either root.
+fn climb<H: MerkleHasher>(proof: &MmrConsistencyProof<H::Digest>) -> Result<Climb<H::Digest>, MerkleError> {
+    let (old_size, new_size) = (proof.old_size, proof.new_size);
+    if old_size > new_size {
+        return Err(MerkleError::SizeOutOfRange { size: old_size, len: new_size });
+    }
+    if proof.old_peaks.len() != old_size.count_ones() as usize {
+        return Err(MerkleError::MalformedProof);
+    }
+
+    let mut new_peaks = Vec::new();
+    let mut steps = Vec::new();
+    let mut path = proof.path.iter();
+    if old_size > 0 {
+        let (_, top) = peak_of(old_size - 1, new_size);
+        let old_peaks: Vec<(usize, H::Digest)> = peak_heights(old_size).zip(proof.old_peaks.iter().copied()).collect();
+        // Old peaks above the new one covering the last old leaf are new peaks as they are
+        new_peaks.extend(old_peaks.iter().filter(|(height, _)| *height > top).map(|&(_, peak)| peak));
+
+        let (bottom, mut node) = old_peaks[old_peaks.len() - 1];
+        for height in bottom..top {
+            if ((old_size - 1) >> height).is_multiple_of(2) {
+                let sibling = *path.next().ok_or(MerkleError::MalformedProof)?;
+                steps.push((node, sibling));
+                node = concatenate_hash_values::<H>(node, sibling);
+            } else {
+                // A right child's left sibling lies within the old range, so it is an old peak
+                let &(_, sibling) = old_peaks.iter().find(|(h, _)| *h == height).unwrap();
+                steps.push((node, sibling));
+                node = concatenate_hash_values::<H>(sibling, node);
+            }
+        }
+        new_peaks.push(node);
+    }
+    if path.next().is_some() {
+        return Err(MerkleError::MalformedProof);
+    }
+    new_peaks.extend_from_slice(&proof.new_peaks);
+    if new_peaks.len() != new_size.count_ones() as usize {
+        return Err(MerkleError::MalformedProof);
+    }
+    Ok(Climb { new_peaks, steps })
+}
+
+/// Checks that `old_root`, the root of a range of `proof.old_size` leaves, and `new_root`, the root
+/// of a range of `proof.new_size` leaves, belong to the same append-only range.
+pub fn verify_mmr_consistency<H: MerkleHasher>(
+    old_root: &H::Digest,
+    new_root: &H::Digest,
+    proof: &MmrConsistencyProof<H::Digest>,
+) -> Result<(), MerkleError> {
+    let climb = climb::<H>(proof)?;
+    if bag_peaks::<H>(&proof.old_peaks) != *old_root || bag_peaks::<H>(&climb.new_peaks) != *new_root {
+        return Err(MerkleError::RootMismatch);
+    }
+    Ok(())
+}
+
+impl<D: Copy + Eq> MmrProof<D> {
+    /// Carries the proof over to the larger range of a consistency proof from its size, without
+    /// access to the range: the path is extended with the siblings up to the leaf's new peak and
+    /// the other peaks are replaced. Verify the result against the new root.
+    pub fn upgrade<H: MerkleHasher<Digest = D>>(
+        &self,
+        consistency: &MmrConsistencyProof<D>,
+    ) -> Result<MmrProof<D>, MerkleError> {
+        check_index(self.leaf_index, self.size)?;
+        if self.size != consistency.old_size {
+            return Err(MerkleError::SizeOutOfRange { size: consistency.old_size, len: self.size });
+        }
+        // The other old peaks of this proof must be the ones the consistency proof starts from
+        let (position, height) = peak_of(self.leaf_index, self.size);
+        let mut others = consistency.old_peaks.clone();
+        if others.len() != self.peaks.len() + 1 {
+            return Err(MerkleError::MalformedProof);
+        }
+        others.remove(position);
+        if others != self.peaks {
+            return Err(MerkleError::MalformedProof);
+        }
+
+        let climb = climb::<H>(consistency)?;
+        let mut path = self.path.clone();
+        let mut peaks = climb.new_peaks;
+        let (new_position, _) = peak_of(self.leaf_index, consistency.new_size);
+        let bottom = self.size.trailing_zeros() as usize;
+        // Peaks above the new one covering the last old leaf stay peaks, the others were merged: the
+        // smallest one climbs with the siblings, a larger one has the node climbing from the
+        // smaller ones as its right sibling
+        if let Some(steps) = climb.steps.get(height - bottom..) {
+            let mut steps = steps.iter();
+            if let Some(&(node, sibling)) = steps.next() {
+                path.push(if height == bottom { sibling } else { node });
+            }
+            path.extend(steps.map(|&(_, sibling)| sibling));
+        }
+        peaks.remove(new_position);
+        Ok(MmrProof { leaf_index: self.leaf_index, size: consistency.new_size, path, peaks })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{calculate_merkle_root_with_shape, SipHasher64, TreeShape};
+
+    fn range(n: usize) -> MerkleMountainRange<SipHasher64> {
+        let mut mmr = MerkleMountainRange::new();
+        mmr.extend((0..n as u32).map(u32::to_le_bytes));
+        mmr
+    }
+
+    #[test]
+    fn roots_match_the_unbalanced_tree() {
+        let mut mmr = MerkleMountainRange::<SipHasher64>::new();
+        let mut leaves = Vec::new();
+        for n in 0..=200u32 {
+            assert_eq!(calculate_merkle_root_with_shape::<SipHasher64>(leaves.clone(), TreeShape::Unbalanced), mmr.root());
+            assert_eq!(n.count_ones() as usize, mmr.peaks().len());
+
+            let leaf = SipHasher64::hash_leaf(&n.to_le_bytes());
+            assert_eq!(n as usize, mmr.push_digest(leaf));
+            leaves.push(leaf);
+        }
+        for size in [0, 1, 7, 64, 150] {
+            assert_eq!(
+                calculate_merkle_root_with_shape::<SipHasher64>(leaves[..size].to_vec(), TreeShape::Unbalanced),
+                mmr.root_at(size)
+            );
+        }
+        assert_eq!(Err(MerkleError::SizeOutOfRange { size: 202, len: 201 }), mmr.try_root_at(202));
+    }
+
+    #[test]
+    fn peaks_are_the_complete_subtrees() {
+        let mut mmr = MerkleMountainRange::<SipHasher64>::new();
+        mmr.extend(["a", "b", "c", "d", "e", "f", "g"]);
+        let [a, b, c, d, e, f, g] =
+            ["a", "b", "c", "d", "e", "f", "g"].map(|leaf| SipHasher64::hash_leaf(leaf.as_bytes()));
+        let h = concatenate_hash_values::<SipHasher64>;
+        assert_eq!(vec![h(h(a, b), h(c, d)), h(e, f), g], mmr.peaks());
+        assert_eq!(h(h(h(a, b), h(c, d)), h(h(e, f), g)), mmr.root());
+        assert_eq!(MmrProof { leaf_index: 4, size: 7, path: vec![f], peaks: vec![h(h(a, b), h(c, d)), g] }, mmr.proof(4));
+    }
+
+    #[test]
+    fn every_proof_verifies() {
+        for n in 1..=40 {
+            let mmr = range(n);
+            for index in 0..n {
+                let proof = mmr.proof(index);
+                let leaf = (index as u32).to_le_bytes();
+                assert_eq!(Ok(()), verify_mmr_proof::<SipHasher64>(&mmr.root(), &leaf, &proof));
+                assert_eq!(Err(MerkleError::RootMismatch), verify_mmr_proof::<SipHasher64>(&mmr.root(), b"other", &proof));
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_proofs() {
+        let mmr = range(11);
+        let root = mmr.root();
+        let leaf = 9u32.to_le_bytes();
+        let proof = mmr.proof(9);
+
+        let mut moved = proof.clone();
+        moved.leaf_index = 8;
+        assert_eq!(Err(MerkleError::RootMismatch), verify_mmr_proof::<SipHasher64>(&root, &leaf, &moved));
+        moved.leaf_index = 10;
+        assert_eq!(
+            Err(MerkleError::ProofDepthMismatch { expected: 0, actual: 1 }),
+            verify_mmr_proof::<SipHasher64>(&root, &leaf, &moved)
+        );
+        moved.leaf_index = 11;
+        assert_eq!(
+            Err(MerkleError::IndexOutOfRange { index: 11, len: 11 }),
+            verify_mmr_proof::<SipHasher64>(&root, &leaf, &moved)
+        );
+        let mut missing = proof.clone();
+        missing.peaks.pop();
+        assert_eq!(Err(MerkleError::MalformedProof), verify_mmr_proof::<SipHasher64>(&root, &leaf, &missing));
+        let mut tampered = proof;
+        tampered.peaks[0] ^= 1;
+        assert_eq!(Err(MerkleError::RootMismatch), verify_mmr_proof::<SipHasher64>(&root, &leaf, &tampered));
+
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 11, len: 11 }), mmr.try_proof(11));
+        assert_eq!(Err(MerkleError::EmptyTree), range(0).try_proof(0));
+    }
+
+    #[test]
+    fn every_consistency_proof_verifies() {
+        let mmr = range(33);
+        for new_size in 0..=33 {
+            for old_size in 0..=new_size {
+                let proof = mmr.consistency_proof(old_size, new_size);
+                let (old_root, new_root) = (mmr.root_at(old_size), mmr.root_at(new_size));
+                assert_eq!(Ok(()), verify_mmr_consistency::<SipHasher64>(&old_root, &new_root, &proof));
+                if old_size < new_size {
+                    assert_eq!(
+                        Err(MerkleError::RootMismatch),
+                        verify_mmr_consistency::<SipHasher64>(&new_root, &old_root, &proof)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn consistency_detects_a_rewritten_leaf() {
+        let mmr = range(20);
+        let mut forked = range(12);
+        forked.extend((12..20u32).map(u32::to_le_bytes));
+        assert_eq!(mmr.root(), forked.root());
+
+        let mut rewritten = range(5);
+        rewritten.push(b"rewritten");
+        rewritten.extend((6..20u32).map(u32::to_le_bytes));
+        let proof = rewritten.consistency_proof(12, 20);
+        assert_eq!(
+            Err(MerkleError::RootMismatch),
+            verify_mmr_consistency::<SipHasher64>(&mmr.root_at(12), &rewritten.root(), &proof)
+        );
+    }
+
+    #[test]
+    fn malformed_consistency_proofs() {
+        let mmr = range(21);
+        let (old_root, new_root) = (mmr.root_at(6), mmr.root());
+        let proof = mmr.consistency_proof(6, 21);
+
+        let mut extra = proof.clone();
+        extra.path.push(0);
+        assert_eq!(Err(MerkleError::MalformedProof), verify_mmr_consistency::<SipHasher64>(&old_root, &new_root, &extra));
+        let mut missing = proof.clone();
+        missing.new_peaks.pop();
+        assert_eq!(Err(MerkleError::MalformedProof), verify_mmr_consistency::<SipHasher64>(&old_root, &new_root, &missing));
+        let mut tampered = proof.clone();
+        tampered.path[0] ^= 1;
+        assert_eq!(Err(MerkleError::RootMismatch), verify_mmr_consistency::<SipHasher64>(&old_root, &new_root, &tampered));
+        let mut shrunk = proof;
+        shrunk.new_size = 5;
+        assert_eq!(
+            Err(MerkleError::SizeOutOfRange { size: 6, len: 5 }),
+            verify_mmr_consistency::<SipHasher64>(&old_root, &new_root, &shrunk)
+        );
+
+        assert_eq!(Err(MerkleError::SizeOutOfRange { size: 22, len: 21 }), mmr.try_consistency_proof(3, 22));
+        assert_eq!(Err(MerkleError::SizeOutOfRange { size: 9, len: 8 }), mmr.try_consistency_proof(9, 8));
+    }
+
+    #[test]
+    fn proofs_upgrade_as_the_range_grows() {
+        let mmr = range(33);
+        for old_size in 1..=33 {
+            let old = range(old_size);
+            for new_size in old_size..=33 {
+                let consistency = mmr.consistency_proof(old_size, new_size);
+                let new = range(new_size);
+                for index in 0..old_size {
+                    let proof = old.proof(index);
+                    let upgraded = proof.upgrade::<SipHasher64>(&consistency).unwrap();
+                    // Existing siblings never change, the path only grows
+                    assert!(upgraded.path.starts_with(&proof.path));
+                    assert_eq!(new.proof(index), upgraded);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn upgrades_need_a_matching_consistency_proof() {
+        let mmr = range(30);
+        let proof = range(13).proof(4);
+        assert_eq!(
+            Err(MerkleError::SizeOutOfRange { size: 12, len: 13 }),
+            proof.upgrade::<SipHasher64>(&mmr.consistency_proof(12, 30))
+        );
+        let mut consistency = mmr.consistency_proof(13, 30);
+        consistency.old_peaks[2] ^= 1;
+        assert_eq!(Err(MerkleError::MalformedProof), proof.upgrade::<SipHasher64>(&consistency));
+    }
+}
@@ -0,0 +1,359 @@
+//! Trees and proofs compatible with OpenZeppelin's `MerkleProof` library.
+//!
+//! OpenZeppelin's trees (`StandardMerkleTree` in `@openzeppelin/merkle-tree`) differ from the
+//! other trees of this crate in three ways: leaves are hashed twice with Keccak-256, interior nodes
+//! hash their children in sorted order so proofs need no directions, and the nodes form a complete
+//! binary tree stored in an array, `tree[0]` being the root, the children of `i` being `2i + 1` and
+//! `2i + 2`, and the leaves filling the end of the array in reverse order. [`OpenZeppelinTree`]
+//! builds the same array, so its roots, proofs and multiproofs are the ones the JS library produces
+//! and `MerkleProof.verify` and `MerkleProof.multiProofVerify` accept.
+
+use crate::error::{check_index, check_indices};
+use crate::{Keccak256Hasher, MerkleError, MerkleHasher};
+use serde::{Deserialize, Serialize};
+
+/// Keccak-256 with OpenZeppelin's conventions: a leaf is `keccak256(keccak256(data))`, where `data`
+/// is usually the ABI encoding of the leaf's values, and the children of a node are hashed in
+/// ascending order, so a node is the same whichever side each child is on.
+///
+/// Leaves cannot be confused with interior nodes, since an interior node is a single hash of 64
+/// bytes and a leaf a hash of 32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OpenZeppelinHasher;
+
+impl MerkleHasher for OpenZeppelinHasher {
+    type Digest = [u8; 32];
+    const ALGORITHM_ID: u8 = 6;
+
+    fn hash_parts(parts: &[&[u8]]) -> [u8; 32] {
+        Keccak256Hasher::hash_parts(parts)
+    }
+
+    fn hash_leaf(data: &[u8]) -> [u8; 32] {
+        Keccak256Hasher::hash_parts(&[&Keccak256Hasher::hash_parts(&[data])])
+    }
+
+    fn hash_nodes(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+        let (low, high) = if left <= right { (left, right) } else { (right, left) };
+        Keccak256Hasher::hash_parts(&[low, high])
+    }
+}
+
+/// A multiproof in the layout of `MerkleProof.multiProofVerify`: the proven leaves, the hashes
+/// they need and for every node on the way to the root whether its second child is taken from the
+/// leaves and nodes computed so far (`true`) or from `proof` (`false`).
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct OpenZeppelinMultiProof {
+    pub leaves: Vec<[u8; 32]>,
+    pub proof: Vec<[u8; 32]>,
+    pub proof_flags: Vec<bool>,
+}
+
+/// A tree laid out like OpenZeppelin's `StandardMerkleTree`.
+#[derive(Debug, Clone)]
+pub struct OpenZeppelinTree {
+    // The nodes in OpenZeppelin's array layout, the leaves at the end in reverse order
+    tree: Vec<[u8; 32]>,
+    // The array index of every leaf, in the order the leaves were given
+    positions: Vec<usize>,
+}
+
+impl OpenZeppelinTree {
+    /// Builds the tree `StandardMerkleTree.of` builds: the leaves, usually ABI-encoded values, are
+    /// hashed with [`OpenZeppelinHasher`] and sorted by their hash. Leaves keep their index in
+    /// `leaves` for [`OpenZeppelinTree::proof`] and [`OpenZeppelinTree::multiproof`].
+    pub fn from_leaves<T: AsRef<[u8]>>(leaves: &[T]) -> Result<Self, MerkleError> {
+        let hashes = leaves.iter().map(|leaf| OpenZeppelinHasher::hash_leaf(leaf.as_ref())).collect();
+        Self::from_leaf_hashes(hashes, true)
+    }
+
+    /// Builds the tree of leaves that were already hashed, sorting them first if `sort_leaves` is
+    /// set, like the `sortLeaves` option of the JS library.
+    pub fn from_leaf_hashes(hashes: Vec<[u8; 32]>, sort_leaves: bool) -> Result<Self, MerkleError> {
+        check_index(0, hashes.len())?;
+        let mut order: Vec<usize> = (0..hashes.len()).collect();
+        if sort_leaves {
+            order.sort_by_key(|&index| hashes[index]);
+        }
+
+        let len = 2 * hashes.len() - 1;
+        let mut tree = vec![[0; 32]; len];
+        let mut positions = vec![0; hashes.len()];
+        for (sorted, &index) in order.iter().enumerate() {
+            positions[index] = len - 1 - sorted;
+            tree[len - 1 - sorted] = hashes[index];
+        }
+        for i in (0..len - hashes.len()).rev() {
+            tree[i] = OpenZeppelinHasher::hash_nodes(&tree[2 * i + 1], &tree[2 * i + 2]);
+        }
+        Ok(OpenZeppelinTree { tree, positions })
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        self.tree[0]
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// The hash of the leaf at `index`, in the order the leaves were given.
+    ///
+    /// Panics if the index is out of range.
+    pub fn leaf_hash(&self, index: usize) -> [u8; 32] {
+        self.tree[self.positions[index]]
+    }
+
+    /// Returns the siblings from the leaf at `index` up to the root, as `MerkleProof.verify`
+    /// takes them.
+    ///
+    /// Panics if the index is out of range.
+    pub fn proof(&self, index: usize) -> Vec<[u8; 32]> {
+        self.try_proof(index).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`OpenZeppelinTree::proof`], but returns an error instead of panicking.
+    pub fn try_proof(&self, index: usize) -> Result<Vec<[u8; 32]>, MerkleError> {
+        check_index(index, self.len())?;
+        let mut proof = Vec::new();
+        let mut node = self.positions[index];
+        while node > 0 {
+            proof.push(self.tree[sibling(node)]);
+            node = (node - 1) / 2;
+        }
+        Ok(proof)
+    }
+
+    /// Returns the multiproof for the leaves at `indices`, as `getMultiProof` of the JS library
+    /// computes it. The leaves of the proof are in the order the contract consumes them, which is
+    /// not the order of `indices`.
+    ///
+    /// Panics if `indices` is empty, contains duplicates or an index is out of range.
+    pub fn multiproof(&self, indices: &[usize]) -> OpenZeppelinMultiProof {
+        self.try_multiproof(indices).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`OpenZeppelinTree::multiproof`], but returns an error instead of panicking.
+    pub fn try_multiproof(&self, indices: &[usize]) -> Result<OpenZeppelinMultiProof, MerkleError> {
+        check_indices(indices, self.len())?;
+        let mut nodes: Vec<usize> = indices.iter().map(|&index| self.positions[index]).collect();
+        nodes.sort_unstable_by(|a, b| b.cmp(a));
+        let leaves = nodes.iter().map(|&node| self.tree[node]).collect();
+
+        // Deepest nodes first: a node is paired with the next one if they are siblings, otherwise
+        // its sibling goes into the proof, and its parent is queued
+        let mut queue: std::collections::VecDeque<usize> = nodes.into();
+        let mut proof = Vec::new();
+        let mut proof_flags = Vec::new();
+        while let Some(node) = queue.pop_front().filter(|&node| node > 0) {
+            if queue.front() == Some(&sibling(node)) {
+                queue.pop_front();
+                proof_flags.push(true);
+            } else {
+                proof.push(self.tree[sibling(node)]);
+                proof_flags.push(false);
+            }
+            queue.push_back((node - 1) / 2);
+        }
+        Ok(OpenZeppelinMultiProof { leaves, proof, proof_flags })
+    }
+}
+
+/// The index of the other child of the parent of `node`, which must not be the root.
+fn sibling(node: usize) -> usize {
+    if node % 2 == 1 {
+        node + 1
+    } else {
+        node - 1
+    }
+}
+
+/// Checks a proof the way `MerkleProof.verify` does: the leaf hash is combined with each sibling
+/// in turn, in sorted order, and the result must be the root.
+pub fn verify_openzeppelin_proof(root: &[u8; 32], leaf_hash: [u8; 32], proof: &[[u8; 32]]) -> Result<(), MerkleError> {
+    let computed = proof.iter().fold(leaf_hash, |node, sibling| OpenZeppelinHasher::hash_nodes(&node, sibling));
+    if computed != *root {
+        return Err(MerkleError::RootMismatch);
+    }
+    Ok(())
+}
+
+/// Checks a multiproof the way `MerkleProof.multiProofVerify` does. Proofs whose lengths do not
+/// add up, or whose flags read more leaves, nodes or proof hashes than there are, are rejected as
+/// malformed instead of reverting.
+pub fn verify_openzeppelin_multiproof(root: &[u8; 32], multiproof: &OpenZeppelinMultiProof) -> Result<(), MerkleError> {
+    let OpenZeppelinMultiProof { leaves, proof, proof_flags } = multiproof;
+    if leaves.len() + proof.len() != proof_flags.len() + 1 {
+        return Err(MerkleError::MalformedProof);
+    }
+
+    // Leaves are consumed first, then the nodes computed from them, as one queue
+    let mut hashes: Vec<[u8; 32]> = Vec::with_capacity(proof_flags.len());
+    let (mut leaf_pos, mut hash_pos) = (0, 0);
+    let mut next = |hashes: &Vec<[u8; 32]>| {
+        if leaf_pos < leaves.len() {
+            leaf_pos += 1;
+            Some(leaves[leaf_pos - 1])
+        } else {
+            hash_pos += 1;
+            hashes.get(hash_pos - 1).copied()
+        }
+    };
+    let mut proof_hashes = proof.iter();
+    for &flag in proof_flags {
+        let a = next(&hashes).ok_or(MerkleError::MalformedProof)?;
+        let b = if flag { next(&hashes) } else { proof_hashes.next().copied() };
+        hashes.push(OpenZeppelinHasher::hash_nodes(&a, &b.ok_or(MerkleError::MalformedProof)?));
+    }
+    // Without any flags, the root is the only leaf or the only proof hash
+    let computed = match (hashes.last(), leaves.first()) {
+        (Some(_), _) if proof_hashes.next().is_some() => return Err(MerkleError::MalformedProof),
+        (Some(&node), _) | (None, Some(&node)) => node,
+        (None, None) => proof[0],
+    };
+    if computed != *root {
+        return Err(MerkleError::RootMismatch);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MerkleTree, SiblingNode};
+
+    /// The ABI encoding of an `(address, uint256)` pair, two 32-byte words.
+    fn encode_address_amount(address: &str, amount: u128) -> Vec<u8> {
+        let mut encoded = vec![0; 12];
+        encoded.extend(hex::decode(address.trim_start_matches("0x")).unwrap());
+        encoded.extend([0; 16]);
+        encoded.extend(amount.to_be_bytes());
+        encoded
+    }
+
+    fn leaves(n: usize) -> Vec<Vec<u8>> {
+        (0..n as u128).map(|i| encode_address_amount(&format!("{:040x}", i + 1), i * 1_000)).collect()
+    }
+
+    /// The example of the `@openzeppelin/merkle-tree` README, built with
+    /// `StandardMerkleTree.of(values, ["address", "uint256"])`.
+    #[test]
+    fn matches_the_js_library() {
+        let values = [
+            encode_address_amount("0x1111111111111111111111111111111111111111", 5_000_000_000_000_000_000),
+            encode_address_amount("0x2222222222222222222222222222222222222222", 2_500_000_000_000_000_000),
+        ];
+        let tree = OpenZeppelinTree::from_leaves(&values).unwrap();
+        assert_eq!("d4dee0beab2d53f2cc83e567171bd2820e49898130a22622b10ead383e90bd77", hex::encode(tree.root()));
+        let proof = tree.proof(0);
+        assert_eq!(
+            vec!["b92c48e9d7abe27fd8dfd6b5dfdbfb1c9a463f80c712b66f3a5180a090cccafc"],
+            proof.iter().map(hex::encode).collect::<Vec<_>>()
+        );
+        assert_eq!(Ok(()), verify_openzeppelin_proof(&tree.root(), tree.leaf_hash(0), &proof));
+    }
+
+    #[test]
+    fn leaves_fill_the_array_in_reverse() {
+        let tree = OpenZeppelinTree::from_leaf_hashes(vec![[1; 32], [2; 32], [3; 32]], false).unwrap();
+        let h = OpenZeppelinHasher::hash_nodes;
+        assert_eq!(vec![h(&h(&[2; 32], &[1; 32]), &[3; 32]), h(&[2; 32], &[1; 32]), [3; 32], [2; 32], [1; 32]], tree.tree);
+        assert_eq!(vec![[1; 32], [3; 32]], tree.proof(1));
+
+        // Sorting only changes which leaf ends up where
+        let sorted = OpenZeppelinTree::from_leaf_hashes(vec![[3; 32], [1; 32], [2; 32]], true).unwrap();
+        assert_eq!(tree.root(), sorted.root());
+        assert_eq!([3; 32], sorted.leaf_hash(0));
+        assert_eq!(tree.proof(0), sorted.proof(1));
+    }
+
+    #[test]
+    fn every_proof_verifies() {
+        for n in 1..=17 {
+            let leaves = leaves(n);
+            let tree = OpenZeppelinTree::from_leaves(&leaves).unwrap();
+            for (index, leaf) in leaves.iter().enumerate() {
+                let proof = tree.proof(index);
+                assert_eq!(Ok(()), verify_openzeppelin_proof(&tree.root(), OpenZeppelinHasher::hash_leaf(leaf), &proof));
+                assert_eq!(
+                    Err(MerkleError::RootMismatch),
+                    verify_openzeppelin_proof(&tree.root(), OpenZeppelinHasher::hash_leaf(b"other"), &proof)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn every_multiproof_verifies() {
+        for n in 1..=9 {
+            let tree = OpenZeppelinTree::from_leaves(&leaves(n)).unwrap();
+            for subset in 1..1u32 << n {
+                let indices: Vec<usize> = (0..n).filter(|&i| subset & (1 << i) != 0).collect();
+                let multiproof = tree.multiproof(&indices);
+                assert_eq!(indices.len(), multiproof.leaves.len());
+                assert_eq!(Ok(()), verify_openzeppelin_multiproof(&tree.root(), &multiproof));
+            }
+        }
+    }
+
+    #[test]
+    fn multiproof_layout() {
+        // Leaves 0..5 are at array indices 8..4, and nodes 3 = (7, 8), 2 = (5, 6) and 1 = (3, 4)
+        let hashes: Vec<[u8; 32]> = (1..=5).map(|i| [i; 32]).collect();
+        let tree = OpenZeppelinTree::from_leaf_hashes(hashes.clone(), false).unwrap();
+        let multiproof = tree.multiproof(&[0, 1, 3]);
+        assert_eq!(vec![hashes[0], hashes[1], hashes[3]], multiproof.leaves);
+        assert_eq!(vec![hashes[2], hashes[4]], multiproof.proof);
+        assert_eq!(vec![true, false, false, true], multiproof.proof_flags);
+    }
+
+    #[test]
+    fn malformed_multiproofs() {
+        let tree = OpenZeppelinTree::from_leaves(&leaves(10)).unwrap();
+        let root = tree.root();
+        let multiproof = tree.multiproof(&[2, 5, 7]);
+
+        let mut tampered = multiproof.clone();
+        tampered.proof[0][0] ^= 1;
+        assert_eq!(Err(MerkleError::RootMismatch), verify_openzeppelin_multiproof(&root, &tampered));
+        let mut short = multiproof.clone();
+        short.proof.pop();
+        assert_eq!(Err(MerkleError::MalformedProof), verify_openzeppelin_multiproof(&root, &short));
+        // Lengths that add up, with flags that read past the proof hashes
+        let mut flipped = multiproof.clone();
+        flipped.proof_flags.iter_mut().for_each(|flag| *flag = false);
+        flipped.proof_flags.pop();
+        flipped.leaves.pop();
+        assert_eq!(Err(MerkleError::MalformedProof), verify_openzeppelin_multiproof(&root, &flipped));
+        let nothing = OpenZeppelinMultiProof { leaves: vec![], proof: vec![root], proof_flags: vec![] };
+        assert_eq!(Ok(()), verify_openzeppelin_multiproof(&root, &nothing));
+
+        assert_eq!(Err(MerkleError::DuplicateIndex(5)), tree.try_multiproof(&[5, 2, 5]));
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 10, len: 10 }), tree.try_proof(10));
+        assert_eq!(Err(MerkleError::EmptyTree), OpenZeppelinTree::from_leaf_hashes(vec![], true).map(|tree| tree.root()));
+    }
+
+    /// With sorted pairs the order of siblings does not matter, so a padded tree of a power of two
+    /// leaves has the same root and proofs as OpenZeppelin's, whose leaves are mirrored.
+    #[test]
+    fn power_of_two_trees_match_the_padded_tree() {
+        let leaves = leaves(16);
+        let tree = OpenZeppelinTree::from_leaves(&leaves).unwrap();
+        let mut hashes: Vec<[u8; 32]> = leaves.iter().map(|leaf| OpenZeppelinHasher::hash_leaf(leaf)).collect();
+        hashes.sort();
+        let padded = MerkleTree::<OpenZeppelinHasher>::from_leaf_digests(hashes.clone());
+        assert_eq!(tree.root(), padded.root());
+
+        let index = hashes.iter().position(|&hash| hash == tree.leaf_hash(3)).unwrap();
+        let siblings: Vec<[u8; 32]> = padded
+            .proof(index)
+            .into_iter()
+            .map(|(SiblingNode::Left(hash) | SiblingNode::Right(hash))| hash)
+            .collect();
+        assert_eq!(tree.proof(3), siblings);
+    }
+}
@@ -0,0 +1,80 @@
+//! Hashing the leaves and levels of large trees across threads.
+//!
+//! With the `parallel` feature, leaves and the pairs of each level are hashed with rayon once there
+//! are at least [`PARALLEL_THRESHOLD`] of them; below that, or without the feature, everything
+//! stays on the calling thread, where small trees are faster. Every node is hashed from the same
+//! children in either case, so the roots and proofs are bit-identical.
+
+/// The number of hashes in a level from which the `parallel` feature spreads them across threads.
+pub const PARALLEL_THRESHOLD: usize = 1 << 12;
+
+/// Collects `f(0), f(1), .., f(len - 1)` in order, across threads if the `parallel` feature is
+/// enabled and `len` is at least [`PARALLEL_THRESHOLD`].
+pub(crate) fn map_indices<T, F>(len: usize, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(usize) -> T + Send + Sync,
+{
+    #[cfg(feature = "parallel")]
+    if len >= PARALLEL_THRESHOLD {
+        use rayon::prelude::*;
+        return (0..len).into_par_iter().map(f).collect();
+    }
+    (0..len).map(f).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        calculate_merkle_root_from_digests, calculate_merkle_root_from_leaves, calculate_merkle_root_with_shape,
+        concatenate_hash_values, generate_compact_multiproof_from_digests, MerkleHasher, MerkleTree, SipHasher64,
+        TreeLayout, TreeShape,
+    };
+
+    /// Builds the root one pair at a time, the way the tree is defined.
+    fn sequential_root(mut level: Vec<u64>) -> u64 {
+        while level.len() > 1 {
+            level = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => concatenate_hash_values::<SipHasher64>(*left, *right),
+                    [node] => *node,
+                    _ => unreachable!(),
+                })
+                .collect();
+        }
+        level[0]
+    }
+
+    #[test]
+    fn map_indices_keeps_the_order() {
+        for len in [0, 1, PARALLEL_THRESHOLD - 1, PARALLEL_THRESHOLD, 3 * PARALLEL_THRESHOLD + 1] {
+            assert_eq!((0..len).map(|i| i * i).collect::<Vec<_>>(), map_indices(len, |i| i * i));
+        }
+    }
+
+    /// Sizes around the threshold hash some levels across threads and the ones above on one.
+    #[test]
+    fn roots_are_identical_around_the_threshold() {
+        for n in [PARALLEL_THRESHOLD - 1, PARALLEL_THRESHOLD, 2 * PARALLEL_THRESHOLD + 3, 5 * PARALLEL_THRESHOLD] {
+            let data: Vec<Vec<u8>> = (0..n as u32).map(|i| i.to_le_bytes().to_vec()).collect();
+            let leaves: Vec<u64> = data.iter().map(|leaf| SipHasher64::hash_leaf(leaf)).collect();
+
+            let unbalanced = sequential_root(leaves.clone());
+            assert_eq!(unbalanced, calculate_merkle_root_with_shape::<SipHasher64>(leaves.clone(), TreeShape::Unbalanced));
+            let tree = MerkleTree::<SipHasher64>::from_leaves_with_shape(&data, TreeShape::Unbalanced);
+            assert_eq!(unbalanced, tree.root());
+
+            let mut padded_leaves = leaves.clone();
+            padded_leaves.resize(n.next_power_of_two(), SipHasher64::hash_leaf(&[]));
+            let padded = sequential_root(padded_leaves);
+            assert_eq!(padded, calculate_merkle_root_from_leaves::<SipHasher64, _>(&data));
+            assert_eq!(padded, calculate_merkle_root_from_digests::<SipHasher64>(leaves.clone()));
+            let levels = TreeLayout::padded(n).build_levels::<SipHasher64>(leaves.clone());
+            assert_eq!(vec![padded], levels[levels.len() - 1]);
+            let (root, _) = generate_compact_multiproof_from_digests::<SipHasher64>(leaves, vec![0, n / 2, n - 1]);
+            assert_eq!(padded, root);
+        }
+    }
+}
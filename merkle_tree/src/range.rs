@@ -0,0 +1,253 @@
+//! Proofs for a contiguous run of leaves.
+//!
+//! Inside a run of known leaves every node can be recomputed, so the only hashes a verifier is
+//! missing are the siblings just outside its two ends: at each level, the left neighbour of the
+//! first known node if that node is a right child, and the right neighbour of the last known node
+//! if that node is a left child. A [`RangeProof`] carries exactly these, at most two per level,
+//! however long the run is.
+
+use crate::error::check_index;
+use crate::layout::parent;
+use crate::{MerkleError, MerkleHasher, MerkleTree, TreeLayout, TreeShape};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// A proof for the leaves `start..end` of a tree of `tree_size` leaves: the siblings left of the
+/// run and the siblings right of it, each from the leaves up.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct RangeProof<D> {
+    pub tree_size: usize,
+    pub left: Vec<D>,
+    pub right: Vec<D>,
+}
+
+/// Checks that `start..end` is a non-empty range of a tree of `len` leaves.
+fn check_range(start: usize, end: usize, len: usize) -> Result<(), MerkleError> {
+    check_index(0, len)?;
+    if start >= end || end > len {
+        return Err(MerkleError::InvalidRange { start, end, len });
+    }
+    Ok(())
+}
+
+/// Calls `boundary` at every level below the root with the range of known nodes and whether the
+/// nodes left and right of it are siblings the verifier is missing, stopping at the first error.
+fn walk_boundaries(
+    layout: TreeLayout,
+    range: Range<usize>,
+    mut boundary: impl FnMut(usize, &Range<usize>, bool, bool) -> Result<(), MerkleError>,
+) -> Result<(), MerkleError> {
+    let mut known = range;
+    for height in 0..layout.height() {
+        let first = known.start;
+        let last = known.end - 1;
+        let left = !first.is_multiple_of(2);
+        let right = last.is_multiple_of(2) && layout.sibling(height, last).is_some();
+        boundary(height, &known, left, right)?;
+        known = first / 2..last / 2 + 1;
+    }
+    Ok(())
+}
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    /// Returns the proof for the leaves `start..end`, to be checked with [`verify_range`].
+    ///
+    /// Panics if the range is empty or extends beyond the tree.
+    pub fn range_proof(&self, start: usize, end: usize) -> RangeProof<H::Digest> {
+        self.try_range_proof(start, end).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`MerkleTree::range_proof`], but returns an error instead of panicking.
+    pub fn try_range_proof(&self, start: usize, end: usize) -> Result<RangeProof<H::Digest>, MerkleError> {
+        check_range(start, end, self.len())?;
+        let mut proof = RangeProof { tree_size: self.len(), left: Vec::new(), right: Vec::new() };
+        walk_boundaries(self.layout(), start..end, |height, known, left, right| {
+            let level = &self.levels()[height];
+            if left {
+                proof.left.push(level[known.start - 1]);
+            }
+            if right {
+                proof.right.push(level[known.end]);
+            }
+            Ok(())
+        })?;
+        Ok(proof)
+    }
+}
+
+/// Checks that `leaves` are the data blocks starting at index `start` of the padded tree of
+/// `tree_size` leaves with the given root.
+///
+/// The size must come from the verifier, not from the proof: the root of a padded tree does not
+/// fix its size, since padding leaves could be passed off as data beyond its end. A proof made for
+/// another size is rejected as [`MerkleError::MalformedProof`].
+pub fn verify_range<H: MerkleHasher, T: AsRef<[u8]>>(
+    root: &H::Digest,
+    start: usize,
+    tree_size: usize,
+    leaves: &[T],
+    proof: &RangeProof<H::Digest>,
+) -> Result<(), MerkleError> {
+    let leaf_hashes = leaves.iter().map(|leaf| H::hash_leaf(leaf.as_ref())).collect();
+    verify_range_for_digests::<H>(root, start, tree_size, leaf_hashes, proof)
+}
+
+/// Same as [`verify_range`], for leaves that were already hashed.
+pub fn verify_range_for_digests<H: MerkleHasher>(
+    root: &H::Digest,
+    start: usize,
+    tree_size: usize,
+    leaf_hashes: Vec<H::Digest>,
+    proof: &RangeProof<H::Digest>,
+) -> Result<(), MerkleError> {
+    verify_range_with_shape::<H>(root, start, tree_size, leaf_hashes, proof, TreeShape::Padded)
+}
+
+/// Same as [`verify_range_for_digests`], for a tree of the given shape.
+pub fn verify_range_with_shape<H: MerkleHasher>(
+    root: &H::Digest,
+    start: usize,
+    tree_size: usize,
+    leaf_hashes: Vec<H::Digest>,
+    proof: &RangeProof<H::Digest>,
+    shape: TreeShape,
+) -> Result<(), MerkleError> {
+    let end = start.saturating_add(leaf_hashes.len());
+    check_range(start, end, tree_size)?;
+    if proof.tree_size != tree_size {
+        return Err(MerkleError::MalformedProof);
+    }
+
+    let mut lefts = proof.left.iter();
+    let mut rights = proof.right.iter();
+    let mut nodes = leaf_hashes;
+    walk_boundaries(TreeLayout::new(proof.tree_size, shape), start..end, |_, _, left, right| {
+        let mut level = Vec::with_capacity(nodes.len() + 2);
+        if left {
+            level.push(*lefts.next().ok_or(MerkleError::MalformedProof)?);
+        }
+        level.append(&mut nodes);
+        if right {
+            level.push(*rights.next().ok_or(MerkleError::MalformedProof)?);
+        }
+        // The level now starts at a left child, so its pairs are siblings
+        nodes = (0..level.len().div_ceil(2)).map(|i| parent::<H>(&level, i)).collect();
+        Ok(())
+    })?;
+
+    if lefts.next().is_some() || rights.next().is_some() {
+        return Err(MerkleError::MalformedProof);
+    }
+    if nodes[..] != [*root] {
+        return Err(MerkleError::RootMismatch);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::tests::leaf_digests;
+    use crate::SipHasher64;
+
+    #[test]
+    fn every_range_verifies() {
+        for shape in [TreeShape::Padded, TreeShape::Unbalanced] {
+            for n in 1..=33 {
+                let leaves = leaf_digests(n);
+                let tree = MerkleTree::<SipHasher64>::from_leaf_digests_with_shape(leaves.clone(), shape);
+                for start in 0..n {
+                    for end in start + 1..=n {
+                        let proof = tree.range_proof(start, end);
+                        let indices: Vec<usize> = (start..end).collect();
+                        // Exactly the hashes a multiproof for the same leaves needs
+                        assert_eq!(tree.multiproof(&indices).hashes.len(), proof.left.len() + proof.right.len());
+                        let known = leaves[start..end].to_vec();
+                        assert_eq!(Ok(()), verify_range_with_shape::<SipHasher64>(&tree.root(), start, n, known, &proof, shape));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn proofs_only_contain_boundary_siblings() {
+        let tree = MerkleTree::<SipHasher64>::from_leaf_digests(leaf_digests(1 << 10));
+        let proof = tree.range_proof(100, 251);
+        assert!(proof.left.len() <= 10 && proof.right.len() <= 10);
+        let known = leaf_digests(1 << 10)[100..251].to_vec();
+        assert_eq!(Ok(()), verify_range_for_digests::<SipHasher64>(&tree.root(), 100, 1 << 10, known, &proof));
+        assert_eq!(RangeProof { tree_size: 1 << 10, left: vec![], right: vec![] }, tree.range_proof(0, 1 << 10));
+    }
+
+    #[test]
+    fn sentence_ranges() {
+        let sentence = "Here's an eight word sentence, special for you.";
+        let words: Vec<&str> = sentence.split_whitespace().collect();
+        let tree = MerkleTree::<SipHasher64>::from_sentence(sentence);
+        let proof = tree.range_proof(2, 5);
+        assert_eq!(Ok(()), verify_range::<SipHasher64, _>(&tree.root(), 2, 8, &words[2..5], &proof));
+        assert_eq!(Err(MerkleError::RootMismatch), verify_range::<SipHasher64, _>(&tree.root(), 2, 8, &words[1..4], &proof));
+        // The same words at another position need other siblings
+        assert_eq!(Err(MerkleError::MalformedProof), verify_range::<SipHasher64, _>(&tree.root(), 3, 8, &words[2..5], &proof));
+    }
+
+    #[test]
+    fn padding_is_not_data() {
+        // The padded tree of 10 leaves has the root of the 16 leaves that include its padding
+        let mut padded = leaf_digests(10);
+        padded.resize(16, SipHasher64::hash_leaf(&[]));
+        let tree = MerkleTree::<SipHasher64>::from_leaf_digests(leaf_digests(10));
+        let inflated = MerkleTree::<SipHasher64>::from_leaf_digests(padded.clone());
+        assert_eq!(tree.root(), inflated.root());
+
+        // A proof claiming 16 leaves proves the padding, but not against the known size
+        let proof = inflated.range_proof(8, 16);
+        let known = padded[8..16].to_vec();
+        assert_eq!(Ok(()), verify_range_for_digests::<SipHasher64>(&tree.root(), 8, 16, known.clone(), &proof));
+        assert_eq!(
+            Err(MerkleError::InvalidRange { start: 8, end: 16, len: 10 }),
+            verify_range_for_digests::<SipHasher64>(&tree.root(), 8, 10, known, &proof)
+        );
+        let known = padded[8..10].to_vec();
+        assert_eq!(
+            Err(MerkleError::MalformedProof),
+            verify_range_for_digests::<SipHasher64>(&tree.root(), 8, 10, known, &inflated.range_proof(8, 10))
+        );
+    }
+
+    #[test]
+    fn malformed_proofs_and_ranges() {
+        let leaves = leaf_digests(10);
+        let tree = MerkleTree::<SipHasher64>::from_leaf_digests(leaves.clone());
+        let root = tree.root();
+        let proof = tree.range_proof(3, 7);
+        let known = leaves[3..7].to_vec();
+
+        let mut extra = proof.clone();
+        extra.right.push(0);
+        assert_eq!(Err(MerkleError::MalformedProof), verify_range_for_digests::<SipHasher64>(&root, 3, 10, known.clone(), &extra));
+        let mut missing = proof.clone();
+        missing.left.pop();
+        assert_eq!(Err(MerkleError::MalformedProof), verify_range_for_digests::<SipHasher64>(&root, 3, 10, known.clone(), &missing));
+        let mut tampered = proof.clone();
+        tampered.right[0] ^= 1;
+        assert_eq!(Err(MerkleError::RootMismatch), verify_range_for_digests::<SipHasher64>(&root, 3, 10, known.clone(), &tampered));
+        let mut resized = proof.clone();
+        resized.tree_size = 6;
+        assert_eq!(Err(MerkleError::MalformedProof), verify_range_for_digests::<SipHasher64>(&root, 3, 10, known, &resized));
+        assert_eq!(
+            Err(MerkleError::InvalidRange { start: 3, end: 7, len: 6 }),
+            verify_range_for_digests::<SipHasher64>(&root, 3, 6, leaves[3..7].to_vec(), &resized)
+        );
+        assert_eq!(
+            Err(MerkleError::InvalidRange { start: 3, end: 3, len: 10 }),
+            verify_range_for_digests::<SipHasher64>(&root, 3, 10, vec![], &proof)
+        );
+
+        assert_eq!(Err(MerkleError::InvalidRange { start: 4, end: 4, len: 10 }), tree.try_range_proof(4, 4));
+        assert_eq!(Err(MerkleError::InvalidRange { start: 4, end: 11, len: 10 }), tree.try_range_proof(4, 11));
+        let empty = MerkleTree::<SipHasher64>::from_leaf_digests_with_shape(vec![], TreeShape::Unbalanced);
+        assert_eq!(Err(MerkleError::EmptyTree), empty.try_range_proof(0, 1));
+    }
+}
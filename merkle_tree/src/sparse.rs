@@ -0,0 +1,379 @@
+//! A sparse Merkle tree over 256-bit keys.
+//!
+//! Every possible key has a fixed leaf, so the tree conceptually has 2^256 leaves and is 256
+//! levels deep. Almost all of them are empty: an empty leaf is `H::Digest::default()`, and the
+//! root of an empty subtree of every height is precomputed once, so only the nodes above
+//! non-empty leaves are stored and an update or proof touches 256 nodes. Because the position of
+//! a leaf is determined by its key, a proof that the leaf is empty proves that the key is absent.
+
+use crate::{concatenate_hash_values, MerkleDigest, MerkleHasher};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Number of levels between a leaf and the root of a [`SparseMerkleTree`].
+pub const SPARSE_TREE_DEPTH: usize = 256;
+
+/// A key of a [`SparseMerkleTree`], read as a big-endian 256-bit path from the root to the leaf.
+pub type SparseKey = [u8; 32];
+
+/// Returns the bit of `key` that decides whether its node at `height` is a left (`false`) or right
+/// (`true`) child. The last bit of the key chooses between the leaves.
+fn is_right(key: &SparseKey, height: usize) -> bool {
+    key[31 - height / 8] >> (height % 8) & 1 == 1
+}
+
+/// Returns the key of the node at `height` on the path of `key`, i.e. `key` with its `height`
+/// lowest bits cleared.
+fn node_key(key: &SparseKey, height: usize) -> SparseKey {
+    let mut node = *key;
+    for bit in 0..height.min(SPARSE_TREE_DEPTH) {
+        node[31 - bit / 8] &= !(1 << (bit % 8));
+    }
+    node
+}
+
+/// Returns the key of the sibling of the node at `height` on the path of `key`.
+fn sibling_key(key: &SparseKey, height: usize) -> SparseKey {
+    let mut sibling = node_key(key, height);
+    sibling[31 - height / 8] ^= 1 << (height % 8);
+    sibling
+}
+
+/// Returns the roots of empty subtrees, indexed by height, from an empty leaf to an empty tree.
+fn empty_hashes<H: MerkleHasher>() -> Vec<H::Digest> {
+    let mut hashes = vec![H::Digest::default()];
+    for height in 0..SPARSE_TREE_DEPTH {
+        let empty = hashes[height];
+        hashes.push(concatenate_hash_values::<H>(empty, empty));
+    }
+    hashes
+}
+
+/// A proof for one key of a [`SparseMerkleTree`], proving either the key's value or its absence.
+///
+/// Siblings that are roots of empty subtrees are left out; `non_empty` has bit `h % 8` of byte
+/// `h / 8` set if the sibling at height `h` is in `siblings`, which are ordered from the leaf up.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct SparseMerkleProof<D> {
+    pub non_empty: [u8; 32],
+    pub siblings: Vec<D>,
+}
+
+/// A sparse Merkle tree with 2^256 leaves, addressed by [`SparseKey`].
+#[derive(Debug, Clone)]
+pub struct SparseMerkleTree<H: MerkleHasher> {
+    // The non-empty nodes of each level, keyed by the key of their leftmost leaf
+    levels: Vec<HashMap<SparseKey, H::Digest>>,
+    empty_hashes: Vec<H::Digest>,
+}
+
+impl<H: MerkleHasher> Default for SparseMerkleTree<H> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<H: MerkleHasher> SparseMerkleTree<H> {
+    /// Creates a tree in which every key is absent.
+    pub fn new() -> Self {
+        SparseMerkleTree {
+            levels: vec![HashMap::new(); SPARSE_TREE_DEPTH + 1],
+            empty_hashes: empty_hashes::<H>(),
+        }
+    }
+
+    /// Returns the root hash of the tree.
+    pub fn root(&self) -> H::Digest {
+        self.node(&[0; 32], SPARSE_TREE_DEPTH)
+    }
+
+    /// Returns the root of a tree without any keys.
+    pub fn empty_root() -> H::Digest {
+        empty_hashes::<H>()[SPARSE_TREE_DEPTH]
+    }
+
+    /// Returns the number of keys present in the tree.
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    /// Returns `true` if no key is present.
+    pub fn is_empty(&self) -> bool {
+        self.levels[0].is_empty()
+    }
+
+    /// Returns the leaf digest stored under `key`, if any.
+    pub fn get(&self, key: &SparseKey) -> Option<H::Digest> {
+        self.levels[0].get(key).copied()
+    }
+
+    /// Returns `true` if `key` is present.
+    pub fn contains_key(&self, key: &SparseKey) -> bool {
+        self.levels[0].contains_key(key)
+    }
+
+    /// Hashes `value` with [`MerkleHasher::hash_leaf`] and stores it under `key`, replacing the
+    /// previous value. Returns the new root.
+    pub fn insert<T: AsRef<[u8]>>(&mut self, key: SparseKey, value: T) -> H::Digest {
+        self.insert_digest(key, H::hash_leaf(value.as_ref()))
+    }
+
+    /// Stores an already hashed leaf under `key`, replacing the previous value. Returns the new
+    /// root.
+    ///
+    /// The default digest marks empty leaves, so inserting it is the same as removing the key.
+    pub fn insert_digest(&mut self, key: SparseKey, leaf: H::Digest) -> H::Digest {
+        self.update_path(&key, leaf)
+    }
+
+    /// Removes `key` from the tree and returns its leaf digest, if it was present.
+    pub fn remove(&mut self, key: &SparseKey) -> Option<H::Digest> {
+        let leaf = self.get(key)?;
+        self.update_path(key, H::Digest::default());
+        Some(leaf)
+    }
+
+    /// Returns the proof for `key`. If the key is present it proves its value with
+    /// [`verify_sparse_inclusion`], otherwise it proves its absence with
+    /// [`verify_sparse_non_inclusion`].
+    pub fn proof(&self, key: &SparseKey) -> SparseMerkleProof<H::Digest> {
+        let mut proof = SparseMerkleProof { non_empty: [0; 32], siblings: Vec::new() };
+        for height in 0..SPARSE_TREE_DEPTH {
+            if let Some(sibling) = self.levels[height].get(&sibling_key(key, height)) {
+                proof.non_empty[height / 8] |= 1 << (height % 8);
+                proof.siblings.push(*sibling);
+            }
+        }
+        proof
+    }
+
+    fn node(&self, key: &SparseKey, height: usize) -> H::Digest {
+        self.levels[height].get(key).copied().unwrap_or(self.empty_hashes[height])
+    }
+
+    /// Sets the leaf of `key` and recomputes the nodes on its path, dropping the ones that became
+    /// empty.
+    fn update_path(&mut self, key: &SparseKey, leaf: H::Digest) -> H::Digest {
+        let mut node = leaf;
+        for height in 0..=SPARSE_TREE_DEPTH {
+            let level = &mut self.levels[height];
+            if node == self.empty_hashes[height] {
+                level.remove(&node_key(key, height));
+            } else {
+                level.insert(node_key(key, height), node);
+            }
+            if height == SPARSE_TREE_DEPTH {
+                break;
+            }
+
+            let sibling = self.node(&sibling_key(key, height), height);
+            node = if is_right(key, height) {
+                concatenate_hash_values::<H>(sibling, node)
+            } else {
+                concatenate_hash_values::<H>(node, sibling)
+            };
+        }
+        node
+    }
+}
+
+/// Recomputes the root from the leaf of `key` and its proof. Returns `None` if the proof does not
+/// have exactly as many siblings as it claims.
+fn sparse_root_from_proof<H: MerkleHasher>(
+    key: &SparseKey,
+    leaf: H::Digest,
+    proof: &SparseMerkleProof<H::Digest>,
+) -> Option<H::Digest> {
+    let mut siblings = proof.siblings.iter();
+    let mut node = leaf;
+    let mut empty = H::Digest::default();
+    for height in 0..SPARSE_TREE_DEPTH {
+        let sibling = if proof.non_empty[height / 8] >> (height % 8) & 1 == 1 {
+            *siblings.next()?
+        } else {
+            empty
+        };
+        node = if is_right(key, height) {
+            concatenate_hash_values::<H>(sibling, node)
+        } else {
+            concatenate_hash_values::<H>(node, sibling)
+        };
+        empty = concatenate_hash_values::<H>(empty, empty);
+    }
+    siblings.next().is_none().then_some(node)
+}
+
+/// Checks that `value` is stored under `key` in the sparse tree with root `root`.
+pub fn verify_sparse_inclusion<H: MerkleHasher>(
+    root: &H::Digest,
+    key: &SparseKey,
+    value: &[u8],
+    proof: &SparseMerkleProof<H::Digest>,
+) -> bool {
+    verify_sparse_inclusion_for_digest::<H>(root, key, H::hash_leaf(value), proof)
+}
+
+/// Checks that the already hashed `leaf` is stored under `key` in the sparse tree with root
+/// `root`.
+pub fn verify_sparse_inclusion_for_digest<H: MerkleHasher>(
+    root: &H::Digest,
+    key: &SparseKey,
+    leaf: H::Digest,
+    proof: &SparseMerkleProof<H::Digest>,
+) -> bool {
+    leaf != H::Digest::default() && sparse_root_from_proof::<H>(key, leaf, proof) == Some(*root)
+}
+
+/// Checks that `key` is absent from the sparse tree with root `root`.
+pub fn verify_sparse_non_inclusion<H: MerkleHasher>(
+    root: &H::Digest,
+    key: &SparseKey,
+    proof: &SparseMerkleProof<H::Digest>,
+) -> bool {
+    sparse_root_from_proof::<H>(key, H::Digest::default(), proof) == Some(*root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DomainSeparated, Sha256Hasher, SipHasher64};
+
+    type Hasher = DomainSeparated<Sha256Hasher>;
+
+    fn key(name: &str) -> SparseKey {
+        Sha256Hasher::hash_parts(&[name.as_bytes()])
+    }
+
+    #[test]
+    fn empty_tree_root() {
+        let tree = SparseMerkleTree::<Hasher>::new();
+        assert_eq!(SparseMerkleTree::<Hasher>::empty_root(), tree.root());
+        assert!(tree.is_empty());
+
+        // The empty root only depends on the hasher
+        let mut expected = [0u8; 32];
+        for _ in 0..SPARSE_TREE_DEPTH {
+            expected = Hasher::hash_nodes(&expected, &expected);
+        }
+        assert_eq!(expected, tree.root());
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut tree = SparseMerkleTree::<Hasher>::new();
+        let empty_root = tree.root();
+
+        let root = tree.insert(key("file1.txt"), "content 1");
+        assert_eq!(root, tree.root());
+        assert_ne!(empty_root, root);
+        assert_eq!(Some(Hasher::hash_leaf(b"content 1")), tree.get(&key("file1.txt")));
+        assert_eq!(None, tree.get(&key("file2.txt")));
+
+        tree.insert(key("file2.txt"), "content 2");
+        assert_eq!(2, tree.len());
+        assert_eq!(Some(Hasher::hash_leaf(b"content 2")), tree.remove(&key("file2.txt")));
+        assert_eq!(None, tree.remove(&key("file2.txt")));
+        assert_eq!(root, tree.root());
+
+        tree.remove(&key("file1.txt"));
+        assert_eq!(empty_root, tree.root());
+        // Empty nodes are dropped again, not just reset
+        assert!(tree.levels.iter().all(|level| level.is_empty()));
+    }
+
+    #[test]
+    fn root_is_independent_of_insertion_order() {
+        let names: Vec<String> = (0..20).map(|i| format!("file{}.txt", i)).collect();
+        let mut forward = SparseMerkleTree::<Hasher>::new();
+        let mut backward = SparseMerkleTree::<Hasher>::new();
+        for name in &names {
+            forward.insert(key(name), name);
+        }
+        for name in names.iter().rev() {
+            backward.insert(key(name), name);
+        }
+        assert_eq!(forward.root(), backward.root());
+
+        backward.insert(key("file3.txt"), "changed");
+        assert_ne!(forward.root(), backward.root());
+        backward.insert(key("file3.txt"), "file3.txt");
+        assert_eq!(forward.root(), backward.root());
+    }
+
+    #[test]
+    fn inclusion_and_non_inclusion_proofs() {
+        let mut tree = SparseMerkleTree::<Hasher>::new();
+        for i in 0..10 {
+            tree.insert(key(&format!("file{}.txt", i)), format!("content {}", i));
+        }
+        let root = tree.root();
+
+        for i in 0..10 {
+            let key = key(&format!("file{}.txt", i));
+            let proof = tree.proof(&key);
+            let content = format!("content {}", i);
+            assert!(verify_sparse_inclusion::<Hasher>(&root, &key, content.as_bytes(), &proof));
+            assert!(!verify_sparse_inclusion::<Hasher>(&root, &key, b"other content", &proof));
+            assert!(!verify_sparse_non_inclusion::<Hasher>(&root, &key, &proof));
+        }
+
+        for i in 10..20 {
+            let key = key(&format!("file{}.txt", i));
+            let proof = tree.proof(&key);
+            assert!(verify_sparse_non_inclusion::<Hasher>(&root, &key, &proof));
+            assert!(!verify_sparse_inclusion::<Hasher>(&root, &key, b"content", &proof));
+        }
+    }
+
+    #[test]
+    fn proofs_only_contain_non_empty_siblings() {
+        let mut tree = SparseMerkleTree::<SipHasher64>::new();
+        // Two keys that share every bit but the last one are siblings
+        let mut left = [0u8; 32];
+        let mut right = [0u8; 32];
+        left[0] = 0x80;
+        right[0] = 0x80;
+        right[31] = 1;
+        tree.insert(left, "left");
+        tree.insert(right, "right");
+
+        let proof = tree.proof(&left);
+        assert_eq!(vec![SipHasher64::hash_leaf(b"right")], proof.siblings);
+        assert_eq!(1, proof.non_empty[0]);
+        assert!(verify_sparse_inclusion::<SipHasher64>(&tree.root(), &left, b"left", &proof));
+
+        // The empty half of the tree only needs the root of the other half
+        let absent = [0u8; 32];
+        let proof = tree.proof(&absent);
+        assert_eq!(1, proof.siblings.len());
+        assert_eq!(0x80, proof.non_empty[31]);
+        assert!(verify_sparse_non_inclusion::<SipHasher64>(&tree.root(), &absent, &proof));
+    }
+
+    #[test]
+    fn malformed_proofs_are_rejected() {
+        let mut tree = SparseMerkleTree::<Hasher>::new();
+        for i in 0..5 {
+            tree.insert(key(&format!("file{}.txt", i)), "content");
+        }
+        let root = tree.root();
+        let absent = key("missing.txt");
+        let proof = tree.proof(&absent);
+        assert!(verify_sparse_non_inclusion::<Hasher>(&root, &absent, &proof));
+
+        let mut extra = proof.clone();
+        extra.siblings.push([0; 32]);
+        assert!(!verify_sparse_non_inclusion::<Hasher>(&root, &absent, &extra));
+
+        let mut missing = proof.clone();
+        missing.siblings.pop();
+        assert!(!verify_sparse_non_inclusion::<Hasher>(&root, &absent, &missing));
+
+        let mut tampered = proof.clone();
+        tampered.siblings[0][0] ^= 1;
+        assert!(!verify_sparse_non_inclusion::<Hasher>(&root, &absent, &tampered));
+
+        // A proof for one key says nothing about another
+        assert!(!verify_sparse_non_inclusion::<Hasher>(&root, &key("other.txt"), &proof));
+    }
+}
@@ -0,0 +1,308 @@
+//! Merkle sum trees, where every node carries the sum of the values below it.
+//!
+//! A leaf is a hash and a value, such as a user's balance, and an interior node is the hash of
+//! both children's hashes and sums together with their total, so the root commits to the sum of
+//! all values. This is the commitment of proof-of-reserves schemes: a user who checks that their
+//! balance is included also learns the sums of the sibling subtrees along the path, and since
+//! every sum is part of the hash above it, the total at the root cannot leave out their balance.
+//!
+//! The trees are ordinary [`MerkleTree`]s over [`SumHasher`], so proofs are [`SiblingNode`]s
+//! whose siblings carry their sums, and multiproofs and binary encodings work unchanged. Values
+//! are `u64`, so no sum can be negative; the sum verifiers below additionally reject proofs whose
+//! sums overflow, which would otherwise let a tree wrap around and hide liabilities.
+
+use crate::error::check_index;
+use crate::{
+    try_validate_compact_multiproof_with_shape, verify_digest_at_with_shape, CompactMerkleMultiProof, MerkleDigest,
+    MerkleError, MerkleHasher, MerkleProof, MerkleTree, SiblingNode, TreeShape, LEAF_PREFIX, NODE_PREFIX,
+};
+use serde::{Deserialize, Serialize};
+use std::marker::PhantomData;
+
+/// A node of a Merkle sum tree: its hash and the sum of the values of the leaves below it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub struct SumNode<D> {
+    pub hash: D,
+    pub sum: u64,
+}
+
+impl<D: MerkleDigest> MerkleDigest for SumNode<D> {
+    type Bytes = Vec<u8>;
+    const LEN: usize = D::LEN + 8;
+
+    /// The bytes of the hash followed by the sum in big-endian byte order.
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LEN);
+        bytes.extend_from_slice(self.hash.to_bytes().as_ref());
+        bytes.extend_from_slice(&self.sum.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != Self::LEN {
+            return None;
+        }
+        let (hash, sum) = bytes.split_at(D::LEN);
+        Some(SumNode { hash: D::from_bytes(hash)?, sum: u64::from_be_bytes(sum.try_into().ok()?) })
+    }
+}
+
+/// A proof for a leaf of a Merkle sum tree, each sibling with its sum.
+pub type MerkleSumProof<D> = MerkleProof<SumNode<D>>;
+
+/// Turns a hasher into the hasher of a Merkle sum tree. Leaves are hashed as `H(0x00 || data)`
+/// and interior nodes as `H(0x01 || left hash || left sum || right hash || right sum)`, with their
+/// children's total as their sum, so `H` should be a plain hash function such as
+/// [`crate::Sha256Hasher`] rather than [`crate::DomainSeparated`].
+///
+/// [`MerkleHasher::hash_leaf`] makes leaves with a value of 0, which is what padding leaves have;
+/// use [`SumHasher::hash_leaf_with_sum`] for the others. A sum that overflows saturates at
+/// `u64::MAX` instead of panicking, so that the generic validators cannot be made to panic; trees
+/// built with [`MerkleTree::from_balances`] and proofs checked with [`verify_sum_proof`] or
+/// [`verify_sum_multiproof`] never get that far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SumHasher<H>(PhantomData<H>);
+
+impl<H: MerkleHasher> SumHasher<H> {
+    /// Hashes a data block into a leaf holding `sum`.
+    pub fn hash_leaf_with_sum(data: &[u8], sum: u64) -> SumNode<H::Digest> {
+        SumNode { hash: H::hash_parts(&[&[LEAF_PREFIX], data]), sum }
+    }
+}
+
+impl<H: MerkleHasher> MerkleHasher for SumHasher<H> {
+    type Digest = SumNode<H::Digest>;
+    // Tells sum trees apart from the plain and the domain-separated hash function
+    const ALGORITHM_ID: u8 = H::ALGORITHM_ID | 0x40;
+
+    fn hash_parts(parts: &[&[u8]]) -> SumNode<H::Digest> {
+        SumNode { hash: H::hash_parts(parts), sum: 0 }
+    }
+
+    fn hash_leaf(data: &[u8]) -> SumNode<H::Digest> {
+        Self::hash_leaf_with_sum(data, 0)
+    }
+
+    fn hash_nodes(left: &SumNode<H::Digest>, right: &SumNode<H::Digest>) -> SumNode<H::Digest> {
+        SumNode {
+            hash: H::hash_parts(&[&[NODE_PREFIX], &left.to_bytes(), &right.to_bytes()]),
+            sum: left.sum.saturating_add(right.sum),
+        }
+    }
+
+    fn hash_children(children: &[SumNode<H::Digest>]) -> SumNode<H::Digest> {
+        let bytes: Vec<_> = children.iter().map(MerkleDigest::to_bytes).collect();
+        let mut parts: Vec<&[u8]> = vec![&[NODE_PREFIX]];
+        parts.extend(bytes.iter().map(Vec::as_slice));
+        let sum = children.iter().fold(0, |total: u64, child| total.saturating_add(child.sum));
+        SumNode { hash: H::hash_parts(&parts), sum }
+    }
+}
+
+impl<H: MerkleHasher> MerkleTree<SumHasher<H>> {
+    /// Builds a padded sum tree over `(data, value)` leaves, or returns
+    /// [`MerkleError::SumOverflow`] if the values add up to more than `u64::MAX`.
+    pub fn from_balances<T: AsRef<[u8]>>(leaves: &[(T, u64)]) -> Result<Self, MerkleError> {
+        Self::from_balances_with_shape(leaves, TreeShape::Padded)
+    }
+
+    /// Same as [`MerkleTree::from_balances`], for a tree of the given shape.
+    pub fn from_balances_with_shape<T: AsRef<[u8]>>(
+        leaves: &[(T, u64)],
+        shape: TreeShape,
+    ) -> Result<Self, MerkleError> {
+        // Every interior sum is at most the total, so checking the total is enough
+        checked_total(leaves.iter().map(|(_, sum)| *sum))?;
+        let nodes = leaves
+            .iter()
+            .map(|(data, sum)| SumHasher::<H>::hash_leaf_with_sum(data.as_ref(), *sum))
+            .collect();
+        Ok(Self::from_leaf_digests_with_shape(nodes, shape))
+    }
+
+    /// The sum of the values of all leaves.
+    pub fn total(&self) -> u64 {
+        self.root().sum
+    }
+}
+
+/// Adds up `sums`, failing if the total does not fit into a `u64`.
+fn checked_total(sums: impl IntoIterator<Item = u64>) -> Result<u64, MerkleError> {
+    sums.into_iter().try_fold(0u64, |total, sum| total.checked_add(sum).ok_or(MerkleError::SumOverflow))
+}
+
+/// Checks that `leaf`, made with [`SumHasher::hash_leaf_with_sum`], is the leaf at `index` of the
+/// padded sum tree of `tree_size` leaves with the given root, and that none of the sums on its path
+/// overflow.
+///
+/// The leaf and the siblings of a proof cover disjoint parts of the tree, so every sum on the path
+/// is at most their total, which is checked before anything is hashed.
+pub fn verify_sum_proof<H: MerkleHasher>(
+    root: &SumNode<H::Digest>,
+    index: usize,
+    tree_size: usize,
+    leaf: SumNode<H::Digest>,
+    proof: &MerkleSumProof<H::Digest>,
+) -> Result<(), MerkleError> {
+    verify_sum_proof_with_shape::<H>(root, index, tree_size, leaf, proof, TreeShape::Padded)
+}
+
+/// Same as [`verify_sum_proof`], for a tree of the given shape.
+pub fn verify_sum_proof_with_shape<H: MerkleHasher>(
+    root: &SumNode<H::Digest>,
+    index: usize,
+    tree_size: usize,
+    leaf: SumNode<H::Digest>,
+    proof: &MerkleSumProof<H::Digest>,
+    shape: TreeShape,
+) -> Result<(), MerkleError> {
+    check_index(index, tree_size)?;
+    let siblings = proof.iter().map(|(SiblingNode::Left(node) | SiblingNode::Right(node))| node.sum);
+    checked_total(siblings.chain([leaf.sum]))?;
+    verify_digest_at_with_shape::<SumHasher<H>>(root, index, tree_size, leaf, proof, shape)
+}
+
+/// Checks that `leaves`, given in the order of the proof's indices, are in the padded sum tree with
+/// the given root, and that none of the sums needed to reach the root overflow.
+pub fn verify_sum_multiproof<H: MerkleHasher>(
+    root: &SumNode<H::Digest>,
+    leaves: Vec<SumNode<H::Digest>>,
+    proof: &CompactMerkleMultiProof<SumNode<H::Digest>>,
+) -> Result<(), MerkleError> {
+    verify_sum_multiproof_with_shape::<H>(root, leaves, proof, TreeShape::Padded)
+}
+
+/// Same as [`verify_sum_multiproof`], for a tree of the given shape.
+pub fn verify_sum_multiproof_with_shape<H: MerkleHasher>(
+    root: &SumNode<H::Digest>,
+    leaves: Vec<SumNode<H::Digest>>,
+    proof: &CompactMerkleMultiProof<SumNode<H::Digest>>,
+    shape: TreeShape,
+) -> Result<(), MerkleError> {
+    // As for single proofs, the leaves and proof hashes are disjoint subtrees
+    checked_total(leaves.iter().chain(&proof.hashes).map(|node| node.sum))?;
+    match try_validate_compact_multiproof_with_shape::<SumHasher<H>>(root, leaves, proof.clone(), shape)? {
+        true => Ok(()),
+        false => Err(MerkleError::RootMismatch),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{proof_from_bytes, proof_to_bytes, validate_proof_for_digest, SipHasher64};
+
+    type Hasher = SumHasher<SipHasher64>;
+
+    fn balances(n: usize) -> Vec<(String, u64)> {
+        (0..n).map(|i| (format!("user{}", i), (i as u64 + 1) * 100)).collect()
+    }
+
+    fn leaf((data, sum): &(String, u64)) -> SumNode<u64> {
+        Hasher::hash_leaf_with_sum(data.as_bytes(), *sum)
+    }
+
+    #[test]
+    fn nodes_commit_to_child_sums() {
+        let tree = MerkleTree::<Hasher>::from_balances(&balances(3)).unwrap();
+        assert_eq!(600, tree.total());
+        let (a, b, c) = (leaf(&balances(3)[0]), leaf(&balances(3)[1]), leaf(&balances(3)[2]));
+        let padding = Hasher::hash_leaf(&[]);
+        assert_eq!(0, padding.sum);
+        assert_eq!(Hasher::hash_nodes(&Hasher::hash_nodes(&a, &b), &Hasher::hash_nodes(&c, &padding)), tree.root());
+
+        // The same leaves with their values moved around have the same total but another root
+        let moved = SumNode { sum: b.sum + 1, ..b };
+        let other = Hasher::hash_nodes(&SumNode { sum: a.sum - 1, ..a }, &moved);
+        assert_eq!(Hasher::hash_nodes(&a, &b).sum, other.sum);
+        assert_ne!(Hasher::hash_nodes(&a, &b).hash, other.hash);
+    }
+
+    #[test]
+    fn every_proof_verifies() {
+        for shape in [TreeShape::Padded, TreeShape::Unbalanced] {
+            for n in 1..=17 {
+                let balances = balances(n);
+                let tree = MerkleTree::<Hasher>::from_balances_with_shape(&balances, shape).unwrap();
+                assert_eq!(50 * n as u64 * (n as u64 + 1), tree.total());
+                for (index, balance) in balances.iter().enumerate() {
+                    let proof = tree.proof(index);
+                    assert_eq!(
+                        Ok(()),
+                        verify_sum_proof_with_shape::<SipHasher64>(&tree.root(), index, n, leaf(balance), &proof, shape)
+                    );
+                    // Claiming another balance for the same user fails
+                    let inflated = SumNode { sum: balance.1 + 1, ..leaf(balance) };
+                    assert_eq!(
+                        Err(MerkleError::RootMismatch),
+                        verify_sum_proof_with_shape::<SipHasher64>(&tree.root(), index, n, inflated, &proof, shape)
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn every_multiproof_verifies() {
+        for n in 1..=8 {
+            let balances = balances(n);
+            let tree = MerkleTree::<Hasher>::from_balances(&balances).unwrap();
+            for subset in 1..1u32 << n {
+                let indices: Vec<usize> = (0..n).filter(|&i| subset & (1 << i) != 0).rev().collect();
+                let multiproof = tree.multiproof(&indices);
+                let leaves = indices.iter().map(|&i| leaf(&balances[i])).collect();
+                assert_eq!(Ok(()), verify_sum_multiproof::<SipHasher64>(&tree.root(), leaves, &multiproof));
+            }
+        }
+    }
+
+    #[test]
+    fn overflowing_sums_are_rejected() {
+        let whales = [("a", u64::MAX), ("b", 1)];
+        assert_eq!(
+            Err(MerkleError::SumOverflow),
+            MerkleTree::<Hasher>::from_balances(&whales).map(|tree| tree.total())
+        );
+
+        // A tree built without the check saturates, which the generic validator cannot tell
+        let leaves = whales.iter().map(|(data, sum)| Hasher::hash_leaf_with_sum(data.as_bytes(), *sum)).collect();
+        let forged = MerkleTree::<Hasher>::from_leaf_digests(leaves);
+        assert_eq!(u64::MAX, forged.total());
+        let b = Hasher::hash_leaf_with_sum(b"b", 1);
+        assert!(validate_proof_for_digest::<Hasher>(&forged.root(), b, forged.proof(1)));
+        assert_eq!(
+            Err(MerkleError::SumOverflow),
+            verify_sum_proof::<SipHasher64>(&forged.root(), 1, 2, b, &forged.proof(1))
+        );
+        let multiproof = forged.multiproof(&[1]);
+        assert_eq!(
+            Err(MerkleError::SumOverflow),
+            verify_sum_multiproof::<SipHasher64>(&forged.root(), vec![b], &multiproof)
+        );
+
+        // Sums that reach u64::MAX exactly are fine
+        let full = MerkleTree::<Hasher>::from_balances(&[("a", u64::MAX - 1), ("b", 1)]).unwrap();
+        assert_eq!(u64::MAX, full.total());
+        assert_eq!(Ok(()), verify_sum_proof::<SipHasher64>(&full.root(), 1, 2, b, &full.proof(1)));
+    }
+
+    #[test]
+    fn proofs_carry_sibling_sums() {
+        let balances = balances(4);
+        let tree = MerkleTree::<Hasher>::from_balances(&balances).unwrap();
+        let sums: Vec<u64> = tree
+            .proof(0)
+            .iter()
+            .map(|(SiblingNode::Left(node) | SiblingNode::Right(node))| node.sum)
+            .collect();
+        assert_eq!(vec![200, 700], sums);
+
+        // Sums survive the binary encoding, which is bound to the sum hasher
+        let bytes = proof_to_bytes::<Hasher>(&tree.proof(0), 4);
+        assert_eq!(Ok((tree.proof(0), 4)), proof_from_bytes::<Hasher>(&bytes));
+        assert_eq!(
+            Err(MerkleError::HashAlgorithmMismatch { expected: 1, actual: 0x41 }),
+            proof_from_bytes::<SipHasher64>(&bytes).map(|_| ())
+        );
+    }
+}
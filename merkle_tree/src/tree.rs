@@ -0,0 +1,542 @@
+//! A Merkle tree that is built once and keeps every level in memory.
+//!
+//! The free functions in the crate root rebuild the whole tree on every call. [`MerkleTree`] hashes
+//! the leaves once, after which the root is a lookup and proofs only read the O(log n) nodes on the
+//! path from the leaf to the root.
+
+use crate::{
+    error::{check_index, check_indices},
+    hash_leaves,
+    layout::parent,
+    CompactMerkleMultiProof, CompactMerkleProof, MerkleError, MerkleHasher, MerkleProof, TreeLayout,
+};
+
+/// How a tree is shaped when the number of leaves is not a power of two.
+///
+/// ```text
+///              Padded                         Unbalanced
+///                 O                                O
+///             /       \                        /     \
+///           O           O                     O       X
+///         /   \       /   \                 /   \
+///        O     O     O     O               O     O
+///       / \   / \   / \   / \             / \   / \
+///      X  X  X  X  X  '' '' ''            X  X  X  X
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TreeShape {
+    /// The leaves are padded with empty leaves up to the next power of two, like
+    /// [`crate::calculate_merkle_root`] does.
+    #[default]
+    Padded,
+    /// No padding: the largest power of two number of leaves goes into the left subtree and the
+    /// rest into the right one, as in Certificate Transparency (RFC 6962). Building the tree level
+    /// by level, a node without a sibling is promoted to the next level unchanged. An empty tree
+    /// has the hash of the empty string as its root.
+    Unbalanced,
+}
+
+/// A binary Merkle tree over a list of data blocks. By default it is padded to a power of two
+/// with empty leaves like [`crate::calculate_merkle_root`] does, see [`TreeShape`].
+///
+/// `levels[0]` holds the (padded) leaf hashes and the last level holds only the root.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleTree<H: MerkleHasher> {
+    levels: Vec<Vec<H::Digest>>,
+    layout: TreeLayout,
+}
+
+impl<H: MerkleHasher> MerkleTree<H> {
+    /// Builds the tree over the whitespace-separated words of `sentence`.
+    pub fn from_sentence(sentence: &str) -> Self {
+        let words: Vec<&str> = sentence.split_whitespace().collect();
+        Self::from_leaves(&words)
+    }
+
+    /// Builds the tree over `leaves`, one leaf per data block.
+    pub fn from_leaves<T: AsRef<[u8]>>(leaves: &[T]) -> Self {
+        Self::from_leaf_digests(hash_leaves::<H, T>(leaves))
+    }
+
+    /// Builds the tree over leaves that were already hashed with [`MerkleHasher::hash_leaf`].
+    pub fn from_leaf_digests(leaves: Vec<H::Digest>) -> Self {
+        Self::from_leaf_digests_with_shape(leaves, TreeShape::Padded)
+    }
+
+    /// Same as [`MerkleTree::from_leaves`] with the given shape.
+    pub fn from_leaves_with_shape<T: AsRef<[u8]>>(leaves: &[T], shape: TreeShape) -> Self {
+        Self::from_leaf_digests_with_shape(hash_leaves::<H, T>(leaves), shape)
+    }
+
+    /// Same as [`MerkleTree::from_leaf_digests`] with the given shape.
+    pub fn from_leaf_digests_with_shape(leaves: Vec<H::Digest>, shape: TreeShape) -> Self {
+        let layout = TreeLayout::new(leaves.len(), shape);
+        let levels = layout.build_levels::<H>(leaves);
+        MerkleTree { levels, layout }
+    }
+
+    /// The Merkle root.
+    pub fn root(&self) -> H::Digest {
+        match self.levels[self.levels.len() - 1].first() {
+            Some(&root) => root,
+            // Only an unbalanced tree can have no nodes at all
+            None => H::hash_parts(&[]),
+        }
+    }
+
+    /// The shape the tree was built with.
+    pub fn shape(&self) -> TreeShape {
+        self.layout.shape()
+    }
+
+    /// The layout of the tree, which all of its proofs follow.
+    pub fn layout(&self) -> TreeLayout {
+        self.layout
+    }
+
+    /// The number of leaves the tree was built from, not counting padding.
+    pub fn len(&self) -> usize {
+        self.layout.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layout.is_empty()
+    }
+
+    /// The number of levels above the leaves, which is also the length of every proof in a padded
+    /// tree. Proofs in an unbalanced tree skip the levels where the node has no sibling.
+    pub fn height(&self) -> usize {
+        self.layout.height()
+    }
+
+    /// All levels of the tree, from the leaves up to the root.
+    pub fn levels(&self) -> &[Vec<H::Digest>] {
+        &self.levels
+    }
+
+    /// The nodes at `height` above the leaves, or `None` above the root.
+    pub fn level(&self, height: usize) -> Option<&[H::Digest]> {
+        self.levels.get(height).map(Vec::as_slice)
+    }
+
+    /// The node at `index` within the level at `height`.
+    pub fn node(&self, height: usize, index: usize) -> Option<H::Digest> {
+        self.level(height)?.get(index).copied()
+    }
+
+    /// The leaf hashes, including padding if the tree is padded.
+    pub fn leaves(&self) -> &[H::Digest] {
+        &self.levels[0]
+    }
+
+    /// Replaces the data block at `index` and returns the new root. Only the nodes on the path
+    /// from the leaf to the root are recomputed.
+    ///
+    /// Panics if the index is beyond the number of leaves.
+    pub fn update_leaf<T: AsRef<[u8]>>(&mut self, index: usize, leaf: T) -> H::Digest {
+        self.update_leaves(&[(index, leaf)])
+    }
+
+    /// Same as [`MerkleTree::update_leaf`], but returns an error instead of panicking.
+    pub fn try_update_leaf<T: AsRef<[u8]>>(&mut self, index: usize, leaf: T) -> Result<H::Digest, MerkleError> {
+        self.try_update_leaves(&[(index, leaf)])
+    }
+
+    /// Replaces several data blocks at once and returns the new root. Every node shared by the
+    /// paths of the updated leaves is only recomputed once. If an index appears more than once,
+    /// the last block given for it wins.
+    ///
+    /// Panics if any index is beyond the number of leaves.
+    pub fn update_leaves<T: AsRef<[u8]>>(&mut self, updates: &[(usize, T)]) -> H::Digest {
+        self.try_update_leaves(updates).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`MerkleTree::update_leaves`], but returns an error instead of panicking. The tree
+    /// is left unchanged if any index is out of range.
+    pub fn try_update_leaves<T: AsRef<[u8]>>(&mut self, updates: &[(usize, T)]) -> Result<H::Digest, MerkleError> {
+        let updates: Vec<(usize, H::Digest)> = updates
+            .iter()
+            .map(|(index, leaf)| (*index, H::hash_leaf(leaf.as_ref())))
+            .collect();
+        self.try_update_leaf_digests(&updates)
+    }
+
+    /// Same as [`MerkleTree::update_leaf`] for a leaf that was already hashed.
+    pub fn update_leaf_digest(&mut self, index: usize, leaf: H::Digest) -> H::Digest {
+        self.update_leaf_digests(&[(index, leaf)])
+    }
+
+    /// Same as [`MerkleTree::update_leaf_digest`], but returns an error instead of panicking.
+    pub fn try_update_leaf_digest(&mut self, index: usize, leaf: H::Digest) -> Result<H::Digest, MerkleError> {
+        self.try_update_leaf_digests(&[(index, leaf)])
+    }
+
+    /// Same as [`MerkleTree::update_leaves`] for leaves that were already hashed.
+    pub fn update_leaf_digests(&mut self, updates: &[(usize, H::Digest)]) -> H::Digest {
+        self.try_update_leaf_digests(updates).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`MerkleTree::update_leaf_digests`], but returns an error instead of panicking.
+    /// The tree is left unchanged if any index is out of range.
+    pub fn try_update_leaf_digests(&mut self, updates: &[(usize, H::Digest)]) -> Result<H::Digest, MerkleError> {
+        for &(index, _) in updates {
+            check_index(index, self.len())?;
+        }
+
+        let mut dirty = Vec::with_capacity(updates.len());
+        for &(index, leaf) in updates {
+            self.levels[0][index] = leaf;
+            dirty.push(index);
+        }
+        dirty.sort_unstable();
+        dirty.dedup();
+
+        for height in 0..self.height() {
+            dirty = dirty.iter().map(|idx| idx / 2).collect();
+            dirty.dedup();
+
+            let (below, above) = self.levels.split_at_mut(height + 1);
+            let (children, parents) = (&below[height], &mut above[0]);
+            for &idx in &dirty {
+                parents[idx] = parent::<H>(children, idx);
+            }
+        }
+
+        Ok(self.root())
+    }
+
+    /// Returns the proof for the leaf at `index`, the same proof [`crate::generate_proof`] would
+    /// return for the sentence.
+    ///
+    /// Panics if the index is beyond the number of leaves.
+    pub fn proof(&self, index: usize) -> MerkleProof<H::Digest> {
+        self.try_proof(index).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`MerkleTree::proof`], but returns an error instead of panicking.
+    pub fn try_proof(&self, index: usize) -> Result<MerkleProof<H::Digest>, MerkleError> {
+        check_index(index, self.len())?;
+        Ok(self.layout.proof::<H>(&self.levels, index))
+    }
+
+    /// Returns the proof for the leaf at `index` without sibling directions, which the verifier
+    /// derives from the index and the tree size.
+    ///
+    /// Panics if the index is beyond the number of leaves.
+    pub fn compact_proof(&self, index: usize) -> CompactMerkleProof<H::Digest> {
+        self.try_compact_proof(index).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`MerkleTree::compact_proof`], but returns an error instead of panicking.
+    pub fn try_compact_proof(&self, index: usize) -> Result<CompactMerkleProof<H::Digest>, MerkleError> {
+        let proof = self.try_proof(index)?;
+        CompactMerkleProof::from_proof_with_shape(&proof, index, self.len(), self.shape())
+    }
+
+    /// Returns a compact multiproof for the leaves at `indices`, which are not necessarily sorted.
+    /// See [`crate::generate_compact_multiproof`] for the layout of the proof.
+    ///
+    /// Panics if no index is given, any index is beyond the number of leaves, or any index is
+    /// duplicated.
+    pub fn multiproof(&self, indices: &[usize]) -> CompactMerkleMultiProof<H::Digest> {
+        self.try_multiproof(indices).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as [`MerkleTree::multiproof`], but returns an error instead of panicking.
+    pub fn try_multiproof(&self, indices: &[usize]) -> Result<CompactMerkleMultiProof<H::Digest>, MerkleError> {
+        let known = check_indices(indices, self.len())?;
+        let hashes = self.layout.multiproof_hashes::<H>(&self.levels, known);
+
+        Ok(CompactMerkleMultiProof {
+            leaf_indices: indices.to_vec(),
+            tree_size: self.len(),
+            hashes,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        calculate_merkle_root, calculate_merkle_root_with_shape, concatenate_hash_values, generate_compact_multiproof,
+        generate_proof, string_of_random_words, validate_compact_multiproof_with_shape,
+        validate_proof, validate_proof_for_digest, DomainSeparated, MerkleHasher, Sha256Hasher,
+        SipHasher64,
+    };
+    use rand::{seq::index::sample, SeedableRng};
+
+    type Rfc6962Hasher = DomainSeparated<Sha256Hasher>;
+
+    /// The leaves of the RFC 6962 test data used by Certificate Transparency implementations.
+    pub(crate) fn rfc6962_leaves() -> Vec<Vec<u8>> {
+        vec![
+            vec![],
+            vec![0x00],
+            vec![0x10],
+            vec![0x20, 0x21],
+            vec![0x30, 0x31],
+            vec![0x40, 0x41, 0x42, 0x43],
+            (0x50..0x58).collect(),
+            (0x60..0x70).collect(),
+        ]
+    }
+
+    /// `n` distinct leaf digests for tests that only need some tree of a given size.
+    pub(crate) fn leaf_digests(n: usize) -> Vec<u64> {
+        (0..n).map(|i| SipHasher64::hash_leaf(&i.to_le_bytes())).collect()
+    }
+
+    /// The roots of the first 1 to 8 leaves of [`rfc6962_leaves`].
+    pub(crate) const RFC6962_ROOTS: [&str; 8] = [
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+    ];
+
+    #[test]
+    fn root_matches_calculate_merkle_root() {
+        for n in 0..=33 {
+            let sentence = string_of_random_words(n);
+            let tree = MerkleTree::<SipHasher64>::from_sentence(&sentence);
+            assert_eq!(calculate_merkle_root::<SipHasher64>(&sentence), tree.root());
+            assert_eq!(n, tree.len());
+        }
+    }
+
+    #[test]
+    fn proofs_match_generate_proof() {
+        let sentence = "the quick brown fox jumps over the lazy dog";
+        let tree = MerkleTree::<Sha256Hasher>::from_sentence(sentence);
+        for (i, word) in sentence.split_whitespace().enumerate() {
+            let (root, proof) = generate_proof::<Sha256Hasher>(sentence, i);
+            assert_eq!(root, tree.root());
+            assert_eq!(proof, tree.proof(i));
+            assert!(validate_proof::<Sha256Hasher>(&tree.root(), word, tree.proof(i)));
+        }
+    }
+
+    #[test]
+    fn multiproof_matches_generate_compact_multiproof() {
+        let sentence = "Here's an eight word sentence, special for you.";
+        let tree = MerkleTree::<SipHasher64>::from_sentence(sentence);
+        for indices in [vec![0, 1, 6], vec![6, 0, 1], vec![3], vec![0, 2, 4, 7], (0..8).collect()] {
+            let expected = generate_compact_multiproof::<SipHasher64>(sentence, indices.clone());
+            assert_eq!(expected, (tree.root(), tree.multiproof(&indices)));
+        }
+    }
+
+    #[test]
+    fn accessors() {
+        let tree = MerkleTree::<SipHasher64>::from_sentence("You trust me?");
+        assert_eq!(3, tree.len());
+        assert_eq!(2, tree.height());
+        assert_eq!(4, tree.leaves().len());
+        assert_eq!(Some(tree.root()), tree.node(2, 0));
+        assert_eq!(None, tree.node(2, 1));
+        assert_eq!(None, tree.level(3));
+        assert_eq!(
+            Some(concatenate_hash_values::<SipHasher64>(tree.leaves()[2], tree.leaves()[3])),
+            tree.node(1, 1)
+        );
+    }
+
+    #[test]
+    fn update_leaf_matches_rebuild() {
+        let mut words = vec!["the", "quick", "brown", "fox", "jumps", "over", "the", "lazy", "dog"];
+        let mut tree = MerkleTree::<Sha256Hasher>::from_leaves(&words);
+
+        words[4] = "walks";
+        let root = tree.update_leaf(4, "walks");
+        let rebuilt = MerkleTree::<Sha256Hasher>::from_leaves(&words);
+        assert_eq!(rebuilt.root(), root);
+        assert_eq!(rebuilt, tree);
+        assert!(validate_proof::<Sha256Hasher>(&root, "walks", tree.proof(4)));
+    }
+
+    #[test]
+    fn update_leaves_matches_rebuild() {
+        let sentence = string_of_random_words(37);
+        let mut words: Vec<&str> = sentence.split_whitespace().collect();
+        let mut tree = MerkleTree::<SipHasher64>::from_leaves(&words);
+
+        let updates = [(0, "zero"), (1, "one"), (17, "seventeen"), (36, "last"), (17, "again")];
+        let root = tree.update_leaves(&updates);
+        for &(index, word) in &updates {
+            words[index] = word;
+        }
+        assert_eq!(MerkleTree::<SipHasher64>::from_leaves(&words), tree);
+        assert_eq!(calculate_merkle_root::<SipHasher64>(&words.join(" ")), root);
+    }
+
+    #[test]
+    fn update_leaves_empty_batch() {
+        let mut tree = MerkleTree::<SipHasher64>::from_sentence("You trust me?");
+        let root = tree.root();
+        assert_eq!(root, tree.update_leaves::<&str>(&[]));
+    }
+
+    #[test]
+    fn byte_and_digest_leaves() {
+        let blocks: Vec<Vec<u8>> = (0u8..5).map(|i| vec![i; i as usize]).collect();
+        let digests: Vec<[u8; 32]> = blocks.iter().map(|block| Sha256Hasher::hash_leaf(block)).collect();
+
+        let tree = MerkleTree::<Sha256Hasher>::from_leaves(&blocks);
+        assert_eq!(tree, MerkleTree::<Sha256Hasher>::from_leaf_digests(digests.clone()));
+        assert_eq!(5, tree.len());
+
+        let mut updated = tree.clone();
+        updated.update_leaf(2, [0xffu8; 3]);
+        let mut updated_digest = tree.clone();
+        updated_digest.update_leaf_digest(2, Sha256Hasher::hash_leaf(&[0xff; 3]));
+        assert_eq!(updated, updated_digest);
+    }
+
+    #[test]
+    fn unbalanced_rfc6962_roots() {
+        let leaves = rfc6962_leaves();
+        for (n, expected) in RFC6962_ROOTS.iter().enumerate() {
+            let tree = MerkleTree::<Rfc6962Hasher>::from_leaves_with_shape(&leaves[..n + 1], TreeShape::Unbalanced);
+            assert_eq!(*expected, hex::encode(tree.root()));
+        }
+
+        let empty = MerkleTree::<Rfc6962Hasher>::from_leaves_with_shape::<&[u8]>(&[], TreeShape::Unbalanced);
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            hex::encode(empty.root())
+        );
+        assert_eq!(0, empty.height());
+    }
+
+    #[test]
+    fn unbalanced_does_not_pad() {
+        let tree = MerkleTree::<Sha256Hasher>::from_leaves_with_shape(&["a", "b", "c", "d", "e"], TreeShape::Unbalanced);
+        assert_eq!(vec![5, 3, 2, 1], tree.levels().iter().map(Vec::len).collect::<Vec<_>>());
+        assert_eq!(
+            Sha256Hasher::hash_nodes(&tree.node(2, 0).unwrap(), &Sha256Hasher::hash_leaf(b"e")),
+            tree.root()
+        );
+
+        // Trailing empty leaves are indistinguishable from padding in a padded tree only
+        let padded = |leaves: &[&str]| MerkleTree::<Sha256Hasher>::from_leaves(leaves).root();
+        let unbalanced = |leaves: &[&str]| {
+            MerkleTree::<Sha256Hasher>::from_leaves_with_shape(leaves, TreeShape::Unbalanced).root()
+        };
+        assert_eq!(padded(&["a", "b", "c"]), padded(&["a", "b", "c", ""]));
+        assert_ne!(unbalanced(&["a", "b", "c"]), unbalanced(&["a", "b", "c", ""]));
+    }
+
+    #[test]
+    fn shapes_agree_on_roots_proofs_and_multiproofs() {
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(6962);
+        for shape in [TreeShape::Padded, TreeShape::Unbalanced] {
+            for n in 1..=40usize {
+                let leaves: Vec<[u8; 32]> = (0..n).map(|i| Sha256Hasher::hash_leaf(&i.to_le_bytes())).collect();
+                let tree = MerkleTree::<Sha256Hasher>::from_leaf_digests_with_shape(leaves.clone(), shape);
+                assert_eq!(calculate_merkle_root_with_shape::<Sha256Hasher>(leaves.clone(), shape), tree.root());
+
+                for (i, leaf) in leaves.iter().enumerate() {
+                    assert!(validate_proof_for_digest::<Sha256Hasher>(&tree.root(), *leaf, tree.proof(i)));
+                }
+
+                for k in 1..=n.min(6) {
+                    let indices = sample(&mut rng, n, k).into_vec();
+                    let proven: Vec<[u8; 32]> = indices.iter().map(|&i| leaves[i]).collect();
+                    let multiproof = tree.multiproof(&indices);
+                    assert!(validate_compact_multiproof_with_shape::<Sha256Hasher>(
+                        &tree.root(),
+                        proven.clone(),
+                        multiproof.clone(),
+                        shape
+                    ));
+
+                    let mut tampered = proven;
+                    tampered[0][0] ^= 1;
+                    assert!(!validate_compact_multiproof_with_shape::<Sha256Hasher>(
+                        &tree.root(),
+                        tampered,
+                        multiproof,
+                        shape
+                    ));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unbalanced_multiproof_needs_the_tree_size() {
+        let leaves = ["a", "b", "c"];
+        let tree = MerkleTree::<Sha256Hasher>::from_leaves_with_shape(&leaves, TreeShape::Unbalanced);
+        // The last leaf is promoted, so only the hash of the first two leaves is needed
+        let multiproof = tree.multiproof(&[2]);
+        assert_eq!(vec![tree.node(1, 0).unwrap()], multiproof.hashes);
+
+        let leaf = vec![Sha256Hasher::hash_leaf(b"c")];
+        let validate = |size, shape| {
+            let proof = CompactMerkleMultiProof { tree_size: size, ..multiproof.clone() };
+            validate_compact_multiproof_with_shape::<Sha256Hasher>(&tree.root(), leaf.clone(), proof, shape)
+        };
+        assert!(validate(3, TreeShape::Unbalanced));
+        assert!(!validate(4, TreeShape::Unbalanced));
+        assert!(!validate(3, TreeShape::Padded));
+    }
+
+    #[test]
+    fn unbalanced_update_leaves_matches_rebuild() {
+        let sentence = string_of_random_words(37);
+        let mut words: Vec<&str> = sentence.split_whitespace().collect();
+        let mut tree = MerkleTree::<SipHasher64>::from_leaves_with_shape(&words, TreeShape::Unbalanced);
+
+        let updates = [(3, "three"), (32, "thirty-two"), (36, "last")];
+        let root = tree.update_leaves(&updates);
+        for &(index, word) in &updates {
+            words[index] = word;
+        }
+        assert_eq!(MerkleTree::<SipHasher64>::from_leaves_with_shape(&words, TreeShape::Unbalanced), tree);
+        assert_eq!(root, tree.root());
+    }
+
+    #[test]
+    fn try_methods_return_errors() {
+        let mut tree = MerkleTree::<SipHasher64>::from_sentence("You trust me?");
+        let root = tree.root();
+        assert_eq!(Err(MerkleError::IndexOutOfRange { index: 3, len: 3 }), tree.try_proof(3));
+        assert_eq!(Err(MerkleError::DuplicateIndex(2)), tree.try_multiproof(&[2, 0, 2]));
+        assert_eq!(Err(MerkleError::NoIndices), tree.try_multiproof(&[]));
+        assert_eq!(Ok(tree.multiproof(&[2, 0])), tree.try_multiproof(&[2, 0]));
+
+        // A batch with a bad index is rejected as a whole
+        assert_eq!(
+            Err(MerkleError::IndexOutOfRange { index: 3, len: 3 }),
+            tree.try_update_leaves(&[(0, "They"), (3, "padding")])
+        );
+        assert_eq!(root, tree.root());
+        assert_eq!(Ok(tree.clone().update_leaf(0, "They")), tree.try_update_leaf(0, "They"));
+
+        let empty = MerkleTree::<Sha256Hasher>::from_leaves_with_shape::<&str>(&[], TreeShape::Unbalanced);
+        assert_eq!(Err(MerkleError::EmptyTree), empty.try_proof(0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn update_leaf_out_of_range() {
+        MerkleTree::<SipHasher64>::from_sentence("You trust me?").update_leaf(3, "padding");
+    }
+
+    #[test]
+    #[should_panic]
+    fn proof_out_of_range() {
+        MerkleTree::<SipHasher64>::from_sentence("You trust me?").proof(3);
+    }
+
+    #[test]
+    #[should_panic]
+    fn multiproof_duplicate_index() {
+        MerkleTree::<SipHasher64>::from_sentence("You trust me?").multiproof(&[1, 1]);
+    }
+}
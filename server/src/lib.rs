@@ -1,52 +1,110 @@
-use actix_web::{web, App, HttpServer, Responder, post, get, HttpResponse};
+use actix_web::{web, Responder, post, get, HttpResponse};
 use serde::{Serialize, Deserialize};
 use std::sync::{Mutex, Arc};
 use std::collections::HashMap;
-use merkle_tree::{hash, generate_proof, validate_proof, HashValue, SiblingNode, MerkleProof};
+use merkle_tree::{
+    CompactMerkleMultiProof, DomainSeparated, MerkleError, MerkleHasher, MerkleProof, MerkleTree, Sha256Hasher, SparseKey,
+    SparseMerkleProof, SparseMerkleTree,
+};
+
+/// The hash function used for file contents and the Merkle tree built over them.
+pub type Hasher = DomainSeparated<Sha256Hasher>;
+pub type HashValue = <Hasher as MerkleHasher>::Digest;
 
 #[derive(Clone)]
-struct FileData {
+pub struct FileData {
     content: String,
     hash: HashValue,
+    // Position of the file's leaf in the Merkle tree
+    leaf_index: usize,
 }
 
 pub struct AppState {
     pub files: Arc<Mutex<HashMap<String, FileData>>>,
-    pub merkle_root: Arc<Mutex<Option<HashValue>>>,
+    pub merkle_tree: Arc<Mutex<Option<MerkleTree<Hasher>>>>,
+    // Files keyed by the hash of their name, to prove that a file does not exist
+    pub sparse_tree: Arc<Mutex<SparseMerkleTree<Hasher>>>,
+}
+
+/// Returns the key of a file in the sparse tree.
+pub fn file_key(filename: &str) -> SparseKey {
+    Sha256Hasher::hash_parts(&[filename.as_bytes()])
 }
 
-fn get_sorted_concatenated_hashes(files: &HashMap<String, FileData>) -> String {
+fn get_sorted_hashes(files: &HashMap<String, FileData>) -> Vec<HashValue> {
     let mut sorted_filenames: Vec<&String> = files.keys().collect();
     sorted_filenames.sort();
     sorted_filenames.iter()
-        .map(|&filename| files[filename].hash.clone().to_string())
-        .collect::<Vec<_>>()
-        .join(" ")
-    // let mut hashes = files.values().map(|data| data.hash.clone().to_string()).collect::<Vec<_>>().join(" ")
+        .map(|&filename| files[filename].hash)
+        .collect()
 }
 
 #[post("/upload")]
 async fn upload(file: web::Json<HashMap<String, String>>, state: web::Data<AppState>) -> impl Responder {
     let mut files = state.files.lock().unwrap();
-    let mut hashes: Vec<String> = Vec::new();
+    let mut sparse_tree = state.sparse_tree.lock().unwrap();
+    // Leaves of files that were uploaded before and only changed content
+    let mut updates: Vec<(usize, HashValue)> = Vec::new();
+    let mut added_files = false;
 
     for (filename, content) in file.into_inner() {
-        let file_hash = hash(&content);
+        let file_hash = Hasher::hash_leaf(content.as_bytes());
         println!("Content: {}", content);
-        files.insert(filename.clone(), FileData { content, hash: file_hash.clone() });
-        hashes.push(file_hash.to_string());
+        sparse_tree.insert_digest(file_key(&filename), file_hash);
+        if let Some(file_data) = files.get_mut(&filename) {
+            file_data.content = content;
+            file_data.hash = file_hash;
+            updates.push((file_data.leaf_index, file_hash));
+        } else {
+            files.insert(filename, FileData { content, hash: file_hash, leaf_index: 0 });
+            added_files = true;
+        }
     }
 
-    // Recalculate Merkle root
-    let concatenated_hashes = get_sorted_concatenated_hashes(&files);
-    println!("concatenated_hashes: {}", concatenated_hashes);
-    // TODO: it would be better to use calculate_merkle_root_rec(hashes) directly here
-    let root = merkle_tree::calculate_merkle_root(&concatenated_hashes);
+    let mut merkle_tree = state.merkle_tree.lock().unwrap();
+    let root = match &mut *merkle_tree {
+        Some(tree) if !added_files => tree.update_leaf_digests(&updates),
+        _ => {
+            // New files shift the sorted order, so the leaves are reassigned and the tree rebuilt
+            let mut sorted_filenames: Vec<String> = files.keys().cloned().collect();
+            sorted_filenames.sort();
+            for (index, filename) in sorted_filenames.iter().enumerate() {
+                files.get_mut(filename).unwrap().leaf_index = index;
+            }
+
+            // The file hashes are the leaves of the tree
+            let tree = MerkleTree::<Hasher>::from_leaf_digests(get_sorted_hashes(&files));
+            let root = tree.root();
+            *merkle_tree = Some(tree);
+            root
+        }
+    };
+
+    HttpResponse::Ok().json(format!("Root: {}", hex::encode(root)))
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct NonInclusionResponse {
+    pub root: HashValue,
+    pub proof: SparseMerkleProof<HashValue>,
+}
 
-    let mut merkle_root = state.merkle_root.lock().unwrap();
-    *merkle_root = Some(root);
+/// The 404 response for a missing file, proving its absence from the sparse tree.
+fn not_found(filename: &str, state: &AppState) -> HttpResponse {
+    let sparse_tree = state.sparse_tree.lock().unwrap();
+    HttpResponse::NotFound().json(NonInclusionResponse {
+        root: sparse_tree.root(),
+        proof: sparse_tree.proof(&file_key(filename)),
+    })
+}
 
-    HttpResponse::Ok().json(format!("Root: {}", root))
+/// Turns a failed proof request into a response. Proofs can only fail on bad input, so this is the
+/// client's fault.
+fn error_response(err: MerkleError) -> HttpResponse {
+    match err {
+        MerkleError::EmptyTree => HttpResponse::NotFound().body(err.to_string()),
+        _ => HttpResponse::BadRequest().body(err.to_string()),
+    }
 }
 
 #[get("/download/{filename}")]
@@ -56,46 +114,72 @@ async fn download(file_name: web::Path<String>, state: web::Data<AppState>) -> i
     if let Some(file_data) = files.get(filename) {
         HttpResponse::Ok().json(&file_data.content)
     } else {
-        HttpResponse::NotFound().finish()
+        not_found(filename, &state)
     }
 }
 
 #[derive(Deserialize, Serialize)]
 struct ProofResponse {
     root: HashValue,
-    proof: MerkleProof,
+    proof: MerkleProof<HashValue>,
 }
 
 #[get("/proof/{filename}")]
 async fn proof(file_name: web::Path<String>, state: web::Data<AppState>) -> impl Responder {
     let files = state.files.lock().unwrap();
     let filename = file_name.as_str().rsplit('/').next().unwrap_or("");
-    let merkle_root = state.merkle_root.lock().unwrap();
+    let merkle_tree = state.merkle_tree.lock().unwrap();
     
-    if let Some(file_data) = files.get(filename) {
-        if let Some(root) = &*merkle_root {
-            let concatenated_hashes: String = get_sorted_concatenated_hashes(&files);
-            println!("Concatenated hashes: {}", concatenated_hashes);
+    if let (Some(file_data), Some(tree)) = (files.get(filename), &*merkle_tree) {
+        let index = file_data.leaf_index;
+        println!("Index: {}", index);
+        let proof = match tree.try_proof(index) {
+            Ok(proof) => proof,
+            Err(err) => return error_response(err),
+        };
+        println!("Root: {:?}", tree.root());
+        println!("Proof: {:?}", proof);
+        
+        let proof_response = ProofResponse {
+            root: tree.root(),
+            proof,
+        };
+
+        return HttpResponse::Ok().json(proof_response);
+    }
+    not_found(filename, &state)
+}
 
-            // Create a sorted list of filenames to determine the index
-            let mut sorted_filenames: Vec<&String> = files.keys().collect();
-            sorted_filenames.sort();
-            let index = sorted_filenames.iter().position(|&k| k == filename).unwrap();
-
-            println!("Index: {}", index);
-            let (generated_root, proof) = generate_proof(&concatenated_hashes, index);
-            println!("Root: {:?}", generated_root);
-            println!("Proof: {:?}", proof);
-            
-            let proof_response = ProofResponse {
-                root: generated_root,
-                proof: proof,
-            };
-
-            return HttpResponse::Ok().json(proof_response);
+#[derive(Deserialize, Serialize)]
+pub struct MultiproofResponse {
+    pub root: HashValue,
+    pub proof: CompactMerkleMultiProof<HashValue>,
+}
+
+/// Proves several files at once. The body is the list of file names; the leaf indices in the
+/// response are in the same order.
+#[post("/multiproof")]
+async fn multiproof(filenames: web::Json<Vec<String>>, state: web::Data<AppState>) -> impl Responder {
+    let files = state.files.lock().unwrap();
+    let mut indices = Vec::with_capacity(filenames.len());
+    for filename in filenames.iter() {
+        match files.get(filename) {
+            Some(file_data) => indices.push(file_data.leaf_index),
+            None => return not_found(filename, &state),
         }
     }
-    HttpResponse::NotFound().finish()
+
+    let merkle_tree = state.merkle_tree.lock().unwrap();
+    let Some(tree) = &*merkle_tree else {
+        return error_response(MerkleError::EmptyTree);
+    };
+    match tree.try_multiproof(&indices) {
+        Ok(multiproof) => HttpResponse::Ok().json(MultiproofResponse {
+            root: tree.root(),
+            proof: multiproof,
+        }),
+        Err(err) => error_response(err),
+    }
 }
 
 #[get("/hello")]
@@ -106,7 +190,8 @@ async fn hello() -> impl Responder {
 pub fn create_app_state() -> web::Data<AppState> {
     web::Data::new(AppState {
         files: Arc::new(Mutex::new(HashMap::new())),
-        merkle_root: Arc::new(Mutex::new(None)),
+        merkle_tree: Arc::new(Mutex::new(None)),
+        sparse_tree: Arc::new(Mutex::new(SparseMerkleTree::new())),
     })
 }
 
@@ -114,5 +199,6 @@ pub fn configure_services(cfg: &mut web::ServiceConfig) {
     cfg.service(upload);
     cfg.service(download);
     cfg.service(proof);
+    cfg.service(multiproof);
     cfg.service(hello);
 }
@@ -1,15 +1,19 @@
 use actix_web::{test, App};
-use server::{upload, download, proof, create_app_state, configure_services};
-use std::collections::HashMap;
-use actix_web::web::Data;
-use std::sync::{Arc, Mutex};
-use merkle_tree::{hash};
+use server::{
+    create_app_state, configure_services, file_key, Hasher, HashValue, MultiproofResponse,
+    NonInclusionResponse,
+};
+use merkle_tree::{
+    validate_compact_multiproof_with_shape, validate_proof_for_leaf, verify_sparse_inclusion,
+    verify_sparse_non_inclusion, MerkleHasher, MerkleProof,
+    SparseMerkleTree, TreeShape,
+};
 
 #[actix_web::test]
 async fn test_upload_and_proof() {
     let state = create_app_state();
 
-    let mut app = test::init_service(App::new()
+    let app = test::init_service(App::new()
         .app_data(state.clone())
         .configure(configure_services)
     ).await;
@@ -24,23 +28,151 @@ async fn test_upload_and_proof() {
         .set_json(&payload)
         .to_request();
 
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     assert!(resp.status().is_success());
 
     let req = test::TestRequest::get()
         .uri("/proof/file1.txt")
         .to_request();
 
-    let resp: serde_json::Value = test::call_and_read_body_json(&mut app, req).await;
+    let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
     assert!(resp.get("root").is_some());
     assert!(resp.get("proof").is_some());
+
+    let root: HashValue = serde_json::from_value(resp["root"].clone()).unwrap();
+    let proof: MerkleProof<HashValue> = serde_json::from_value(resp["proof"].clone()).unwrap();
+    assert!(validate_proof_for_leaf::<Hasher>(&root, b"This is the content of file1.", proof));
+}
+
+#[actix_web::test]
+async fn test_reupload_updates_root() {
+    let state = create_app_state();
+
+    let app = test::init_service(App::new()
+        .app_data(state.clone())
+        .configure(configure_services)
+    ).await;
+
+    let payload = serde_json::json!({
+        "file1.txt": "This is the content of file1.",
+        "file2.txt": "File2 contains different content.",
+        "file3.txt": "The third file, file3, has its own content.",
+    });
+    let req = test::TestRequest::post().uri("/upload").set_json(&payload).to_request();
+    let first_root: String = test::call_and_read_body_json(&app, req).await;
+
+    let payload = serde_json::json!({
+        "file2.txt": "File2 was changed.",
+    });
+    let req = test::TestRequest::post().uri("/upload").set_json(&payload).to_request();
+    let second_root: String = test::call_and_read_body_json(&app, req).await;
+    assert_ne!(first_root, second_root);
+
+    // A server that only ever saw the final contents agrees on the root
+    let fresh = test::init_service(App::new()
+        .app_data(create_app_state())
+        .configure(configure_services)
+    ).await;
+    let payload = serde_json::json!({
+        "file1.txt": "This is the content of file1.",
+        "file2.txt": "File2 was changed.",
+        "file3.txt": "The third file, file3, has its own content.",
+    });
+    let req = test::TestRequest::post().uri("/upload").set_json(&payload).to_request();
+    let fresh_root: String = test::call_and_read_body_json(&fresh, req).await;
+    assert_eq!(fresh_root, second_root);
+
+    let req = test::TestRequest::get().uri("/proof/file2.txt").to_request();
+    let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+    let root: HashValue = serde_json::from_value(resp["root"].clone()).unwrap();
+    let proof: MerkleProof<HashValue> = serde_json::from_value(resp["proof"].clone()).unwrap();
+    assert_eq!(second_root, format!("Root: {}", hex::encode(root)));
+    assert!(validate_proof_for_leaf::<Hasher>(&root, b"File2 was changed.", proof));
+}
+
+#[actix_web::test]
+async fn test_missing_file_proves_non_inclusion() {
+    let state = create_app_state();
+
+    let app = test::init_service(App::new()
+        .app_data(state.clone())
+        .configure(configure_services)
+    ).await;
+
+    let payload = serde_json::json!({
+        "file1.txt": "This is the content of file1.",
+        "file2.txt": "File2 contains different content.",
+    });
+    let req = test::TestRequest::post().uri("/upload").set_json(&payload).to_request();
+    test::call_service(&app, req).await;
+
+    // The client can rebuild the sparse root from the files it uploaded
+    let mut expected = SparseMerkleTree::<Hasher>::new();
+    expected.insert(file_key("file1.txt"), "This is the content of file1.");
+    expected.insert(file_key("file2.txt"), "File2 contains different content.");
+
+    for uri in ["/proof/file3.txt", "/download/file3.txt"] {
+        let req = test::TestRequest::get().uri(uri).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(404, resp.status().as_u16());
+
+        let body: NonInclusionResponse = test::read_body_json(resp).await;
+        assert_eq!(expected.root(), body.root);
+        assert!(verify_sparse_non_inclusion::<Hasher>(&body.root, &file_key("file3.txt"), &body.proof));
+        assert!(!verify_sparse_non_inclusion::<Hasher>(&body.root, &file_key("file1.txt"), &body.proof));
+    }
+
+    let proof = state.sparse_tree.lock().unwrap().proof(&file_key("file1.txt"));
+    assert!(verify_sparse_inclusion::<Hasher>(&expected.root(), &file_key("file1.txt"), b"This is the content of file1.", &proof));
+}
+
+#[actix_web::test]
+async fn test_multiproof() {
+    let state = create_app_state();
+
+    let app = test::init_service(App::new()
+        .app_data(state.clone())
+        .configure(configure_services)
+    ).await;
+
+    let payload = serde_json::json!({
+        "file1.txt": "This is the content of file1.",
+        "file2.txt": "File2 contains different content.",
+        "file3.txt": "The third file, file3, has its own content.",
+    });
+    let req = test::TestRequest::post().uri("/upload").set_json(&payload).to_request();
+    test::call_service(&app, req).await;
+
+    let req = test::TestRequest::post()
+        .uri("/multiproof")
+        .set_json(serde_json::json!(["file3.txt", "file1.txt"]))
+        .to_request();
+    let resp: MultiproofResponse = test::call_and_read_body_json(&app, req).await;
+    let leaves = vec![
+        Hasher::hash_leaf(b"The third file, file3, has its own content."),
+        Hasher::hash_leaf(b"This is the content of file1."),
+    ];
+    assert_eq!(vec![2, 0], resp.proof.leaf_indices);
+    assert_eq!(3, resp.proof.tree_size);
+    assert!(validate_compact_multiproof_with_shape::<Hasher>(&resp.root, leaves, resp.proof, TreeShape::Padded));
+
+    // Bad requests are answered with a client error instead of crashing the worker
+    for (files, status) in [
+        (serde_json::json!(["file1.txt", "file1.txt"]), 400),
+        (serde_json::json!([]), 400),
+        (serde_json::json!(["file4.txt"]), 404),
+    ] {
+        let req = test::TestRequest::post().uri("/multiproof").set_json(files).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(status, resp.status().as_u16());
+    }
 }
 
 #[actix_web::test]
 async fn test_hello_world() {
     let state = create_app_state();
 
-    let mut app = test::init_service(App::new()
+    let app = test::init_service(App::new()
         .app_data(state.clone())
         .configure(configure_services)
     ).await;
@@ -49,7 +181,7 @@ async fn test_hello_world() {
         .uri("/hello")
         .to_request();
 
-    let resp = test::call_service(&mut app, req).await;
+    let resp = test::call_service(&app, req).await;
     
     // Check if the response status is success
     if !resp.status().is_success() {